@@ -37,3 +37,15 @@ pub unsafe fn reset() {
     let reset = (0x5FA << 16) | (aircr & (0x7 << 8)) | (1 << 2);
     (*scb).aircr.set(reset);
 }
+
+/// Make `wfi` enter the chip's deep sleep state (SCR.SLEEPDEEP).
+pub unsafe fn set_sleepdeep() {
+    let scr = (*scb).scr.get();
+    (*scb).scr.set(scr | (1 << 2));
+}
+
+/// Make `wfi` enter the chip's regular sleep state.
+pub unsafe fn unset_sleepdeep() {
+    let scr = (*scb).scr.get();
+    (*scb).scr.set(scr & !(1 << 2));
+}
@@ -20,7 +20,7 @@ impl hil::i2c::I2CHwMasterClient for ScanClient {
         let mut dev_id = self.dev_id.get();
 
         if error == hil::i2c::Error::CommandComplete {
-            println!("{:#x}", dev_id);
+            debug!("{:#x}", dev_id);
         }
 
         let dev: &mut I2CMaster = unsafe { &mut i2c::I2C2 };
@@ -29,8 +29,8 @@ impl hil::i2c::I2CHwMasterClient for ScanClient {
             self.dev_id.set(dev_id);
             dev.write(dev_id, buffer, 2);
         } else {
-            println!("Done scanning for I2C devices. Buffer len: {}",
-                     buffer.len());
+            debug!("Done scanning for I2C devices. Buffer len: {}",
+                   buffer.len());
         }
     }
 }
@@ -46,7 +46,7 @@ pub fn i2c_scan_slaves() {
     let dev: &mut I2CMaster = dev;
     dev.enable();
 
-    println!("Scanning for I2C devices...");
+    debug!("Scanning for I2C devices...");
     dev.write(i2c_client.dev_id.get(), unsafe { &mut DATA }, 2);
 }
 
@@ -77,15 +77,15 @@ impl hil::i2c::I2CHwMasterClient for AccelClient {
 
         match self.state.get() {
             ReadingWhoami => {
-                println!("WHOAMI Register 0x{:x} ({})", buffer[0], error);
-                println!("Activating Sensor...");
+                debug!("WHOAMI Register 0x{:x} ({})", buffer[0], error);
+                debug!("Activating Sensor...");
                 buffer[0] = 0x2A as u8; // CTRL_REG1
                 buffer[1] = 1; // Bit 1 sets `active`
-                dev.write(0x1e, i2c::START | i2c::STOP, buffer, 2);
+                dev.write(0x1e, buffer, 2);
                 self.state.set(Activating);
             }
             Activating => {
-                println!("Sensor Activated ({})", error);
+                debug!("Sensor Activated ({})", error);
                 buffer[0] = 0x01 as u8; // X-MSB register
                 // Reading 6 bytes will increment the register pointer through
                 // X-MSB, X-LSB, Y-MSB, Y-LSB, Z-MSB, Z-LSB
@@ -101,11 +101,11 @@ impl hil::i2c::I2CHwMasterClient for AccelClient {
                 let y = ((y >> 2) * 976) / 1000;
                 let z = ((z >> 2) * 976) / 1000;
 
-                println!("Accel data ready x: {}, y: {}, z: {} ({})",
-                         x >> 2,
-                         y >> 2,
-                         z >> 2,
-                         error);
+                debug!("Accel data ready x: {}, y: {}, z: {} ({})",
+                       x >> 2,
+                       y >> 2,
+                       z >> 2,
+                       error);
 
                 buffer[0] = 0x01 as u8; // X-MSB register
                 // Reading 6 bytes will increment the register pointer through
@@ -114,8 +114,8 @@ impl hil::i2c::I2CHwMasterClient for AccelClient {
                 self.state.set(ReadingAccelData);
             }
             Deactivating => {
-                println!("Sensor deactivated ({})", error);
-                println!("Reading Accel's WHOAMI...");
+                debug!("Sensor deactivated ({})", error);
+                debug!("Reading Accel's WHOAMI...");
                 buffer[0] = 0x0D as u8; // 0x0D == WHOAMI register
                 dev.write_read(0x1e, buffer, 1, 1);
                 self.state.set(AccelClientState::ReadingWhoami);
@@ -134,7 +134,7 @@ pub fn i2c_accel_test() {
     dev.enable();
 
     let buf = unsafe { &mut DATA };
-    println!("Reading Accel's WHOAMI...");
+    debug!("Reading Accel's WHOAMI...");
     buf[0] = 0x0D as u8; // 0x0D == WHOAMI register
     dev.write_read(0x1e, buf, 1, 1);
     i2c_client.state.set(AccelClientState::ReadingWhoami);
@@ -165,7 +165,7 @@ impl hil::i2c::I2CHwMasterClient for LiClient {
 
         match self.state.get() {
             Enabling => {
-                println!("Reading Lumminance Registers ({})", error);
+                debug!("Reading Lumminance Registers ({})", error);
                 buffer[0] = 0x02 as u8;
                 buffer[0] = 0;
                 dev.write_read(0x44, buffer, 1, 2);
@@ -173,7 +173,7 @@ impl hil::i2c::I2CHwMasterClient for LiClient {
             }
             ReadingLI => {
                 let intensity = ((buffer[1] as usize) << 8) | buffer[0] as usize;
-                println!("Light Intensity: {}% ({})", (intensity * 100) >> 16, error);
+                debug!("Light Intensity: {}% ({})", (intensity * 100) >> 16, error);
                 buffer[0] = 0x02 as u8;
                 dev.write_read(0x44, buffer, 1, 2);
                 self.state.set(ReadingLI);
@@ -198,10 +198,10 @@ pub fn i2c_li_test() {
     dev.enable();
 
     let buf = unsafe { &mut DATA };
-    println!("Enabling LI...");
+    debug!("Enabling LI...");
     buf[0] = 0;
     buf[1] = 0b10100000;
     buf[2] = 0b00000000;
-    dev.write(0x44, i2c::START | i2c::STOP, buf, 3);
+    dev.write(0x44, buf, 3);
     i2c_client.state.set(LiClientState::Enabling);
 }
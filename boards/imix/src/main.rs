@@ -3,19 +3,21 @@
 #![feature(const_fn,lang_items)]
 
 extern crate capsules;
-#[macro_use(static_init)]
+#[macro_use(debug, static_init)]
 extern crate kernel;
 extern crate sam4l;
 
 use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules::virtual_spi::{MuxSPIMaster, SPIMasterDevice};
+use capsules::virtual_uart::{MuxUart, UartDevice};
 use kernel::{Chip, MPU};
 use kernel::hil;
 use kernel::hil::Controller;
+use kernel::hil::sensors::{AccelerationSensor, HumiditySensor, LightSensor, TemperatureSensor};
 use kernel::hil::spi::SpiMaster;
 
-#[macro_use]
 mod io;
 
 // Unit Tests for drivers.
@@ -25,18 +27,30 @@ mod i2c_dummy;
 mod spi_dummy;
 
 struct Imix {
-    console: &'static capsules::console::Console<'static, sam4l::usart::USART>,
+    console: &'static capsules::console::Console<'static,
+                                                 UartDevice<'static, sam4l::usart::USART>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     si7021: &'static capsules::si7021::SI7021<'static,
                                               VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     isl29035: &'static capsules::isl29035::Isl29035<'static>,
-    adc: &'static capsules::adc::ADC<'static, sam4l::adc::Adc>,
+    adc: &'static capsules::adc::ADC<'static,
+                                     capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>,
+                                     capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
-    button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
-    spi: &'static capsules::spi::Spi<'static, sam4l::spi::Spi>,
+    button: &'static capsules::button::Button<'static,
+                                              sam4l::gpio::GPIOPin,
+                                              VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    spi: &'static capsules::spi::Spi<'static>,
     ipc: kernel::ipc::IPC,
+    process_info: capsules::process_info::ProcessInfo,
     fxos8700_cq: &'static capsules::fxos8700_cq::Fxos8700cq<'static>,
+    rng: &'static capsules::rng::Rng<'static, sam4l::trng::Trng>,
+    i2c_master: &'static capsules::i2c_master_driver::I2CMasterDriver<'static>,
+    temperature: &'static capsules::temperature::TemperatureDriver<'static>,
+    humidity: &'static capsules::humidity::HumidityDriver<'static>,
+    ambient_light: &'static capsules::ambient_light::AmbientLightDriver<'static>,
+    acceleration: &'static capsules::acceleration::AccelerationDriver<'static>,
 }
 
 impl kernel::Platform for Imix {
@@ -55,6 +69,13 @@ impl kernel::Platform for Imix {
             9 => f(Some(self.button)),
             10 => f(Some(self.si7021)),
             11 => f(Some(self.fxos8700_cq)),
+            12 => f(Some(&self.process_info)),
+            17 => f(Some(self.rng)),
+            20 => f(Some(self.i2c_master)),
+            21 => f(Some(self.temperature)),
+            22 => f(Some(self.humidity)),
+            23 => f(Some(self.ambient_light)),
+            25 => f(Some(self.acceleration)),
 
             0xff => f(Some(&self.ipc)),
             _ => f(None),
@@ -142,15 +163,37 @@ pub unsafe fn reset_handler() {
 
     // # CONSOLE
 
+    // Kernel debug output shares USART3 with the console
+    let uart_mux = static_init!(
+        MuxUart<'static, sam4l::usart::USART>,
+        MuxUart::new(&sam4l::usart::USART3),
+        16);
+    hil::uart::UART::set_client(&sam4l::usart::USART3, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, sam4l::usart::USART>,
+        UartDevice::new(uart_mux, true),
+        32);
+    console_uart.setup();
+    let debug_uart = static_init!(
+        UartDevice<'static, sam4l::usart::USART>,
+        UartDevice::new(uart_mux, false),
+        32);
+    debug_uart.setup();
+    kernel::debug::assign_uart(debug_uart);
     let console = static_init!(
-        capsules::console::Console<sam4l::usart::USART>,
-        capsules::console::Console::new(&sam4l::usart::USART3,
+        capsules::console::Console<UartDevice<sam4l::usart::USART>>,
+        capsules::console::Console::new(console_uart,
                      115200,
                      &mut capsules::console::WRITE_BUF,
+                     &mut capsules::console::READ_BUF,
+                     &mut capsules::console::ECHO_BUF,
                      kernel::Container::create()),
-        224/8);
-    hil::uart::UART::set_client(&sam4l::usart::USART3, console);
+        672/8);
+    hil::uart::UART::set_client(console_uart, console);
     console.initialize();
+    // To separate the output of each app on the host with tools/console_demux:
+    // console.set_framed(true);
+    // kernel::debug::set_framed(true);
 
     // # TIMER
 
@@ -177,49 +220,121 @@ pub unsafe fn reset_handler() {
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2), 20);
     sam4l::i2c::I2C2.set_master_client(mux_i2c);
 
+    // Give up on I2C transactions that take too long, and free the bus
+    let i2c_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    i2c_alarm.set_client(&sam4l::i2c::I2C2);
+    sam4l::i2c::I2C2.set_timeout_alarm(i2c_alarm);
+    sam4l::i2c::I2C2.set_bus_pins(&sam4l::gpio::PA[22],
+                                  &sam4l::gpio::PA[21],
+                                  sam4l::gpio::PeripheralFunction::E);
+
     // Configure the ISL29035, device address 0x44
-    let isl29035_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x44), 32);
+    let isl29035_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x44), 88);
     let isl29035 = static_init!(
         capsules::isl29035::Isl29035<'static>,
         capsules::isl29035::Isl29035::new(isl29035_i2c, &mut capsules::isl29035::BUF),
-        36);
+        52);
     isl29035_i2c.set_client(isl29035);
 
     static mut spi_read_buf: [u8; 64] = [0; 64];
     static mut spi_write_buf: [u8; 64] = [0; 64];
 
-    // Initialize and enable SPI HAL
-    let chip_selects = static_init!([u8; 4], [0, 1, 2, 3], 4);
-    let spi = static_init!(
-        capsules::spi::Spi<'static, sam4l::spi::Spi>,
-        capsules::spi::Spi::new(&mut sam4l::spi::SPI, chip_selects),
-        92);
-    spi.config_buffers(&mut spi_read_buf, &mut spi_write_buf);
-    sam4l::spi::SPI.set_client(spi);
+    // Initialize and enable SPI HAL, shared by apps through one virtual
+    // device per chip select
+    let mux_spi = static_init!(
+        MuxSPIMaster<'static, sam4l::spi::Spi>,
+        MuxSPIMaster::new(&sam4l::spi::SPI),
+        12);
+    sam4l::spi::SPI.set_client(mux_spi);
     sam4l::spi::SPI.init();
     sam4l::spi::SPI.enable();
+    let spi_cs0 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 0),
+                               60);
+    let spi_cs1 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 1),
+                               60);
+    let spi_cs2 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 2),
+                               60);
+    let spi_cs3 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 3),
+                               60);
+    let spi_devices = static_init!(
+        [&'static hil::spi::SPIMasterDevice; 4],
+        [spi_cs0, spi_cs1, spi_cs2, spi_cs3],
+        4 * 8);
+    let spi = static_init!(
+        capsules::spi::Spi<'static>,
+        capsules::spi::Spi::new(spi_devices, kernel::Container::create()),
+        56);
+    spi.config_buffers(&mut spi_read_buf, &mut spi_write_buf);
+    spi_cs0.set_client(spi);
+    spi_cs1.set_client(spi);
+    spi_cs2.set_client(spi);
+    spi_cs3.set_client(spi);
 
     // Configure the SI7021, device address 0x40
     let si7021_alarm = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
         24);
-    let si7021_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x40), 32);
+    let si7021_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x40), 88);
     let si7021 = static_init!(
         capsules::si7021::SI7021<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
         capsules::si7021::SI7021::new(si7021_i2c, si7021_alarm, &mut capsules::si7021::BUFFER),
-        36);
+        56);
     si7021_i2c.set_client(si7021);
     si7021_alarm.set_client(si7021);
 
     // FXOS8700CQ accelerometer
-    let fx0_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x1e), 32);
+    let fx0_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x1e), 88);
     let fx0 = static_init!(
         capsules::fxos8700_cq::Fxos8700cq<'static>,
         capsules::fxos8700_cq::Fxos8700cq::new(fx0_i2c, &mut capsules::fxos8700_cq::BUF),
-        44);
+        72);
     fx0_i2c.set_client(fx0);
 
+    // Userspace I2C. Apps may only access the addresses listed for their
+    // package name, e.g. `Permissions { package_name: b"lightmeter",
+    // addresses: &[0x44] }`.
+    static I2C_PERMISSIONS: [capsules::i2c_master_driver::Permissions; 0] = [];
+    let app_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0), 88);
+    let i2c_master = static_init!(
+        capsules::i2c_master_driver::I2CMasterDriver<'static>,
+        capsules::i2c_master_driver::I2CMasterDriver::new(
+            app_i2c,
+            &I2C_PERMISSIONS,
+            &mut capsules::i2c_master_driver::BUFFER,
+            kernel::Container::create()),
+        40);
+    app_i2c.set_client(i2c_master);
+
+    // Sensor drivers with the same interface on every board
+    let temperature = static_init!(
+        capsules::temperature::TemperatureDriver<'static>,
+        capsules::temperature::TemperatureDriver::new(si7021, kernel::Container::create()),
+        16);
+    si7021.set_temperature_client(temperature);
+    let humidity = static_init!(
+        capsules::humidity::HumidityDriver<'static>,
+        capsules::humidity::HumidityDriver::new(si7021, kernel::Container::create()),
+        16);
+    si7021.set_humidity_client(humidity);
+    let ambient_light = static_init!(
+        capsules::ambient_light::AmbientLightDriver<'static>,
+        capsules::ambient_light::AmbientLightDriver::new(isl29035, kernel::Container::create()),
+        16);
+    isl29035.set_light_client(ambient_light);
+    let acceleration = static_init!(
+        capsules::acceleration::AccelerationDriver<'static>,
+        capsules::acceleration::AccelerationDriver::new(fx0, kernel::Container::create()),
+        16);
+    fx0.set_acceleration_client(acceleration);
+
     // Clear sensors enable pin to enable sensor rail
     sam4l::gpio::PC[16].enable_output();
     sam4l::gpio::PC[16].clear();
@@ -227,11 +342,28 @@ pub unsafe fn reset_handler() {
     // # ADC
 
     // Setup ADC
+    let adc_mux = static_init!(
+        capsules::virtual_adc::MuxAdc<'static, sam4l::adc::Adc>,
+        capsules::virtual_adc::MuxAdc::new(&sam4l::adc::ADC),
+        12);
+    sam4l::adc::ADC.set_client(adc_mux);
+    sam4l::adc::ADC.set_continuous_client(adc_mux);
+    let adc_user = static_init!(
+        capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>,
+        capsules::virtual_adc::AdcUser::new(adc_mux),
+        60);
     let adc = static_init!(
-        capsules::adc::ADC<'static, sam4l::adc::Adc>,
-        capsules::adc::ADC::new(&mut sam4l::adc::ADC),
-        160/8);
-    sam4l::adc::ADC.set_client(adc);
+        capsules::adc::ADC<'static,
+                           capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>,
+                           capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>>,
+        capsules::adc::ADC::new(adc_user,
+                                adc_user,
+                                &mut capsules::adc::BUFFER1,
+                                &mut capsules::adc::BUFFER2,
+                                kernel::Container::create()),
+        56);
+    adc_user.set_client(adc);
+    adc_user.set_continuous_client(adc);
 
     // # GPIO
 
@@ -253,8 +385,8 @@ pub unsafe fn reset_handler() {
     );
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
-        capsules::gpio::GPIO::new(gpio_pins),
-        20);
+        capsules::gpio::GPIO::new(gpio_pins, kernel::Container::create()),
+        16);
     for pin in gpio_pins.iter() {
         pin.set_client(gpio);
     }
@@ -276,14 +408,39 @@ pub unsafe fn reset_handler() {
         [&sam4l::gpio::PC[24]],
         1 * 4);
 
+    let button_states = static_init!(
+        [capsules::button::ButtonState; 1],
+        [capsules::button::ButtonState::new()],
+        1 * 20);
+    let button_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
     let button = static_init!(
-        capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
-        capsules::button::Button::new(button_pins, kernel::Container::create()),
-        96/8);
+        capsules::button::Button<'static,
+                                 sam4l::gpio::GPIOPin,
+                                 VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::button::Button::new(button_pins,
+                                      button_states,
+                                      button_alarm,
+                                      capsules::led::ActivationMode::ActiveLow,
+                                      20,
+                                      kernel::Container::create()),
+        320/8);
+    button_alarm.set_client(button);
     for btn in button_pins.iter() {
         btn.set_client(button);
     }
 
+    // # RNG
+
+    let rng_buf = static_init!([u8; 64], [0; 64], 64);
+    let rng = static_init!(
+        capsules::rng::Rng<'static, sam4l::trng::Trng>,
+        capsules::rng::Rng::new(&sam4l::trng::TRNG, rng_buf, kernel::Container::create()),
+        20);
+    sam4l::trng::TRNG.set_client(rng);
+
     let imix = Imix {
         console: console,
         timer: timer,
@@ -295,7 +452,14 @@ pub unsafe fn reset_handler() {
         button: button,
         spi: spi,
         ipc: kernel::ipc::IPC::new(),
+        process_info: capsules::process_info::ProcessInfo::new(),
         fxos8700_cq: fx0,
+        rng: rng,
+        i2c_master: i2c_master,
+        temperature: temperature,
+        humidity: humidity,
+        ambient_light: ambient_light,
+        acceleration: acceleration,
     };
 
 
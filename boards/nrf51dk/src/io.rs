@@ -1,50 +0,0 @@
-use core::fmt::*;
-use kernel::hil::uart::{self, UART};
-use nrf51;
-
-pub struct Writer {
-    initialized: bool,
-}
-
-pub static mut WRITER: Writer = Writer { initialized: false };
-
-impl Write for Writer {
-    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
-        let uart = unsafe { &mut nrf51::uart::UART0 };
-        if !self.initialized {
-            self.initialized = true;
-            uart.init(uart::UARTParams {
-                baud_rate: 115200,
-                stop_bits: uart::StopBits::One,
-                parity: uart::Parity::None,
-                hw_flow_control: false,
-            });
-
-        }
-        for c in s.bytes() {
-            unsafe {
-                uart.send_byte(c);
-            }
-            while !uart.tx_ready() {}
-        }
-        Ok(())
-    }
-}
-
-
-#[macro_export]
-macro_rules! print {
-        ($($arg:tt)*) => (
-            {
-                use core::fmt::write;
-                let writer = &mut $crate::io::WRITER;
-                let _ = write(writer, format_args!($($arg)*));
-            }
-        );
-}
-
-#[macro_export]
-macro_rules! println {
-        ($fmt:expr) => (print!(concat!($fmt, "\n")));
-            ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
-}
@@ -45,14 +45,12 @@ extern crate nrf51;
 
 use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_uart::{MuxUart, UartDevice};
 use kernel::{Chip, SysTick};
 use kernel::hil::uart::UART;
 use nrf51::pinmux::Pinmux;
 use nrf51::rtc::{RTC, Rtc};
 
-#[macro_use]
-pub mod io;
-
 // The nRF51 DK LEDs (see back of board)
 const LED1_PIN: usize = 21;
 const LED2_PIN: usize = 22;
@@ -94,9 +92,13 @@ unsafe fn load_process() -> &'static mut [Option<kernel::process::Process<'stati
 pub struct Platform {
     gpio: &'static capsules::gpio::GPIO<'static, nrf51::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
-    console: &'static capsules::console::Console<'static, nrf51::uart::UART>,
+    console: &'static capsules::console::Console<'static,
+                                                 UartDevice<'static, nrf51::uart::UART>>,
     led: &'static capsules::led::LED<'static, nrf51::gpio::GPIOPin>,
-    button: &'static capsules::button::Button<'static, nrf51::gpio::GPIOPin>,
+    button: &'static capsules::button::Button<'static,
+                                              nrf51::gpio::GPIOPin,
+                                              VirtualMuxAlarm<'static, Rtc>>,
+    rng: &'static capsules::rng::Rng<'static, nrf51::rng::Rng>,
 }
 
 
@@ -110,6 +112,7 @@ impl kernel::Platform for Platform {
             3 => f(Some(self.timer)),
             8 => f(Some(self.led)),
             9 => f(Some(self.button)),
+            17 => f(Some(self.rng)),
             _ => f(None),
         }
     }
@@ -141,14 +144,9 @@ pub unsafe fn reset_handler() {
          &nrf51::gpio::PORT[BUTTON4_PIN], // 20
         ],
         4 * 4);
-    let button = static_init!(
-        capsules::button::Button<'static, nrf51::gpio::GPIOPin>,
-        capsules::button::Button::new(button_pins, kernel::Container::create()),
-        96/8);
     for btn in button_pins.iter() {
         use kernel::hil::gpio::PinCtl;
         btn.set_input_mode(kernel::hil::gpio::InputMode::PullUp);
-        btn.set_client(button);
     }
 
     let gpio_pins = static_init!(
@@ -169,8 +167,8 @@ pub unsafe fn reset_handler() {
 
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, nrf51::gpio::GPIOPin>,
-        capsules::gpio::GPIO::new(gpio_pins),
-        20);
+        capsules::gpio::GPIO::new(gpio_pins, kernel::Container::create()),
+        16);
     for pin in gpio_pins.iter() {
         pin.set_client(gpio);
     }
@@ -179,15 +177,37 @@ pub unsafe fn reset_handler() {
                                  Pinmux::new(11),
                                  Pinmux::new(10),
                                  Pinmux::new(8));
+    // Kernel debug output shares UART0 with the console
+    let uart_mux = static_init!(
+        MuxUart<'static, nrf51::uart::UART>,
+        MuxUart::new(&nrf51::uart::UART0),
+        16);
+    UART::set_client(&nrf51::uart::UART0, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, nrf51::uart::UART>,
+        UartDevice::new(uart_mux, true),
+        32);
+    console_uart.setup();
+    let debug_uart = static_init!(
+        UartDevice<'static, nrf51::uart::UART>,
+        UartDevice::new(uart_mux, false),
+        32);
+    debug_uart.setup();
+    kernel::debug::assign_uart(debug_uart);
     let console = static_init!(
-        capsules::console::Console<nrf51::uart::UART>,
-        capsules::console::Console::new(&nrf51::uart::UART0,
+        capsules::console::Console<UartDevice<nrf51::uart::UART>>,
+        capsules::console::Console::new(console_uart,
                                         115200,
                                         &mut capsules::console::WRITE_BUF,
+                                        &mut capsules::console::READ_BUF,
+                                        &mut capsules::console::ECHO_BUF,
                                         kernel::Container::create()),
-        224/8);
-    UART::set_client(&nrf51::uart::UART0, console);
+        672/8);
+    UART::set_client(console_uart, console);
     console.initialize();
+    // To separate the output of each app on the host with tools/console_demux:
+    // console.set_framed(true);
+    // kernel::debug::set_framed(true);
 
     let alarm = &nrf51::rtc::RTC;
     alarm.start();
@@ -206,6 +226,38 @@ pub unsafe fn reset_handler() {
         12);
     virtual_alarm1.set_client(timer);
 
+    let button_states = static_init!(
+        [capsules::button::ButtonState; 4],
+        [capsules::button::ButtonState::new(),
+         capsules::button::ButtonState::new(),
+         capsules::button::ButtonState::new(),
+         capsules::button::ButtonState::new()],
+        4 * 20);
+    let button_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    let button = static_init!(
+        capsules::button::Button<'static, nrf51::gpio::GPIOPin, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::button::Button::new(button_pins,
+                                      button_states,
+                                      button_alarm,
+                                      capsules::led::ActivationMode::ActiveLow,
+                                      20,
+                                      kernel::Container::create()),
+        320/8);
+    button_alarm.set_client(button);
+    for btn in button_pins.iter() {
+        btn.set_client(button);
+    }
+
+    let rng_buf = static_init!([u8; 64], [0; 64], 64);
+    let rng = static_init!(
+        capsules::rng::Rng<'static, nrf51::rng::Rng>,
+        capsules::rng::Rng::new(&nrf51::rng::RNG, rng_buf, kernel::Container::create()),
+        20);
+    nrf51::rng::RNG.set_client(rng);
+
     // Start all of the clocks. Low power operation will require a better
     // approach than this.
     nrf51::clock::CLOCK.low_stop();
@@ -223,6 +275,7 @@ pub unsafe fn reset_handler() {
         console: console,
         led: led,
         button: button,
+        rng: rng,
     };
 
     alarm.start();
@@ -243,12 +296,14 @@ use core::fmt::Arguments;
 #[cfg(not(test))]
 #[lang="panic_fmt"]
 #[no_mangle]
-pub unsafe extern "C" fn rust_begin_unwind(_args: &Arguments,
-                                           _file: &'static str,
-                                           _line: usize)
+pub unsafe extern "C" fn rust_begin_unwind(args: &Arguments,
+                                           file: &'static str,
+                                           line: usize)
                                            -> ! {
     use kernel::hil::gpio::Pin;
 
+    kernel::debug::panic(*args, file, line as u32);
+
     let led0 = &nrf51::gpio::PORT[LED1_PIN];
     let led1 = &nrf51::gpio::PORT[LED2_PIN];
 
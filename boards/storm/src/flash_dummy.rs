@@ -3,163 +3,139 @@
 //! will generate an interrupt.
 
 use core::cell::Cell;
-use sam4l::flashcalw;
-use sam4l::flashcalw::{Error, Client};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::{Client, Error, Flash};
+use sam4l::flashcalw::{self, FLASHCALW, Sam4lPage};
 
 // ======================================
 //  Test the flash controller (using interrupts).
-//  This is essentially a state-machine.
+//  This is essentially a state-machine driven by the flash callbacks: each
+//  page is erased, written and read back `num_cycle_per_page` times.
 //  Note: This assumes that all the buses in the config function is on for the
 //  entire time.
 // ======================================
 
-#[allow(dead_code)]
-#[derive(Copy,Clone,PartialEq)]
-enum FlashClientState {
-    ClearPageBuffer,
-    WritePageBuffer,
-    Writing,
-    Reading,
-    Erasing,
-    EWRCycleStart, // Start of the Erase, Write, Read Cycle
-}
-
 struct FlashClient {
-    state: Cell<FlashClientState>,
-    page: Cell<i32>,
+    page: Cell<usize>,
     num_cycle_per_page: u32,
     val_data: Cell<u8>,
     cycles_finished: Cell<u32>,
+    buffer: TakeCell<&'static mut Sam4lPage>,
 }
 
+static mut PAGE_BUFFER: Sam4lPage = Sam4lPage([0; 512]);
+
 static mut FLASH_CLIENT: FlashClient = FlashClient {
-    state: Cell::new(FlashClientState::EWRCycleStart),
     page: Cell::new(53), // Page to start
     num_cycle_per_page: 2, // How many times to repeat a Erase/Write/Read cycle on a page
     val_data: Cell::new(2), // Data to write to the page.
     cycles_finished: Cell::new(0),
+    buffer: TakeCell::empty(),
 };
 
-const MAX_PAGE_NUM: i32 = 80;   // Page to go up to
+const MAX_PAGE_NUM: usize = 80;   // Page to go up to
+
+impl FlashClient {
+    //  Starts the next Erase, Write, Read cycle.
+    fn start_cycle(&self) {
+        let dev = unsafe { &flashcalw::flash_controller };
+
+        if self.cycles_finished.get() >= self.num_cycle_per_page {
+            // reset count
+            self.cycles_finished.set(0);
+            // increment pg num
+            self.page.set(self.page.get() + 1);
+            if self.page.get() > MAX_PAGE_NUM {
+                debug!("Flash test done");
+                return;
+            }
+            debug!("==============Starting work on page {} \
+                    =================",
+                   self.page.get());
+        } else {
+            debug!("\t Still Cycling page {}", self.page.get());
+        }
 
-impl Client for FlashClient {
-    fn command_complete(&self, _error: Error) {
+        // increment cycle count and val_data
+        self.cycles_finished.set(self.cycles_finished.get() + 1);
+        self.val_data.set(self.val_data.get() + 1);
 
-        print!("Client Notified that job done in state {}",
-               self.state.get() as u32);
+        debug!("\tErasing page {}", self.page.get());
+        if let Err(error) = dev.erase_page(self.page.get()) {
+            debug!("\tErase failed: {:?}", error);
+        }
+    }
+}
 
-        let dev = unsafe { &mut flashcalw::flash_controller };
+impl Client<FLASHCALW> for FlashClient {
+    fn erase_complete(&self, error: Error) {
+        let dev = unsafe { &flashcalw::flash_controller };
 
-        match self.state.get() {
-            FlashClientState::Writing => {
-                println!("\tWriting page {}", self.page.get());
-                let data: [u8; 512] = [self.val_data.get(); 512];
-                dev.write_page(self.page.get(), &data);
-                self.state.set(FlashClientState::Reading);
-            }
-            FlashClientState::Reading => {
-                //  The callback from completing the write will lead to this being
-                //  called.
-                let mut pass = true;
-
-                //  Prints out any differences in the flash page.
-                println!("\treading page {}", self.page.get());
-                let mut data: [u8; 512] = [0; 512];
-                dev.read(self.page.get() as usize * 512, 512, &mut data);
-
-                // verify what we expect
-                for i in 0..512 {
-                    if data[i] != self.val_data.get() {
-                        pass = false;
-                        println!("\t\t======bit:{} expected {}, got {}========",
-                                 i,
-                                 self.val_data.get(),
-                                 data[i]);
-
-                    }
-                }
-
-                //  If there's any discrepancies read the page 3x more and output
-                //  differences.
-                if !pass {
-                    for _ in 0..3 {
-                        println!("\treading page {}", self.page.get());
-                        let mut data: [u8; 512] = [0; 512];
-                        dev.read(self.page.get() as usize * 512, 512, &mut data);
-                        // verify what we expect
-                        for i in 0..512 {
-                            if data[i] != self.val_data.get() {
-                                println!("\t\t\t======bit:{} expected {}, got {}========",
-                                         i,
-                                         self.val_data.get(),
-                                         data[i]);
-
-                            }
-                        }
-                    }
-                }
-
-                // start cycle again
-                self.state.set(FlashClientState::EWRCycleStart);
-                // call self as reading isn't a callback...
-                self.command_complete(Error::CommandComplete);
-            }
-            FlashClientState::Erasing => {
-                println!("\tErasing page {}", self.page.get());
-                dev.erase_page(self.page.get());
-                self.state.set(FlashClientState::Writing);
-            }
-            FlashClientState::EWRCycleStart => {
-                if self.page.get() <= MAX_PAGE_NUM {
-                    if self.cycles_finished.get() >= self.num_cycle_per_page {
-                        // reset count
-                        self.cycles_finished.set(1);
-                        // increment pg num
-                        self.page.set(self.page.get() + 1);
-                        // increment val_data
-                        self.val_data.set(self.val_data.get() + 1);
-                        // start new cycle
-                        if self.page.get() <= MAX_PAGE_NUM {
-                            self.state.set(FlashClientState::Erasing);
-                            println!("==============Starting work on page {} \
-                                =================",
-                                     self.page.get());
-                            self.command_complete(Error::CommandComplete);
-                        }
-                    } else {
-                        println!("\t Still Cycling page {}", self.page.get());
-                        // increment cycle count
-                        self.cycles_finished.set(self.cycles_finished.get() + 1);
-                        // increment val_data
-                        self.val_data.set(self.val_data.get() + 1);
-                        // continue cycle
-                        self.state.set(FlashClientState::Erasing);
-                        self.command_complete(Error::CommandComplete);
-                    }
-                }
+        if error != Error::CommandComplete {
+            debug!("\tErase of page {} failed: {:?}", self.page.get(), error);
+        }
+
+        debug!("\tWriting page {}", self.page.get());
+        self.buffer.take().map(|buffer| {
+            for b in buffer.as_mut().iter_mut() {
+                *b = self.val_data.get();
             }
-            _ => {
-                panic!("Should never reach here!");
+            if let Err((error, buffer)) = dev.write_page(self.page.get(), buffer) {
+                debug!("\tWrite failed: {:?}", error);
+                self.buffer.replace(buffer);
             }
+        });
+    }
+
+    fn write_complete(&self, buffer: &'static mut Sam4lPage, error: Error) {
+        let dev = unsafe { &flashcalw::flash_controller };
+
+        if error != Error::CommandComplete {
+            debug!("\tWrite of page {} failed: {:?}", self.page.get(), error);
+        }
+
+        //  Clear the buffer so the read back actually checks the flash.
+        for b in buffer.as_mut().iter_mut() {
+            *b = 0;
+        }
 
+        debug!("\treading page {}", self.page.get());
+        if let Err((error, buffer)) = dev.read_page(self.page.get(), buffer) {
+            debug!("\tRead failed: {:?}", error);
+            self.buffer.replace(buffer);
         }
     }
+
+    fn read_complete(&self, buffer: &'static mut Sam4lPage, _error: Error) {
+        //  Prints out any differences in the flash page.
+        for (i, b) in buffer.as_ref().iter().enumerate() {
+            if *b != self.val_data.get() {
+                debug!("\t\t======bit:{} expected {}, got {}========",
+                       i,
+                       self.val_data.get(),
+                       b);
+            }
+        }
+        self.buffer.replace(buffer);
+
+        // start cycle again
+        self.start_cycle();
+    }
 }
 
 // Sets up the testing for the flash driver.
 pub fn set_read_write_test() {
-    let flash_client = unsafe { &mut FLASH_CLIENT };
+    let flash_client = unsafe { &FLASH_CLIENT };
     let dev = unsafe { &mut flashcalw::flash_controller };
 
+    flash_client.buffer.replace(unsafe { &mut PAGE_BUFFER });
     dev.set_client(flash_client);
-    print!("Calling configure...");
+    debug!("Calling configure...");
     dev.configure();
-    println!("Is the picocache on? {}",
-             if dev.pico_enabled() { "yes" } else { "no" });
-
-    // generates an interrupt which will cause a callback after initalization.
-    dev.lock_page_region(0, false);
+    debug!("Is the picocache on? {}",
+           if dev.pico_enabled() { "yes" } else { "no" });
 
+    flash_client.start_cycle();
 }
 
 /// This function primarily tests meta information for the chip on the
@@ -167,7 +143,7 @@ pub fn set_read_write_test() {
 /// flash size asserts might fail (as they might not have the same flash size).
 #[allow(unused_unsafe)]
 pub unsafe fn meta_test() {
-    println!("Testing Meta Info...");
+    debug!("Testing Meta Info...");
     assert_eq!(flashcalw::flash_controller.get_page_size(), 512);
     assert_eq!(flashcalw::flash_controller.get_flash_size(), 512 << 10);
     assert_eq!(flashcalw::flash_controller.get_number_pages(), 1024);
@@ -180,5 +156,5 @@ pub unsafe fn meta_test() {
                    pg_num);
         pg_num += 64;
     }
-    println!("Passed Meta Info...");
+    debug!("Passed Meta Info...");
 }
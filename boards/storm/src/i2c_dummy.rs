@@ -1,8 +1,8 @@
 //! A dummy I2C client
 
 use core::cell::Cell;
-use hil;
-use hil::i2c::I2CController;
+use kernel::hil;
+use kernel::hil::i2c::I2CMaster;
 use sam4l::i2c;
 
 // ===========================================
@@ -15,12 +15,12 @@ struct ScanClient {
 
 static mut SCAN_CLIENT: ScanClient = ScanClient { dev_id: Cell::new(1) };
 
-impl hil::i2c::I2CClient for ScanClient {
+impl hil::i2c::I2CHwMasterClient for ScanClient {
     fn command_complete(&self, buffer: &'static mut [u8], error: hil::i2c::Error) {
         let mut dev_id = self.dev_id.get();
 
         match error {
-            hil::i2c::Error::CommandComplete => println!("0x{:x}", dev_id),
+            hil::i2c::Error::CommandComplete => debug!("0x{:x}", dev_id),
             _ => {}
         }
 
@@ -28,10 +28,10 @@ impl hil::i2c::I2CClient for ScanClient {
         if dev_id < 0x7F {
             dev_id += 1;
             self.dev_id.set(dev_id);
-            dev.write(dev_id, i2c::START | i2c::STOP, buffer, 1);
+            dev.write(dev_id, buffer, 1);
         } else {
-            println!("Done scanning for I2C devices. Buffer len: {}",
-                     buffer.len());
+            debug!("Done scanning for I2C devices. Buffer len: {}",
+                   buffer.len());
         }
     }
 }
@@ -42,14 +42,11 @@ pub fn i2c_scan_slaves() {
     let dev = unsafe { &mut i2c::I2C2 };
 
     let i2c_client = unsafe { &SCAN_CLIENT };
-    dev.set_client(i2c_client);
+    dev.set_master_client(i2c_client);
     dev.enable();
 
-    println!("Scanning for I2C devices...");
-    dev.write(i2c_client.dev_id.get(),
-              i2c::START | i2c::STOP,
-              unsafe { &mut DATA },
-              1);
+    debug!("Scanning for I2C devices...");
+    dev.write(i2c_client.dev_id.get(), unsafe { &mut DATA }, 1);
 }
 
 // ===========================================
@@ -70,7 +67,7 @@ struct TMP006Client {
 static mut TMP006_CLIENT: TMP006Client =
     TMP006Client { state: Cell::new(TmpClientState::Enabling) };
 
-impl hil::i2c::I2CClient for TMP006Client {
+impl hil::i2c::I2CHwMasterClient for TMP006Client {
     fn command_complete(&self, buffer: &'static mut [u8], error: hil::i2c::Error) {
         use self::TmpClientState::*;
 
@@ -78,19 +75,19 @@ impl hil::i2c::I2CClient for TMP006Client {
 
         match self.state.get() {
             Enabling => {
-                println!("Selecting Device Id Register ({})", error);
+                debug!("Selecting Device Id Register ({})", error);
                 buffer[0] = 0xFF as u8; // Device Id Register
                 dev.write_read(0x40, buffer, 1, 2);
                 self.state.set(ReadingDevIdReg);
             }
             SelectingDevIdReg => {
-                println!("Device Id Register selected ({})", error);
-                dev.read(0x40, i2c::START | i2c::STOP, buffer, 2);
+                debug!("Device Id Register selected ({})", error);
+                dev.read(0x40, buffer, 2);
                 self.state.set(ReadingDevIdReg);
             }
             ReadingDevIdReg => {
                 let dev_id = (((buffer[0] as u16) << 8) | buffer[1] as u16) as u16;
-                println!("Device Id is 0x{:x} ({})", dev_id, error);
+                debug!("Device Id is 0x{:x} ({})", dev_id, error);
             }
         }
     }
@@ -109,16 +106,16 @@ pub fn i2c_tmp006_test() {
     let dev = unsafe { &mut i2c::I2C2 };
 
     let i2c_client = unsafe { &TMP006_CLIENT };
-    dev.set_client(i2c_client);
+    dev.set_master_client(i2c_client);
     dev.enable();
 
     let buf = unsafe { &mut DATA };
-    println!("Enabling TMP006...");
+    debug!("Enabling TMP006...");
     let config = 0x7100 | (((2 & 0x7) as u16) << 9);
     buf[0] = 0x2 as u8; // 0x2 == Configuration register
     buf[1] = ((config & 0xFF00) >> 8) as u8;
     buf[2] = (config & 0x00FF) as u8;
-    dev.write(0x40, i2c::START | i2c::STOP, buf, 3);
+    dev.write(0x40, buf, 3);
 }
 
 // ===========================================
@@ -140,7 +137,7 @@ struct AccelClient {
 static mut ACCEL_CLIENT: AccelClient =
     AccelClient { state: Cell::new(AccelClientState::ReadingWhoami) };
 
-impl hil::i2c::I2CClient for AccelClient {
+impl hil::i2c::I2CHwMasterClient for AccelClient {
     fn command_complete(&self, buffer: &'static mut [u8], error: hil::i2c::Error) {
         use self::AccelClientState::*;
 
@@ -148,15 +145,15 @@ impl hil::i2c::I2CClient for AccelClient {
 
         match self.state.get() {
             ReadingWhoami => {
-                println!("WHOAMI Register 0x{:x} ({})", buffer[0], error);
-                println!("Activating Sensor...");
+                debug!("WHOAMI Register 0x{:x} ({})", buffer[0], error);
+                debug!("Activating Sensor...");
                 buffer[0] = 0x2A as u8; // CTRL_REG1
                 buffer[1] = 1; // Bit 1 sets `active`
-                dev.write(0x1e, i2c::START | i2c::STOP, buffer, 2);
+                dev.write(0x1e, buffer, 2);
                 self.state.set(Activating);
             }
             Activating => {
-                println!("Sensor Activated ({})", error);
+                debug!("Sensor Activated ({})", error);
                 buffer[0] = 0x01 as u8; // X-MSB register
                 // Reading 6 bytes will increment the register pointer through
                 // X-MSB, X-LSB, Y-MSB, Y-LSB, Z-MSB, Z-LSB
@@ -172,21 +169,21 @@ impl hil::i2c::I2CClient for AccelClient {
                 let y = ((y >> 2) * 976) / 1000;
                 let z = ((z >> 2) * 976) / 1000;
 
-                println!("Accel data ready x: {}, y: {}, z: {} ({})",
-                         x >> 2,
-                         y >> 2,
-                         z >> 2,
-                         error);
+                debug!("Accel data ready x: {}, y: {}, z: {} ({})",
+                       x >> 2,
+                       y >> 2,
+                       z >> 2,
+                       error);
 
-                println!("Deactivating Sensor..");
+                debug!("Deactivating Sensor..");
                 buffer[0] = 0x2A as u8; // CTRL_REG1
                 buffer[1] = 0; // Bit 1 sets `active`
-                dev.write(0x1e, i2c::START | i2c::STOP, buffer, 2);
+                dev.write(0x1e, buffer, 2);
                 self.state.set(Deactivating);
             }
             Deactivating => {
-                println!("Sensor deactivated ({})", error);
-                println!("Reading Accel's WHOAMI...");
+                debug!("Sensor deactivated ({})", error);
+                debug!("Reading Accel's WHOAMI...");
                 buffer[0] = 0x0D as u8; // 0x0D == WHOAMI register
                 dev.write_read(0x1e, buffer, 1, 1);
                 self.state.set(AccelClientState::ReadingWhoami);
@@ -201,11 +198,11 @@ pub fn i2c_accel_test() {
     let dev = unsafe { &mut i2c::I2C2 };
 
     let i2c_client = unsafe { &ACCEL_CLIENT };
-    dev.set_client(i2c_client);
+    dev.set_master_client(i2c_client);
     dev.enable();
 
     let buf = unsafe { &mut DATA };
-    println!("Reading Accel's WHOAMI...");
+    debug!("Reading Accel's WHOAMI...");
     buf[0] = 0x0D as u8; // 0x0D == WHOAMI register
     dev.write_read(0x1e, buf, 1, 1);
     i2c_client.state.set(AccelClientState::ReadingWhoami);
@@ -228,7 +225,7 @@ struct LiClient {
 
 static mut LI_CLIENT: LiClient = LiClient { state: Cell::new(LiClientState::Enabling) };
 
-impl hil::i2c::I2CClient for LiClient {
+impl hil::i2c::I2CHwMasterClient for LiClient {
     fn command_complete(&self, buffer: &'static mut [u8], error: hil::i2c::Error) {
         use self::LiClientState::*;
 
@@ -236,7 +233,7 @@ impl hil::i2c::I2CClient for LiClient {
 
         match self.state.get() {
             Enabling => {
-                println!("Reading Lumminance Registers ({})", error);
+                debug!("Reading Lumminance Registers ({})", error);
                 buffer[0] = 0x02 as u8;
                 buffer[0] = 0;
                 dev.write_read(0x44, buffer, 1, 2);
@@ -244,7 +241,7 @@ impl hil::i2c::I2CClient for LiClient {
             }
             ReadingLI => {
                 let intensity = ((buffer[1] as usize) << 8) | buffer[0] as usize;
-                println!("Light Intensity: {}% ({})", (intensity * 100) >> 16, error);
+                debug!("Light Intensity: {}% ({})", (intensity * 100) >> 16, error);
                 buffer[0] = 0x02 as u8;
                 dev.write_read(0x44, buffer, 1, 2);
                 self.state.set(ReadingLI);
@@ -265,14 +262,14 @@ pub fn i2c_li_test() {
     let dev = unsafe { &mut i2c::I2C2 };
 
     let i2c_client = unsafe { &LI_CLIENT };
-    dev.set_client(i2c_client);
+    dev.set_master_client(i2c_client);
     dev.enable();
 
     let buf = unsafe { &mut DATA };
-    println!("Enabling LI...");
+    debug!("Enabling LI...");
     buf[0] = 0;
     buf[1] = 0b10100000;
     buf[2] = 0b00000000;
-    dev.write(0x44, i2c::START | i2c::STOP, buf, 3);
+    dev.write(0x44, buf, 3);
     i2c_client.state.set(LiClientState::Enabling);
 }
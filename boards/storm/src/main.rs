@@ -4,7 +4,7 @@
 
 extern crate capsules;
 extern crate cortexm4;
-#[macro_use(static_init)]
+#[macro_use(debug, static_init)]
 extern crate kernel;
 extern crate sam4l;
 
@@ -12,15 +12,18 @@ use capsules::console::{self, Console};
 use capsules::nrf51822_serialization::{self, Nrf51822Serialization};
 use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_flash::{FlashUser, MuxFlash};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules::virtual_spi::{MuxSPIMaster, SPIMasterDevice};
+use capsules::virtual_uart::{MuxUart, UartDevice};
 use kernel::{Chip, MPU, Platform};
 use kernel::hil;
 use kernel::hil::Controller;
 use kernel::hil::gpio::PinCtl;
+use kernel::hil::sensors::{LightSensor, TemperatureSensor};
 use kernel::hil::spi::SpiMaster;
 use sam4l::usart;
 
-#[macro_use]
 pub mod io;
 
 // HAL unit tests. To enable a particular unit test, uncomment the call to
@@ -78,16 +81,33 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
 }
 
 struct Firestorm {
-    console: &'static Console<'static, usart::USART>,
+    console: &'static Console<'static, UartDevice<'static, usart::USART>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     tmp006: &'static capsules::tmp006::TMP006<'static>,
     isl29035: &'static capsules::isl29035::Isl29035<'static>,
-    spi: &'static capsules::spi::Spi<'static, sam4l::spi::Spi>,
+    spi: &'static capsules::spi::Spi<'static>,
     nrf51822: &'static Nrf51822Serialization<'static, usart::USART>,
-    adc: &'static capsules::adc::ADC<'static, sam4l::adc::Adc>,
+    adc: &'static capsules::adc::ADC<'static,
+                                     capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>,
+                                     capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
+    app_watchdog: &'static capsules::app_watchdog::AppWatchdog<'static,
+                                                               VirtualMuxAlarm<'static,
+                                                                               sam4l::ast::Ast<'static>>>,
+    app_storage: &'static capsules::app_storage::AppStorage<'static,
+                                                            FlashUser<'static,
+                                                                      sam4l::flashcalw::FLASHCALW>>,
+    kv_store: &'static capsules::kv_store::KVStore<'static,
+                                                   FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+    log: &'static capsules::log::Log<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+    rng: &'static capsules::rng::Rng<'static, sam4l::trng::Trng>,
+    pwm: &'static capsules::pwm::Pwm<'static, sam4l::tc::TcPwm>,
     ipc: kernel::ipc::IPC,
+    process_info: capsules::process_info::ProcessInfo,
+    i2c_master: &'static capsules::i2c_master_driver::I2CMasterDriver<'static>,
+    temperature: &'static capsules::temperature::TemperatureDriver<'static>,
+    ambient_light: &'static capsules::ambient_light::AmbientLightDriver<'static>,
 }
 
 impl Platform for Firestorm {
@@ -105,6 +125,16 @@ impl Platform for Firestorm {
             6 => f(Some(self.isl29035)),
             7 => f(Some(self.adc)),
             8 => f(Some(self.led)),
+            12 => f(Some(&self.process_info)),
+            13 => f(Some(self.app_watchdog)),
+            14 => f(Some(self.app_storage)),
+            15 => f(Some(self.kv_store)),
+            16 => f(Some(self.log)),
+            17 => f(Some(self.rng)),
+            18 => f(Some(self.pwm)),
+            20 => f(Some(self.i2c_master)),
+            21 => f(Some(self.temperature)),
+            23 => f(Some(self.ambient_light)),
 
             0xff => f(Some(&self.ipc)),
             _ => f(None),
@@ -144,10 +174,10 @@ unsafe fn set_pin_primary_functions() {
     // Left  column: SAM4L peripheral function
     PA[04].configure(Some(C));  // LI_INT   --  EIC EXTINT2
     PA[06].configure(Some(C));  // EXTINT1  --  EIC EXTINT1
-    PA[08].configure(None);     // PWM 0    --  GPIO pin
-    PC[16].configure(None);     // PWM 1    --  GPIO pin
-    PC[17].configure(None);     // PWM 2    --  GPIO pin
-    PC[18].configure(None);     // PWM 3    --  GPIO pin
+    PA[08].configure(Some(B));  // PWM 0    --  TC0 A0
+    PC[16].configure(Some(D));  // PWM 1    --  TC1 A0
+    PC[17].configure(Some(D));  // PWM 2    --  TC1 B0
+    PC[18].configure(Some(D));  // PWM 3    --  TC1 A1
     PA[05].configure(Some(A));  // AD5      --  ADCIFE AD1
     PA[07].configure(Some(A));  // AD4      --  ADCIFE AD2
     PB[02].configure(Some(A));  // AD3      --  ADCIFE AD3
@@ -222,14 +252,36 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
+    // Kernel debug output shares USART3 with the console
+    let uart_mux = static_init!(
+        MuxUart<'static, usart::USART>,
+        MuxUart::new(&usart::USART3),
+        16);
+    hil::uart::UART::set_client(&usart::USART3, uart_mux);
+    let console_uart = static_init!(
+        UartDevice<'static, usart::USART>,
+        UartDevice::new(uart_mux, true),
+        32);
+    console_uart.setup();
+    let debug_uart = static_init!(
+        UartDevice<'static, usart::USART>,
+        UartDevice::new(uart_mux, false),
+        32);
+    debug_uart.setup();
+    kernel::debug::assign_uart(debug_uart);
     let console = static_init!(
-        Console<usart::USART>,
-        Console::new(&usart::USART3,
+        Console<UartDevice<usart::USART>>,
+        Console::new(console_uart,
                      115200,
                      &mut console::WRITE_BUF,
+                     &mut console::READ_BUF,
+                     &mut console::ECHO_BUF,
                      kernel::Container::create()),
-        224/8);
-    hil::uart::UART::set_client(&usart::USART3, console);
+        672/8);
+    hil::uart::UART::set_client(console_uart, console);
+    // To separate the output of each app on the host with tools/console_demux:
+    // console.set_framed(true);
+    // kernel::debug::set_framed(true);
 
     // Create the Nrf51822Serialization driver for passing BLE commands
     // over UART to the nRF51822 radio.
@@ -238,7 +290,7 @@ pub unsafe fn reset_handler() {
         Nrf51822Serialization::new(&usart::USART2,
                                    &mut nrf51822_serialization::WRITE_BUF,
                                    &mut nrf51822_serialization::READ_BUF),
-        608/8);
+        704/8);
     hil::uart::UART::set_client(&usart::USART2, nrf_serialization);
 
     let ast = &sam4l::ast::AST;
@@ -252,26 +304,64 @@ pub unsafe fn reset_handler() {
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2), 20);
     sam4l::i2c::I2C2.set_master_client(mux_i2c);
 
+    // Give up on I2C transactions that take too long, and free the bus
+    let i2c_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    i2c_alarm.set_client(&sam4l::i2c::I2C2);
+    sam4l::i2c::I2C2.set_timeout_alarm(i2c_alarm);
+    sam4l::i2c::I2C2.set_bus_pins(&sam4l::gpio::PA[22],
+                                  &sam4l::gpio::PA[21],
+                                  sam4l::gpio::PeripheralFunction::E);
+
     // Configure the TMP006. Device address 0x40
-    let tmp006_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x40), 32);
+    let tmp006_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x40), 88);
     sam4l::gpio::PA[9].set_input_mode(kernel::hil::gpio::InputMode::PullUp);
     let tmp006 = static_init!(
         capsules::tmp006::TMP006<'static>,
         capsules::tmp006::TMP006::new(tmp006_i2c,
                                      &sam4l::gpio::PA[9],
                                      &mut capsules::tmp006::BUFFER),
-        52);
+        68);
     tmp006_i2c.set_client(tmp006);
     sam4l::gpio::PA[9].set_client(tmp006);
 
     // Configure the ISL29035, device address 0x44
-    let isl29035_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x44), 32);
+    let isl29035_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x44), 88);
     let isl29035 = static_init!(
         capsules::isl29035::Isl29035<'static>,
         capsules::isl29035::Isl29035::new(isl29035_i2c, &mut capsules::isl29035::BUF),
-        36);
+        52);
     isl29035_i2c.set_client(isl29035);
 
+    // Userspace I2C. Apps may only access the addresses listed for their
+    // package name, e.g. `Permissions { package_name: b"lightmeter",
+    // addresses: &[0x44] }`.
+    static I2C_PERMISSIONS: [capsules::i2c_master_driver::Permissions; 0] = [];
+    let app_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0), 88);
+    let i2c_master = static_init!(
+        capsules::i2c_master_driver::I2CMasterDriver<'static>,
+        capsules::i2c_master_driver::I2CMasterDriver::new(
+            app_i2c,
+            &I2C_PERMISSIONS,
+            &mut capsules::i2c_master_driver::BUFFER,
+            kernel::Container::create()),
+        40);
+    app_i2c.set_client(i2c_master);
+
+    // Sensor drivers with the same interface on every board
+    let temperature = static_init!(
+        capsules::temperature::TemperatureDriver<'static>,
+        capsules::temperature::TemperatureDriver::new(tmp006, kernel::Container::create()),
+        16);
+    tmp006.set_temperature_client(temperature);
+    let ambient_light = static_init!(
+        capsules::ambient_light::AmbientLightDriver<'static>,
+        capsules::ambient_light::AmbientLightDriver::new(isl29035, kernel::Container::create()),
+        16);
+    isl29035.set_light_client(ambient_light);
+
     let virtual_alarm1 = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
@@ -282,15 +372,137 @@ pub unsafe fn reset_handler() {
         12);
     virtual_alarm1.set_client(timer);
 
-    // Initialize and enable SPI HAL
-    let chip_selects = static_init!([u8; 4], [0, 1, 2, 3], 4);
+    // Supervise apps and service the hardware watchdog, waking up at least
+    // every 500ms so the 1s watchdog period never expires while sleeping.
+    let virtual_alarm2 = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        24);
+    let app_watchdog = static_init!(
+        capsules::app_watchdog::AppWatchdog<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::app_watchdog::AppWatchdog::new(virtual_alarm2,
+                                                 capsules::app_watchdog::FailurePolicy::RestartApp,
+                                                 500,
+                                                 kernel::Container::create()),
+        16);
+    virtual_alarm2.set_client(app_watchdog);
+    app_watchdog.start();
+    kernel::watchdog::enable(&sam4l::wdt::WDT, 1000);
+
+    // The last 96KB of flash, right after the app images, hold the log
+    // (pages 832-879), the key-value store (pages 880-895) and app storage
+    // (pages 896-1023). See layout.ld.
+    let mux_flash = static_init!(
+        MuxFlash<'static, sam4l::flashcalw::FLASHCALW>,
+        MuxFlash::new(&sam4l::flashcalw::flash_controller),
+        12);
+    sam4l::flashcalw::flash_controller.configure();
+    sam4l::flashcalw::flash_controller.set_client(mux_flash);
+
+    let app_storage_flash = static_init!(
+        FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        FlashUser::new(mux_flash),
+        32);
+    let flash_page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+    let app_storage = static_init!(
+        capsules::app_storage::AppStorage<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+        capsules::app_storage::AppStorage::new(app_storage_flash,
+                                               896,
+                                               128,
+                                               flash_page,
+                                               kernel::Container::create()),
+        584);
+    app_storage_flash.set_client(app_storage);
+    app_storage.initialize();
+
+    let kv_store_flash = static_init!(
+        FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        FlashUser::new(mux_flash),
+        32);
+    let kv_tail_page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+    let kv_scratch_page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+    let kv_store = static_init!(
+        capsules::kv_store::KVStore<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+        capsules::kv_store::KVStore::new(kv_store_flash,
+                                         880,
+                                         16,
+                                         kv_tail_page,
+                                         kv_scratch_page,
+                                         kernel::Container::create()),
+        2032);
+    kv_store_flash.set_client(kv_store);
+    kv_store.initialize();
+
+    let log_flash = static_init!(
+        FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        FlashUser::new(mux_flash),
+        32);
+    let log_tail_page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+    let log_spare_page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+    let log_read_page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+    let log = static_init!(
+        capsules::log::Log<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+        capsules::log::Log::new(log_flash,
+                                832,
+                                48,
+                                log_tail_page,
+                                log_spare_page,
+                                log_read_page,
+                                kernel::Container::create()),
+        628);
+    log_flash.set_client(log);
+    log.initialize();
+
+    let rng_buf = static_init!([u8; 64], [0; 64], 64);
+    let rng = static_init!(
+        capsules::rng::Rng<'static, sam4l::trng::Trng>,
+        capsules::rng::Rng::new(&sam4l::trng::TRNG, rng_buf, kernel::Container::create()),
+        20);
+    sam4l::trng::TRNG.set_client(rng);
+
+    // PWM 1 and PWM 2 share a TC channel, and so their frequency
+    let pwm_pins = static_init!(
+        [&'static sam4l::tc::TcPwm; 4],
+        [&sam4l::tc::TC0_A0, &sam4l::tc::TC1_A0, &sam4l::tc::TC1_B0, &sam4l::tc::TC1_A1],
+        4 * 4);
+    let pwm = static_init!(
+        capsules::pwm::Pwm<'static, sam4l::tc::TcPwm>,
+        capsules::pwm::Pwm::new(pwm_pins),
+        8);
+
+    // Initialize and enable SPI HAL, shared by apps through one virtual
+    // device per chip select
+    let mux_spi = static_init!(
+        MuxSPIMaster<'static, sam4l::spi::Spi>,
+        MuxSPIMaster::new(&sam4l::spi::SPI),
+        12);
+    sam4l::spi::SPI.set_client(mux_spi);
+    sam4l::spi::SPI.init();
+    let spi_cs0 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 0),
+                               60);
+    let spi_cs1 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 1),
+                               60);
+    let spi_cs2 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 2),
+                               60);
+    let spi_cs3 = static_init!(SPIMasterDevice<'static, sam4l::spi::Spi>,
+                               SPIMasterDevice::new(mux_spi, 3),
+                               60);
+    let spi_devices = static_init!(
+        [&'static hil::spi::SPIMasterDevice; 4],
+        [spi_cs0, spi_cs1, spi_cs2, spi_cs3],
+        4 * 8);
     let spi = static_init!(
-        capsules::spi::Spi<'static, sam4l::spi::Spi>,
-        capsules::spi::Spi::new(&mut sam4l::spi::SPI, chip_selects),
-        92);
+        capsules::spi::Spi<'static>,
+        capsules::spi::Spi::new(spi_devices, kernel::Container::create()),
+        56);
     spi.config_buffers(&mut spi_read_buf, &mut spi_write_buf);
-    sam4l::spi::SPI.set_client(spi);
-    sam4l::spi::SPI.init();
+    spi_cs0.set_client(spi);
+    spi_cs1.set_client(spi);
+    spi_cs2.set_client(spi);
+    spi_cs3.set_client(spi);
 
     // LEDs
     let led_pins = static_init!(
@@ -303,11 +515,28 @@ pub unsafe fn reset_handler() {
         96/8);
 
     // Setup ADC
+    let adc_mux = static_init!(
+        capsules::virtual_adc::MuxAdc<'static, sam4l::adc::Adc>,
+        capsules::virtual_adc::MuxAdc::new(&sam4l::adc::ADC),
+        12);
+    sam4l::adc::ADC.set_client(adc_mux);
+    sam4l::adc::ADC.set_continuous_client(adc_mux);
+    let adc_user = static_init!(
+        capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>,
+        capsules::virtual_adc::AdcUser::new(adc_mux),
+        60);
     let adc = static_init!(
-        capsules::adc::ADC<'static, sam4l::adc::Adc>,
-        capsules::adc::ADC::new(&mut sam4l::adc::ADC),
-        160/8);
-    sam4l::adc::ADC.set_client(adc);
+        capsules::adc::ADC<'static,
+                           capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>,
+                           capsules::virtual_adc::AdcUser<'static, sam4l::adc::Adc>>,
+        capsules::adc::ADC::new(adc_user,
+                                adc_user,
+                                &mut capsules::adc::BUFFER1,
+                                &mut capsules::adc::BUFFER2,
+                                kernel::Container::create()),
+        56);
+    adc_user.set_client(adc);
+    adc_user.set_continuous_client(adc);
 
 
     // set GPIO driver controlling remaining GPIO pins
@@ -328,8 +557,8 @@ pub unsafe fn reset_handler() {
     );
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
-        capsules::gpio::GPIO::new(gpio_pins),
-        20);
+        capsules::gpio::GPIO::new(gpio_pins, kernel::Container::create()),
+        16);
     for pin in gpio_pins.iter() {
         pin.set_client(gpio);
     }
@@ -351,7 +580,17 @@ pub unsafe fn reset_handler() {
         nrf51822: nrf_serialization,
         adc: adc,
         led: led,
+        app_watchdog: app_watchdog,
+        app_storage: app_storage,
+        kv_store: kv_store,
+        log: log,
+        rng: rng,
+        pwm: pwm,
         ipc: kernel::ipc::IPC::new(),
+        process_info: capsules::process_info::ProcessInfo::new(),
+        i2c_master: i2c_master,
+        temperature: temperature,
+        ambient_light: ambient_light,
     };
 
     // Configure USART2 Pins for connection to nRF51822
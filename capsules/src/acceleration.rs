@@ -0,0 +1,41 @@
+//! Provide capsule driver for three-axis accelerometers.
+//!
+//! The reading is passed to the callback as the signed acceleration along
+//! the x, y and z axes in milli-g. See `sensor_readings` for the system call
+//! interface.
+
+use kernel::{AppId, Callback, Container, Driver};
+use kernel::hil::sensors::{AccelerationSensor, AccelerationClient};
+use sensor_readings::{App, SensorReadings};
+
+pub struct AccelerationDriver<'a> {
+    sensor: &'a AccelerationSensor,
+    readings: SensorReadings,
+}
+
+impl<'a> AccelerationDriver<'a> {
+    pub fn new(sensor: &'a AccelerationSensor,
+               container: Container<App>)
+               -> AccelerationDriver<'a> {
+        AccelerationDriver {
+            sensor: sensor,
+            readings: SensorReadings::new(container),
+        }
+    }
+}
+
+impl<'a> AccelerationClient for AccelerationDriver<'a> {
+    fn acceleration_ready(&self, x: i32, y: i32, z: i32) {
+        self.readings.reading_done(x as usize, y as usize, z as usize);
+    }
+}
+
+impl<'a> Driver for AccelerationDriver<'a> {
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        self.readings.subscribe(subscribe_num, callback)
+    }
+
+    fn command(&self, command_num: usize, _: usize, appid: AppId) -> isize {
+        self.readings.command(command_num, appid, || self.sensor.read_acceleration())
+    }
+}
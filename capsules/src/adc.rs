@@ -1,19 +1,91 @@
+//! Provide capsule driver for the ADC.
+//!
+//! Apps either take single samples or fill a buffer with samples taken at a
+//! fixed rate. Requests of different apps are queued and served one at a time
+//! in process slot order; each app has at most one request outstanding.
+//! Requests go through a `virtual_adc::AdcUser`, so kernel capsules can share
+//! the ADC with apps: their single samples wait while an app fills a buffer.
+//!
+//! Allow:
+//!
+//!   * 0: buffer for buffered sampling, filled with 16-bit little-endian
+//!        samples
+//!
+//! Subscribe:
+//!
+//!   * 0: request done, called with
+//!     - `(0, channel, sample)` for a single sample,
+//!     - `(1, channel, count)` once the buffer holds `count` samples, or
+//!     - `(2, channel, 0)` if the ADC refused a request that had to wait
+//!
+//! Commands:
+//!
+//!   * 0: initialize the ADC
+//!   * 1: take a single sample on channel `data`
+//!   * 2: fill the allowed buffer. The channel is in the low byte of `data`
+//!        and the frequency in Hz in the rest.
+//!   * 3: stop filling the buffer, or drop the app's queued request
+
 use core::cell::Cell;
-use kernel::{AppId, Callback, Driver};
-use kernel::hil::adc::{Client, AdcSingle};
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::adc::{Client, ContinuousClient, AdcContinuous, AdcSingle};
+
+/// Kernel buffers for continuous sampling, filled alternately by the ADC.
+pub static mut BUFFER1: [u16; 128] = [0; 128];
+pub static mut BUFFER2: [u16; 128] = [0; 128];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Request {
+    Single(u8),
+    Continuous(u8, u32),
+}
 
-pub struct ADC<'a, A: AdcSingle + 'a> {
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+    /// A request that has not been issued to the ADC yet.
+    request: Option<Request>,
+}
+
+pub struct ADC<'a, A: AdcSingle + 'a, C: AdcContinuous + 'a> {
     adc: &'a A,
+    continuous: &'a C,
+    apps: Container<App>,
+    /// The app whose request the ADC is working on.
+    current: Cell<Option<AppId>>,
+    /// Whether the current request is for buffered sampling.
+    sampling: Cell<bool>,
     channel: Cell<u8>,
-    callback: Cell<Option<Callback>>,
+    // Kernel buffers the ADC alternates between while sampling continuously
+    buffer1: TakeCell<&'static mut [u16]>,
+    buffer2: TakeCell<&'static mut [u16]>,
+    // Samples requested, handed to the ADC so far, and copied to the app
+    requested: Cell<usize>,
+    queued: Cell<usize>,
+    received: Cell<usize>,
 }
 
-impl<'a, A: AdcSingle + 'a> ADC<'a, A> {
-    pub fn new(adc: &'a A) -> ADC<'a, A> {
+impl<'a, A: AdcSingle + 'a, C: AdcContinuous + 'a> ADC<'a, A, C> {
+    pub fn new(adc: &'a A,
+               continuous: &'a C,
+               buffer1: &'static mut [u16],
+               buffer2: &'static mut [u16],
+               container: Container<App>)
+               -> ADC<'a, A, C> {
         ADC {
             adc: adc,
+            continuous: continuous,
+            apps: container,
+            current: Cell::new(None),
+            sampling: Cell::new(false),
             channel: Cell::new(0),
-            callback: Cell::new(None),
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+            requested: Cell::new(0),
+            queued: Cell::new(0),
+            received: Cell::new(0),
         }
     }
 
@@ -21,27 +93,276 @@ impl<'a, A: AdcSingle + 'a> ADC<'a, A> {
         self.adc.initialize();
     }
 
-    fn sample(&self, channel: u8) {
-        self.channel.set(channel);
-        self.adc.sample(channel);
+    /// Queue a request for `appid` and start it if the ADC is free.
+    fn enqueue(&self, appid: AppId, request: Request) -> isize {
+        let ret = self.apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() || self.is_current(appid) {
+                    return -2;
+                }
+                if let Request::Continuous(_, frequency) = request {
+                    let count = app.buffer.as_ref().map_or(0, |slice| slice.len() / 2);
+                    if count == 0 || frequency == 0 {
+                        return -1;
+                    }
+                }
+                app.request = Some(request);
+                0
+            })
+            .unwrap_or(-3);
+        if ret == 0 && self.current.get().is_none() {
+            // Report a refusal of the request straight away
+            if !self.start(appid) {
+                return -1;
+            }
+        }
+        ret
+    }
+
+    /// Start the next queued request, if the ADC is free.
+    fn run_next(&self) {
+        if self.current.get().is_some() {
+            return;
+        }
+        let mut next = None;
+        for cntr in self.apps.iter() {
+            if next.is_some() {
+                break;
+            }
+            cntr.enter(|app, _| if app.request.is_some() {
+                next = Some(app.appid());
+            });
+        }
+        next.map(|appid| {
+            if !self.start(appid) {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback.map(|mut cb| cb.schedule(2, self.channel.get() as usize, 0));
+                });
+            }
+            self.run_next();
+        });
+    }
+
+    /// Issue the queued request of `appid` to the ADC. Returns false if the
+    /// ADC refused it.
+    fn start(&self, appid: AppId) -> bool {
+        self.apps
+            .enter(appid, |app, _| {
+                let request = match app.request.take() {
+                    Some(request) => request,
+                    None => return true,
+                };
+                match request {
+                    Request::Single(channel) => {
+                        self.channel.set(channel);
+                        if self.adc.sample(channel) {
+                            self.current.set(Some(appid));
+                            self.sampling.set(false);
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    Request::Continuous(channel, frequency) => {
+                        self.channel.set(channel);
+                        let count = app.buffer.as_ref().map_or(0, |slice| slice.len() / 2);
+                        if self.sample_continuous(channel, frequency, count) {
+                            self.current.set(Some(appid));
+                            self.sampling.set(true);
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                }
+            })
+            .unwrap_or(true)
+    }
+
+    fn is_current(&self, appid: AppId) -> bool {
+        self.current.get() == Some(appid)
+    }
+
+    /// Start filling a buffer of `count` samples from `channel` taken at
+    /// `frequency` Hz.
+    fn sample_continuous(&self, channel: u8, frequency: u32, count: usize) -> bool {
+        if count == 0 {
+            return false;
+        }
+        let buffer = match self.take_buffer() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+
+        let length = if count < buffer.len() {
+            count
+        } else {
+            buffer.len()
+        };
+        match self.continuous.sample_continuous(channel, frequency, buffer, length) {
+            Ok(()) => {
+                self.requested.set(count);
+                self.queued.set(length);
+                self.received.set(0);
+                self.queue_next();
+                true
+            }
+            Err(buffer) => {
+                self.return_buffer(buffer);
+                false
+            }
+        }
+    }
+
+    /// Stop the request of `appid`, whether it is being sampled or still
+    /// queued. Single samples in progress run to completion.
+    fn stop_sampling(&self, appid: AppId) -> isize {
+        if self.is_current(appid) && self.sampling.get() {
+            let (current, queued) = self.continuous.stop_sampling();
+            current.map(|buffer| self.return_buffer(buffer));
+            queued.map(|buffer| self.return_buffer(buffer));
+            self.current.set(None);
+            self.run_next();
+            0
+        } else {
+            self.apps
+                .enter(appid, |app, _| {
+                    app.request = None;
+                    0
+                })
+                .unwrap_or(-3)
+        }
+    }
+
+    /// Hand the ADC another buffer if more samples are needed than it has
+    /// been given so far.
+    fn queue_next(&self) {
+        let remaining = self.requested.get() - self.queued.get();
+        if remaining == 0 {
+            return;
+        }
+        self.take_buffer().map(|buffer| {
+            let length = if remaining < buffer.len() {
+                remaining
+            } else {
+                buffer.len()
+            };
+            match self.continuous.provide_buffer(buffer, length) {
+                Ok(()) => self.queued.set(self.queued.get() + length),
+                Err(buffer) => self.return_buffer(buffer),
+            }
+        });
+    }
+
+    fn take_buffer(&self) -> Option<&'static mut [u16]> {
+        self.buffer1.take().or_else(|| self.buffer2.take())
+    }
+
+    fn return_buffer(&self, buffer: &'static mut [u16]) {
+        if self.buffer1.is_none() {
+            self.buffer1.replace(buffer);
+        } else {
+            self.buffer2.replace(buffer);
+        }
     }
 }
 
-impl<'a, A: AdcSingle + 'a> Client for ADC<'a, A> {
+impl<'a, A: AdcSingle + 'a, C: AdcContinuous + 'a> Client for ADC<'a, A, C> {
     fn sample_done(&self, sample: u16) {
-        self.callback.get().map(|mut cb| {
-            cb.schedule(0, self.channel.get() as usize, sample as usize);
+        self.current.get().map(|appid| {
+            self.current.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(0, self.channel.get() as usize, sample as usize);
+                });
+            });
+        });
+        self.run_next();
+    }
+
+    fn sample_failed(&self) {
+        if self.sampling.get() {
+            // Buffered sampling had to wait for the ADC and could not start,
+            // so take the kernel buffers back
+            let (current, queued) = self.continuous.stop_sampling();
+            current.map(|buffer| self.return_buffer(buffer));
+            queued.map(|buffer| self.return_buffer(buffer));
+        }
+        self.current.get().map(|appid| {
+            self.current.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| cb.schedule(2, self.channel.get() as usize, 0));
+            });
+        });
+        self.run_next();
+    }
+}
+
+impl<'a, A: AdcSingle + 'a, C: AdcContinuous + 'a> ContinuousClient for ADC<'a, A, C> {
+    fn buffer_ready(&self, buffer: &'static mut [u16], length: usize) {
+        let received = self.received.get();
+        self.current.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.buffer.as_mut().map(|slice| {
+                    let data = slice.as_mut();
+                    for (i, sample) in buffer[..length].iter().enumerate() {
+                        let offset = (received + i) * 2;
+                        if offset + 1 < data.len() {
+                            data[offset] = *sample as u8;
+                            data[offset + 1] = (*sample >> 8) as u8;
+                        }
+                    }
+                });
+            });
         });
+        self.received.set(received + length);
+        self.return_buffer(buffer);
+
+        if self.received.get() >= self.requested.get() {
+            // The ADC stops by itself once the last queued buffer fills
+            self.current.get().map(|appid| {
+                self.current.set(None);
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback.map(|mut cb| {
+                        cb.schedule(1, self.channel.get() as usize, self.received.get());
+                    });
+                });
+            });
+            self.run_next();
+        } else {
+            self.queue_next();
+        }
     }
 }
 
-impl<'a, A: AdcSingle + 'a> Driver for ADC<'a, A> {
+impl<'a, A: AdcSingle + 'a, C: AdcContinuous + 'a> Driver for ADC<'a, A, C> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        match allow_num {
+            // Buffer for continuous samples
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.buffer = Some(slice);
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+
+            // default
+            _ => -1,
+        }
+    }
+
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
             // subscribe to ADC sample done
             0 => {
-                self.callback.set(Some(callback));
-                0
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-1)
             }
 
             // default
@@ -49,7 +370,7 @@ impl<'a, A: AdcSingle + 'a> Driver for ADC<'a, A> {
         }
     }
 
-    fn command(&self, command_num: usize, data: usize, _: AppId) -> isize {
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
         match command_num {
             // Initialize ADC
             0 => {
@@ -57,10 +378,14 @@ impl<'a, A: AdcSingle + 'a> Driver for ADC<'a, A> {
                 0
             }
             // Sample on channel
-            1 => {
-                self.sample(data as u8);
-                0
+            1 => self.enqueue(appid, Request::Single(data as u8)),
+            // Fill the allowed buffer with samples
+            2 => {
+                self.enqueue(appid,
+                             Request::Continuous(data as u8, (data >> 8) as u32))
             }
+            // Stop sampling into the allowed buffer
+            3 => self.stop_sampling(appid),
 
             // default
             _ => -1,
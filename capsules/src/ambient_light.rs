@@ -0,0 +1,38 @@
+//! Provide capsule driver for ambient light sensors.
+//!
+//! The reading is passed to the callback as the illuminance in lux. See
+//! `sensor_readings` for the system call interface.
+
+use kernel::{AppId, Callback, Container, Driver};
+use kernel::hil::sensors::{LightSensor, LightClient};
+use sensor_readings::{App, SensorReadings};
+
+pub struct AmbientLightDriver<'a> {
+    sensor: &'a LightSensor,
+    readings: SensorReadings,
+}
+
+impl<'a> AmbientLightDriver<'a> {
+    pub fn new(sensor: &'a LightSensor, container: Container<App>) -> AmbientLightDriver<'a> {
+        AmbientLightDriver {
+            sensor: sensor,
+            readings: SensorReadings::new(container),
+        }
+    }
+}
+
+impl<'a> LightClient for AmbientLightDriver<'a> {
+    fn light_ready(&self, lux: u32) {
+        self.readings.reading_done(lux as usize, 0, 0);
+    }
+}
+
+impl<'a> Driver for AmbientLightDriver<'a> {
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        self.readings.subscribe(subscribe_num, callback)
+    }
+
+    fn command(&self, command_num: usize, _: usize, appid: AppId) -> isize {
+        self.readings.command(command_num, appid, || self.sensor.read_light())
+    }
+}
@@ -0,0 +1,606 @@
+//! Provide capsule driver for per-app nonvolatile storage.
+//!
+//! The board reserves a range of flash pages for app storage. Each app gets a
+//! private region within that range, sized by the `storage_size` field of its
+//! header (rounded up to whole pages). Page numbers passed by apps are
+//! relative to their own region, and the capsule rejects any access outside
+//! of it, so apps cannot touch each other's data.
+//!
+//! Regions belong to package names, not process slots: the first page of the
+//! range holds an allocation table recording the name, first page and length
+//! of every region, so an app finds its data again after reboots and after
+//! other apps are installed or removed. A region is allocated the first time
+//! an app accesses its storage, and all of its pages are erased before the
+//! allocation is recorded, so an app never sees data left behind by another
+//! one. Regions of apps that are no longer installed are reclaimed when space
+//! runs out. An app whose requested size changed gets a new, erased region.
+//! Apps without a package name, or with one longer than `MAX_NAME_LEN`
+//! bytes, cannot use storage.
+//!
+//! If the allocation table is lost, for example because power failed while
+//! it was written, all regions are allocated afresh and their data is lost.
+//!
+//! Apps share a single kernel page buffer, so operations are serialized.
+//! Operations requested while another is in flight are queued (one per app)
+//! and started in process slot order.
+//!
+//! Allow:
+//!
+//!   * 0: buffer to read into and write from, at least one page long
+//!
+//! Subscribe:
+//!
+//!   * 0: operation complete, called with the command number, 0 on success or
+//!        -1 on failure, and the page number
+//!
+//! Commands:
+//!
+//!   * 0: get the page size in bytes
+//!   * 1: get the number of pages in the app's region
+//!   * 2: read page `data`
+//!   * 3: write page `data`
+//!   * 4: erase page `data`
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::{self, Flash};
+use kernel::process;
+use page_image::{self, read_u32, write_u32};
+
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Maximum number of regions in the allocation table.
+const MAX_REGIONS: usize = 16;
+
+const MAGIC: u32 = 0x41505331; // "APS1"
+const HEADER_LEN: usize = 12;
+const ENTRY_LEN: usize = 6 + MAX_NAME_LEN;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+    Erase,
+}
+
+impl Operation {
+    fn command_num(&self) -> usize {
+        match *self {
+            Operation::Read => 2,
+            Operation::Write => 3,
+            Operation::Erase => 4,
+        }
+    }
+}
+
+/// A region of the allocation table, with its first page relative to the
+/// start of the range.
+#[derive(Copy, Clone)]
+struct Region {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    start: usize,
+    len: usize,
+}
+
+impl Region {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    fn overlaps(&self, start: usize, len: usize) -> bool {
+        start < self.start + self.len && self.start < start + len
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Uninitialized,
+    /// Reading the allocation table.
+    Loading,
+    Idle,
+    /// Erasing page `page` of a new region, which ends before page `end`.
+    Clearing { page: usize, end: usize },
+    /// Writing the allocation table.
+    Saving,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+    /// Operation waiting for the flash, with the page number relative to the
+    /// app's region.
+    pending: Option<(Operation, usize)>,
+}
+
+pub struct AppStorage<'a, F: Flash + 'static> {
+    driver: &'a F,
+    first_page: usize,
+    num_pages: usize,
+    page_size: usize,
+    buffer: TakeCell<&'static mut F::Page>,
+    /// The app and operation currently using the flash.
+    current: Cell<Option<(AppId, Operation, usize)>>,
+    apps: Container<App>,
+    state: Cell<State>,
+    regions: TakeCell<[Option<Region>; MAX_REGIONS]>,
+    /// The app whose region is being allocated.
+    allocating: Cell<Option<AppId>>,
+}
+
+/// Whether an app with package name `name` can use storage.
+fn is_valid_name(name: &[u8]) -> bool {
+    name.len() > 0 && name.len() <= MAX_NAME_LEN
+}
+
+impl<'a, F: Flash> AppStorage<'a, F> {
+    /// Creates the capsule, giving apps the `num_pages` flash pages starting
+    /// at `first_page`. The first of these holds the allocation table.
+    pub fn new(driver: &'a F,
+               first_page: usize,
+               num_pages: usize,
+               buffer: &'static mut F::Page,
+               container: Container<App>)
+               -> AppStorage<'a, F> {
+        AppStorage {
+            driver: driver,
+            first_page: first_page,
+            num_pages: num_pages,
+            page_size: buffer.as_ref().len(),
+            buffer: TakeCell::new(buffer),
+            current: Cell::new(None),
+            apps: container,
+            state: Cell::new(State::Uninitialized),
+            regions: TakeCell::new([None; MAX_REGIONS]),
+            allocating: Cell::new(None),
+        }
+    }
+
+    /// Loads the allocation table from flash. Must be called once by the
+    /// board; operations are queued until it is loaded.
+    pub fn initialize(&self) {
+        self.regions.map(|regions| for region in regions.iter_mut() {
+            *region = None;
+        });
+        self.state.set(State::Loading);
+        self.buffer.take().map(|buf| if let Err((_, buf)) =
+                                       self.driver.read_page(self.first_page, buf) {
+            self.buffer.replace(buf);
+            self.loaded(None);
+            self.run_next();
+        });
+    }
+
+    /// Fills the in-memory allocation table from the table page `data`, if
+    /// it is valid.
+    fn loaded(&self, data: Option<&[u8]>) {
+        data.map(|data| {
+            let used = (data[4] as usize) | (data[5] as usize) << 8;
+            if read_u32(data, 0) != MAGIC || HEADER_LEN + used > data.len() ||
+               read_u32(data, 8) != page_image::checksum(data, HEADER_LEN, used) {
+                return;
+            }
+            self.regions.map(|regions| for (i, region) in regions.iter_mut().enumerate() {
+                let offset = HEADER_LEN + i * ENTRY_LEN;
+                if offset + ENTRY_LEN > HEADER_LEN + used {
+                    break;
+                }
+                let name_len = data[offset] as usize;
+                let start = (data[offset + 2] as usize) | (data[offset + 3] as usize) << 8;
+                let len = (data[offset + 4] as usize) | (data[offset + 5] as usize) << 8;
+                if name_len > MAX_NAME_LEN || start == 0 || start + len > self.num_pages {
+                    continue;
+                }
+                let mut name = [0; MAX_NAME_LEN];
+                name[..name_len].copy_from_slice(&data[offset + 6..offset + 6 + name_len]);
+                *region = Some(Region {
+                    name: name,
+                    name_len: name_len,
+                    start: start,
+                    len: len,
+                });
+            });
+        });
+        self.state.set(State::Idle);
+    }
+
+    /// Number of pages `appid` asked for in its header.
+    fn requested_pages(&self, appid: AppId) -> usize {
+        let size = process::storage_size(appid.idx()).unwrap_or(0);
+        cmp::min((size + self.page_size - 1) / self.page_size, self.num_pages - 1)
+    }
+
+    /// The region allocated to the app named `name`, if any.
+    fn region(&self, name: &[u8]) -> Option<Region> {
+        self.regions
+            .map(|regions| regions.iter().filter_map(|r| *r).find(|r| r.name() == name))
+            .unwrap_or(None)
+    }
+
+    /// Picks `len` free pages for the app named `name`, dropping its previous
+    /// region and, if needed, the regions of apps that are no longer
+    /// installed. Returns the new region, which is not yet in the table.
+    fn allocate(&self, name: &[u8], len: usize) -> Option<Region> {
+        let installed = |name: &[u8]| {
+            (0..process::num_slots()).any(|slot| process::slot_package_name(slot) == Some(name))
+        };
+        self.regions
+            .map(|regions| {
+                for region in regions.iter_mut() {
+                    if region.map_or(false, |r| r.name() == name) {
+                        *region = None;
+                    }
+                }
+                let mut found = self.find_free(regions, len);
+                if found.is_none() {
+                    for region in regions.iter_mut() {
+                        if region.map_or(false, |r| !installed(r.name())) {
+                            *region = None;
+                        }
+                    }
+                    found = self.find_free(regions, len);
+                }
+                found.map(|start| {
+                    let mut region = Region {
+                        name: [0; MAX_NAME_LEN],
+                        name_len: name.len(),
+                        start: start,
+                        len: len,
+                    };
+                    region.name[..name.len()].copy_from_slice(name);
+                    region
+                })
+            })
+            .unwrap_or(None)
+    }
+
+    /// Returns the first page of the lowest run of `len` pages that no region
+    /// uses, if there is one and a free table slot to record it.
+    fn find_free(&self, regions: &[Option<Region>], len: usize) -> Option<usize> {
+        if !regions.iter().any(|r| r.is_none()) {
+            return None;
+        }
+        let fits = |start: usize| {
+            start + len <= self.num_pages &&
+            !regions.iter().any(|r| r.map_or(false, |r| r.overlaps(start, len)))
+        };
+        // Page 0 holds the table, so candidates are page 1 and the pages
+        // right after existing regions
+        let after_regions = regions.iter().filter_map(|r| r.map(|r| r.start + r.len));
+        (1..2).chain(after_regions).filter(|start| fits(*start)).min()
+    }
+
+    /// Starts allocating a region of `len` pages for `appid`, erasing its
+    /// pages before it is recorded. Fails the app's operation if there is no
+    /// room.
+    fn start_allocation(&self, appid: AppId, name: &[u8], len: usize) {
+        match self.allocate(name, len) {
+            None => self.fail(appid),
+            Some(region) => {
+                self.regions.map(|regions| {
+                    regions.iter_mut().find(|r| r.is_none()).map(|slot| *slot = Some(region));
+                });
+                self.allocating.set(Some(appid));
+                self.clear(region.start, region.start + region.len);
+            }
+        }
+    }
+
+    /// Erases page `page` of the region being allocated, or records the
+    /// region once all pages up to `end` are erased.
+    fn clear(&self, page: usize, end: usize) {
+        if page >= end {
+            self.save();
+            return;
+        }
+        self.state.set(State::Clearing {
+            page: page,
+            end: end,
+        });
+        if self.driver.erase_page(self.first_page + page).is_err() {
+            self.allocation_failed();
+        }
+    }
+
+    /// Writes the allocation table to flash.
+    fn save(&self) {
+        self.state.set(State::Saving);
+        let saved = self.buffer.take().map_or(false, |buf| {
+            {
+                let page = buf.as_mut();
+                for b in page.iter_mut() {
+                    *b = 0xff;
+                }
+                let mut used = 0;
+                self.regions.map(|regions| for region in regions.iter().filter_map(|r| *r) {
+                    let offset = HEADER_LEN + used;
+                    if offset + ENTRY_LEN > page.len() {
+                        break;
+                    }
+                    page[offset] = region.name_len as u8;
+                    page[offset + 1] = 0;
+                    page[offset + 2] = region.start as u8;
+                    page[offset + 3] = (region.start >> 8) as u8;
+                    page[offset + 4] = region.len as u8;
+                    page[offset + 5] = (region.len >> 8) as u8;
+                    page[offset + 6..offset + 6 + region.name_len].copy_from_slice(region.name());
+                    used += ENTRY_LEN;
+                });
+                write_u32(page, 0, MAGIC);
+                page[4] = used as u8;
+                page[5] = (used >> 8) as u8;
+                page[6] = 0;
+                page[7] = 0;
+                let checksum = page_image::checksum(page, HEADER_LEN, used);
+                write_u32(page, 8, checksum);
+            }
+            match self.driver.write_page(self.first_page, buf) {
+                Ok(()) => true,
+                Err((_, buf)) => {
+                    self.buffer.replace(buf);
+                    false
+                }
+            }
+        });
+        if !saved {
+            self.allocation_failed();
+        }
+    }
+
+    /// Fails the operation of the app whose region could not be allocated
+    /// and reloads the table, since it may no longer match the flash.
+    fn allocation_failed(&self) {
+        self.allocating.get().map(|appid| {
+            self.allocating.set(None);
+            self.fail(appid);
+        });
+        self.initialize();
+    }
+
+    /// Fails the queued operation of `appid`.
+    fn fail(&self, appid: AppId) {
+        let _ = self.apps.enter(appid, |app, _| {
+            app.pending.take().map(|(op, rel_page)| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(op.command_num(), -1isize as usize, rel_page)
+                });
+            });
+        });
+    }
+
+    /// Starts `op` on absolute page `page` for `app`. Returns whether the
+    /// flash accepted the operation.
+    fn start(&self, app: &mut App, op: Operation, page: usize) -> bool {
+        match op {
+            Operation::Read => {
+                self.buffer.take().map_or(false, |buf| match self.driver.read_page(page, buf) {
+                    Ok(()) => true,
+                    Err((_, buf)) => {
+                        self.buffer.replace(buf);
+                        false
+                    }
+                })
+            }
+            Operation::Write => {
+                self.buffer.take().map_or(false, |buf| {
+                    {
+                        let kbuf = buf.as_mut();
+                        for b in kbuf.iter_mut() {
+                            *b = 0xff;
+                        }
+                        app.buffer.as_ref().map(|src| for (dst, b) in kbuf.iter_mut()
+                            .zip(src.iter()) {
+                            *dst = *b;
+                        });
+                    }
+                    match self.driver.write_page(page, buf) {
+                        Ok(()) => true,
+                        Err((_, buf)) => {
+                            self.buffer.replace(buf);
+                            false
+                        }
+                    }
+                })
+            }
+            Operation::Erase => self.driver.erase_page(page).is_ok(),
+        }
+    }
+
+    /// Starts the next queued operation, allocating the app's region first
+    /// if needed, if the flash is idle.
+    fn run_next(&self) {
+        for app in self.apps.iter() {
+            if self.state.get() != State::Idle || self.current.get().is_some() {
+                return;
+            }
+            let allocation = app.enter(|app, _| {
+                let (op, rel_page) = match app.pending {
+                    Some(pending) => pending,
+                    None => return None,
+                };
+                let appid = app.appid();
+                let name = process::package_name(appid).unwrap_or(&[]);
+                let len = self.requested_pages(appid);
+                let region = self.region(name).and_then(|r| if r.len == len {
+                    Some(r)
+                } else {
+                    None
+                });
+                let started = match region {
+                    Some(region) if rel_page < region.len => {
+                        let page = self.first_page + region.start + rel_page;
+                        if self.start(app, op, page) {
+                            self.current.set(Some((appid, op, rel_page)));
+                        }
+                        self.current.get().is_some()
+                    }
+                    // Leave the operation queued until the region is ready
+                    None if is_valid_name(name) && rel_page < len => {
+                        return Some((appid, name, len));
+                    }
+                    _ => false,
+                };
+                app.pending = None;
+                if !started {
+                    app.callback.map(|mut cb| {
+                        cb.schedule(op.command_num(), -1isize as usize, rel_page)
+                    });
+                }
+                None
+            });
+            allocation.map(|(appid, name, len)| self.start_allocation(appid, name, len));
+        }
+    }
+
+    /// Signals the app that the current operation finished, copying `data`
+    /// into the app's buffer if the operation was a read.
+    fn complete(&self, error: flash::Error, data: Option<&F::Page>) {
+        self.current.get().map(|(appid, op, rel_page)| {
+            self.current.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                let result = if error == flash::Error::CommandComplete {
+                    if op == Operation::Read {
+                        data.map(|data| {
+                            app.buffer.as_mut().map(|dst| for (d, b) in dst.as_mut()
+                                .iter_mut()
+                                .zip(data.as_ref().iter()) {
+                                *d = *b;
+                            });
+                        });
+                    }
+                    0
+                } else {
+                    -1isize as usize
+                };
+                app.callback.map(|mut cb| cb.schedule(op.command_num(), result, rel_page));
+            });
+        });
+    }
+}
+
+impl<'a, F: Flash> Driver for AppStorage<'a, F> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        match allow_num {
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.buffer = Some(slice);
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+            _ => -1,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+            _ => -1,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        let num_pages = if process::package_name(appid).map_or(false, is_valid_name) {
+            self.requested_pages(appid)
+        } else {
+            0
+        };
+        let op = match command_num {
+            // page size
+            0 => return self.page_size as isize,
+
+            // number of pages in the app's region
+            1 => return num_pages as isize,
+
+            2 => Operation::Read,
+            3 => Operation::Write,
+            4 => Operation::Erase,
+            _ => return -1,
+        };
+
+        if data >= num_pages {
+            return -1;
+        }
+
+        let in_flight = self.current.get().map_or(false, |(id, _, _)| id == appid) ||
+                        self.allocating.get() == Some(appid);
+        let ret = self.apps
+            .enter(appid, |app, _| {
+                let has_buffer = app.buffer
+                    .as_ref()
+                    .map_or(false, |buf| buf.len() >= self.page_size);
+                if app.pending.is_some() || in_flight {
+                    -2
+                } else if op != Operation::Erase && !has_buffer {
+                    -1
+                } else {
+                    app.pending = Some((op, data));
+                    0
+                }
+            })
+            .unwrap_or(-3);
+        if ret == 0 {
+            self.run_next();
+        }
+        ret
+    }
+}
+
+impl<'a, F: Flash> flash::Client<F> for AppStorage<'a, F> {
+    fn read_complete(&self, read_buffer: &'static mut F::Page, error: flash::Error) {
+        if self.state.get() == State::Loading {
+            if error == flash::Error::CommandComplete {
+                self.loaded(Some(read_buffer.as_ref()));
+            } else {
+                self.loaded(None);
+            }
+            self.buffer.replace(read_buffer);
+            self.run_next();
+            return;
+        }
+        self.complete(error, Some(&*read_buffer));
+        self.buffer.replace(read_buffer);
+        self.run_next();
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut F::Page, error: flash::Error) {
+        self.buffer.replace(write_buffer);
+        if self.state.get() == State::Saving {
+            if error != flash::Error::CommandComplete {
+                self.allocation_failed();
+                return;
+            }
+            // The app's queued operation starts now that its region is ready
+            self.allocating.set(None);
+            self.state.set(State::Idle);
+            self.run_next();
+            return;
+        }
+        self.complete(error, None);
+        self.run_next();
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        if let State::Clearing { page, end } = self.state.get() {
+            if error != flash::Error::CommandComplete {
+                self.allocation_failed();
+            } else {
+                self.clear(page + 1, end);
+            }
+            return;
+        }
+        self.complete(error, None);
+        self.run_next();
+    }
+}
@@ -0,0 +1,159 @@
+//! Provide capsule driver for supervising applications with heartbeats.
+//!
+//! An app registers itself as supervised with a timeout and must then send a
+//! heartbeat at least once per timeout. If a supervised app misses its
+//! deadline, the capsule either restarts the app or has the kernel stop
+//! servicing the hardware watchdog so that it resets the board, depending on
+//! the `FailurePolicy` chosen by the board.
+//!
+//! The capsule also keeps its alarm armed at least every `service_interval`
+//! milliseconds, so the kernel wakes up in time to service the hardware
+//! watchdog (see `kernel::watchdog`) even if no app is supervised.
+//!
+//! Commands:
+//!
+//!   * 0: start supervision with a timeout of `data` milliseconds
+//!   * 1: heartbeat
+//!   * 2: stop supervision
+
+use core::cmp;
+use kernel::{AppId, Container, Driver};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::process;
+use kernel::watchdog;
+
+/// What to do when a supervised app misses its deadline.
+#[derive(Copy, Clone, PartialEq)]
+pub enum FailurePolicy {
+    RestartApp,
+    ResetBoard,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Timeout in alarm tics, 0 if the app is not supervised.
+    timeout: u32,
+    last_heartbeat: u32,
+}
+
+pub struct AppWatchdog<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    policy: FailurePolicy,
+    service_interval: u32,
+    apps: Container<App>,
+}
+
+impl<'a, A: Alarm> AppWatchdog<'a, A> {
+    pub fn new(alarm: &'a A,
+               policy: FailurePolicy,
+               service_interval: u32,
+               container: Container<App>)
+               -> AppWatchdog<'a, A> {
+        AppWatchdog {
+            alarm: alarm,
+            policy: policy,
+            service_interval: Self::ms_to_tics(service_interval),
+            apps: container,
+        }
+    }
+
+    /// Arms the supervision alarm. Must be called once by the board after
+    /// setting the alarm's client.
+    pub fn start(&self) {
+        self.reset_alarm();
+    }
+
+    /// Converts milliseconds to alarm tics, saturating at the longest
+    /// interval the alarm can represent.
+    fn ms_to_tics(ms: u32) -> u32 {
+        let tics = ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        cmp::min(tics, u32::max_value() as u64) as u32
+    }
+
+    /// Sets the alarm to the nearest heartbeat deadline, or to the next
+    /// service interval if that is sooner.
+    fn reset_alarm(&self) {
+        let now = self.alarm.now();
+        let mut next_dist = self.service_interval;
+        for app in self.apps.iter() {
+            next_dist = app.enter(|app, _| if app.timeout > 0 {
+                let deadline = app.last_heartbeat.wrapping_add(app.timeout);
+                cmp::min(next_dist, deadline.wrapping_sub(now))
+            } else {
+                next_dist
+            });
+        }
+        self.alarm.set_alarm(now.wrapping_add(next_dist));
+    }
+
+    fn handle_failure(&self, appid: AppId) {
+        if self.policy == FailurePolicy::ResetBoard && watchdog::reset() {
+            return;
+        }
+        unsafe {
+            process::restart(appid);
+        }
+    }
+}
+
+impl<'a, A: Alarm> Driver for AppWatchdog<'a, A> {
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        let now = self.alarm.now();
+        let (ret, reset) = self.apps
+            .enter(appid, |app, _| match command_num {
+                // start supervision
+                0 => {
+                    if data == 0 {
+                        (-2, false)
+                    } else {
+                        app.timeout = Self::ms_to_tics(data as u32);
+                        app.last_heartbeat = now;
+                        (0, true)
+                    }
+                }
+
+                // heartbeat
+                1 => {
+                    if app.timeout == 0 {
+                        (-2, false)
+                    } else {
+                        app.last_heartbeat = now;
+                        (0, true)
+                    }
+                }
+
+                // stop supervision
+                2 => {
+                    app.timeout = 0;
+                    (0, true)
+                }
+
+                _ => (-1, false),
+            })
+            .unwrap_or((-3, false));
+        if reset {
+            self.reset_alarm();
+        }
+        ret
+    }
+}
+
+impl<'a, A: Alarm> time::Client for AppWatchdog<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for app in self.apps.iter() {
+            let failed = app.enter(|app, _| if app.timeout > 0 &&
+                                               now.wrapping_sub(app.last_heartbeat) >=
+                                               app.timeout {
+                app.timeout = 0;
+                Some(app.appid())
+            } else {
+                None
+            });
+            // Only act after leaving the app's container, since restarting
+            // the app reinitializes its memory.
+            failed.map(|appid| self.handle_failure(appid));
+        }
+        self.reset_alarm();
+    }
+}
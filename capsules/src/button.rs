@@ -1,36 +1,213 @@
 //! Provide capsule driver for controlling buttons on a board.  This allows for much more cross
 //! platform controlling of buttons without having to know which of the GPIO pins exposed across
 //! the syscall interface are buttons.
+//!
+//! Button edges are debounced: after an edge, a button must keep its new level for the board's
+//! debounce interval before the change is reported. Besides presses and releases, the capsule
+//! reports long presses (held for `LONG_PRESS_MS`) and double clicks (a press within
+//! `DOUBLE_CLICK_MS` of releasing a short press).
+//!
+//! Callbacks are called with the button number, the debounced pin level and the `Event`. Apps
+//! choose the buttons they hear about with commands 0 and 1, and the events with command 3. By
+//! default apps receive presses and releases.
 
+use core::cell::Cell;
 use kernel::{AppId, Container, Callback, Driver};
 use kernel::hil;
 use kernel::hil::gpio::{Client, InterruptMode};
+use kernel::hil::time::{self, Alarm, Frequency};
+use led::ActivationMode;
 
 pub type SubscribeMap = u32;
 
-pub struct Button<'a, G: hil::gpio::Pin + 'a> {
+/// How long a button must be held to report a long press.
+pub const LONG_PRESS_MS: u32 = 1000;
+
+/// How soon after releasing a short press a second press counts as a double click.
+pub const DOUBLE_CLICK_MS: u32 = 400;
+
+#[derive(Copy, Clone)]
+pub enum Event {
+    Press = 0,
+    Release = 1,
+    LongPress = 2,
+    DoubleClick = 3,
+}
+
+const DEFAULT_EVENTS: SubscribeMap = (1 << Event::Press as u32) | (1 << Event::Release as u32);
+const ALL_EVENTS: SubscribeMap = 0xf;
+
+pub struct App {
+    callback: Option<Callback>,
+    /// Buttons the app subscribed to.
+    pins: SubscribeMap,
+    /// Events the app subscribed to, one bit per `Event`.
+    events: SubscribeMap,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            pins: 0,
+            events: DEFAULT_EVENTS,
+        }
+    }
+}
+
+/// Debouncing and gesture state of a single button.
+pub struct ButtonState {
+    /// When the pin last changed level.
+    changed: Cell<u32>,
+    /// When the current press started.
+    pressed_at: Cell<u32>,
+    /// When the last short press was released.
+    released_at: Cell<u32>,
+    /// Debounced pin level.
+    level: Cell<bool>,
+    /// Whether the pin changed level and has not settled yet.
+    settling: Cell<bool>,
+    /// Whether the button is pressed and no long press was reported yet.
+    holding: Cell<bool>,
+    /// Whether the next press may complete a double click.
+    clicked: Cell<bool>,
+    /// Whether the current press completed a double click.
+    double: Cell<bool>,
+}
+
+impl ButtonState {
+    pub const fn new() -> ButtonState {
+        ButtonState {
+            changed: Cell::new(0),
+            pressed_at: Cell::new(0),
+            released_at: Cell::new(0),
+            level: Cell::new(false),
+            settling: Cell::new(false),
+            holding: Cell::new(false),
+            clicked: Cell::new(false),
+            double: Cell::new(false),
+        }
+    }
+}
+
+pub struct Button<'a, G: hil::gpio::Pin + 'a, A: Alarm + 'a> {
     pins: &'a [&'a G],
-    callback: Container<(Option<Callback>, SubscribeMap)>,
+    states: &'a [ButtonState],
+    alarm: &'a A,
+    /// Debounce interval, long press and double click times in alarm tics.
+    debounce: u32,
+    long_press: u32,
+    double_click: u32,
+    callback: Container<App>,
+    mode: ActivationMode,
 }
 
-impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Button<'a, G> {
+impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl, A: Alarm> Button<'a, G, A> {
+    /// `states` needs one entry per pin. Pins must be configured (e.g. pull-ups) before the
+    /// capsule is created, as their initial levels are read here.
     pub fn new(pins: &'a [&'a G],
-               container: Container<(Option<Callback>, SubscribeMap)>)
-               -> Button<'a, G> {
+               states: &'a [ButtonState],
+               alarm: &'a A,
+               mode: ActivationMode,
+               debounce_ms: u32,
+               container: Container<App>)
+               -> Button<'a, G, A> {
         // Make all pins output and off
         for (i, pin) in pins.iter().enumerate() {
             pin.make_input();
             pin.enable_interrupt(i, InterruptMode::EitherEdge);
+            states[i].level.set(pin.read());
         }
 
         Button {
             pins: pins,
+            states: states,
+            alarm: alarm,
+            debounce: Self::ms_to_tics(debounce_ms),
+            long_press: Self::ms_to_tics(LONG_PRESS_MS),
+            double_click: Self::ms_to_tics(DOUBLE_CLICK_MS),
             callback: container,
+            mode: mode,
+        }
+    }
+
+    fn ms_to_tics(ms: u32) -> u32 {
+        ms * <A::Frequency>::frequency() / 1000
+    }
+
+    fn is_pressed(&self, level: bool) -> bool {
+        match self.mode {
+            ActivationMode::ActiveHigh => level,
+            ActivationMode::ActiveLow => !level,
+        }
+    }
+
+    /// Reports a change of the debounced level of button `pin_num`, if the pin really changed.
+    fn settle(&self, pin_num: usize, now: u32) {
+        let state = &self.states[pin_num];
+        let level = self.pins[pin_num].read();
+        if level == state.level.get() {
+            // Bounced back to the old level
+            return;
+        }
+        state.level.set(level);
+
+        if self.is_pressed(level) {
+            state.holding.set(true);
+            state.pressed_at.set(now);
+            self.notify(pin_num, Event::Press);
+            let double = state.clicked.get() &&
+                         now.wrapping_sub(state.released_at.get()) <= self.double_click;
+            state.clicked.set(false);
+            state.double.set(double);
+            if double {
+                self.notify(pin_num, Event::DoubleClick);
+            }
+        } else {
+            // Only a short press that did not complete a double click may start one
+            state.clicked.set(state.holding.get() && !state.double.get());
+            state.released_at.set(now);
+            state.holding.set(false);
+            self.notify(pin_num, Event::Release);
+        }
+    }
+
+    fn notify(&self, pin_num: usize, event: Event) {
+        let level = self.states[pin_num].level.get();
+        self.callback.each(|cntr| {
+            cntr.callback.map(|mut callback| {
+                if cntr.pins & (1 << pin_num) != 0 && cntr.events & (1 << event as u32) != 0 {
+                    callback.schedule(pin_num, level as usize, event as usize);
+                }
+            });
+        });
+    }
+
+    /// Sets the alarm to the nearest pending debounce or long press deadline.
+    fn reset_alarm(&self) {
+        let now = self.alarm.now();
+        let mut next_dist = None;
+        for state in self.states.iter().take(self.pins.len()) {
+            if state.settling.get() {
+                let deadline = state.changed.get().wrapping_add(self.debounce);
+                next_dist = Some(Self::nearer(next_dist, deadline.wrapping_sub(now)));
+            }
+            if state.holding.get() {
+                let deadline = state.pressed_at.get().wrapping_add(self.long_press);
+                next_dist = Some(Self::nearer(next_dist, deadline.wrapping_sub(now)));
+            }
         }
+        next_dist.map(|dist| self.alarm.set_alarm(now.wrapping_add(dist)));
+    }
+
+    fn nearer(current: Option<u32>, dist: u32) -> u32 {
+        // A deadline that already passed wraps around to a huge distance
+        let dist = if dist > u32::max_value() / 2 { 0 } else { dist };
+        current.map_or(dist, |current| if dist < current { dist } else { current })
     }
 }
 
-impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
+impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl, A: Alarm> Driver for Button<'a, G, A> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
             // set callback for pin interrupts (no affect or reliance on individual pins being
@@ -38,7 +215,7 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
             0 => {
                 self.callback
                     .enter(callback.app_id(), |cntr, _| {
-                        cntr.0 = Some(callback);
+                        cntr.callback = Some(callback);
                         0
                     })
                     .unwrap_or(-2)
@@ -57,7 +234,7 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
                 if data < pins.len() {
                     self.callback
                         .enter(appid, |cntr, _| {
-                            cntr.1 |= 1 << data;
+                            cntr.pins |= 1 << data;
                             0
                         })
                         .unwrap_or(-3)
@@ -74,7 +251,7 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
                 } else {
                     self.callback
                         .enter(appid, |cntr, _| {
-                            cntr.1 &= !(1 << data);
+                            cntr.pins &= !(1 << data);
                             0
                         })
                         .unwrap_or(-3)
@@ -91,25 +268,54 @@ impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl> Driver for Button<'a, G> {
                 }
             }
 
+            // select events, one bit per `Event`
+            3 => {
+                if data as SubscribeMap & !ALL_EVENTS != 0 {
+                    -2
+                } else {
+                    self.callback
+                        .enter(appid, |cntr, _| {
+                            cntr.events = data as SubscribeMap;
+                            0
+                        })
+                        .unwrap_or(-3)
+                }
+            }
+
             // default
             _ => -1,
         }
     }
 }
 
-impl<'a, G: hil::gpio::Pin> Client for Button<'a, G> {
+impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl, A: Alarm> Client for Button<'a, G, A> {
     fn fired(&self, pin_num: usize) {
-        // read the value of the pin
-        let pins = self.pins.as_ref();
-        let pin_state = pins[pin_num].read();
+        let now = self.alarm.now();
+        if self.debounce == 0 {
+            self.settle(pin_num, now);
+        } else {
+            // Wait for the pin to settle before reporting the change
+            let state = &self.states[pin_num];
+            state.settling.set(true);
+            state.changed.set(now);
+        }
+        self.reset_alarm();
+    }
+}
 
-        // schedule callback with the pin number and value
-        self.callback.each(|cntr| {
-            cntr.0.map(|mut callback| {
-                if cntr.1 & (1 << pin_num) != 0 {
-                    callback.schedule(pin_num, pin_state as usize, 0);
-                }
-            });
-        });
+impl<'a, G: hil::gpio::Pin + hil::gpio::PinCtl, A: Alarm> time::Client for Button<'a, G, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for (i, state) in self.states.iter().take(self.pins.len()).enumerate() {
+            if state.settling.get() && now.wrapping_sub(state.changed.get()) >= self.debounce {
+                state.settling.set(false);
+                self.settle(i, now);
+            }
+            if state.holding.get() && now.wrapping_sub(state.pressed_at.get()) >= self.long_press {
+                state.holding.set(false);
+                self.notify(i, Event::LongPress);
+            }
+        }
+        self.reset_alarm();
     }
 }
@@ -1,43 +1,121 @@
+//! Provide capsule driver for the console.
+//!
+//! Apps write by allowing a buffer and subscribing a callback, and read by
+//! allowing a buffer and issuing a read command.
+//!
+//! Writes longer than the kernel's transmit buffer are sent in chunks, taking
+//! turns between the apps that are writing. Input goes to one reading app at a
+//! time: the first app (in process slot order) with an outstanding read
+//! receives every byte until its read completes. Bytes arriving while no app
+//! is reading are dropped.
+//!
+//! Allow:
+//!
+//!   * 0: read buffer
+//!   * 1: write buffer
+//!
+//! Subscribe:
+//!
+//!   * 0: read done, called with the number of bytes read
+//!   * 1: start writing the write buffer; called with the number of bytes
+//!        written when done
+//!
+//! Commands:
+//!
+//!   * 0: putc
+//!   * 1: read `data` bytes, or until the read buffer is full
+//!   * 2: read a line of at most `data` bytes. The line ends at '\r' or
+//!        '\n', which is not stored; backspace deletes the last byte.
+//!   * 3: echo received bytes back if `data` is nonzero
+//!   * 4: abort the outstanding read, calling back with the bytes read so far
+//!
+//! In framed mode, set with `set_framed`, each transmission is wrapped in a
+//! frame naming the app it belongs to, so that a host can separate the output
+//! of different apps (see `kernel::frame` and `tools/console_demux`). Echoed
+//! input is framed as output of the reading app.
+
+use core::cell::Cell;
+use core::cmp;
 use kernel::{AppId, AppSlice, Container, Callback, Shared, Driver};
+use kernel::frame;
+use kernel::process;
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::uart::{self, UART, Client};
 
 pub struct App {
     write_callback: Option<Callback>,
+    read_callback: Option<Callback>,
     read_buffer: Option<AppSlice<Shared, u8>>,
     write_buffer: Option<AppSlice<Shared, u8>>,
+    /// Bytes to write, written so far, and in the chunk being transmitted.
     write_len: usize,
+    write_idx: usize,
+    write_chunk: usize,
     pending_write: bool,
     read_idx: usize,
+    /// Bytes requested by the outstanding read, 0 if the app is not reading.
+    read_len: usize,
+    read_line: bool,
+    echo: bool,
+    /// Sequence number of the app's next frame.
+    seq: u16,
 }
 
 impl Default for App {
     fn default() -> App {
         App {
             write_callback: None,
+            read_callback: None,
             read_buffer: None,
             write_buffer: None,
             write_len: 0,
+            write_idx: 0,
+            write_chunk: 0,
             pending_write: false,
             read_idx: 0,
+            read_len: 0,
+            read_line: false,
+            echo: false,
+            seq: 0,
         }
     }
 }
 
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+pub static mut ECHO_BUF: [u8; 16] = [0; 16];
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
 
 pub struct Console<'a, U: UART + 'a> {
     uart: &'a U,
     apps: Container<App>,
     in_progress: TakeCell<AppId>,
     tx_buffer: TakeCell<&'static mut [u8]>,
+    rx_buffer: TakeCell<&'static mut [u8]>,
+    /// Bytes waiting to be echoed.
+    echo_buffer: TakeCell<&'static mut [u8]>,
+    echo_len: Cell<usize>,
+    /// The app receiving input.
+    reader: Cell<Option<AppId>>,
+    /// The app the bytes waiting to be echoed belong to.
+    echo_app: Cell<Option<AppId>>,
+    /// Whether the last byte received was a carriage return, so a following
+    /// line feed does not end another line.
+    last_cr: Cell<bool>,
+    framed: Cell<bool>,
     baud_rate: u32,
+    /// Process slot of the app that wrote the last chunk.
+    last_writer: Cell<usize>,
 }
 
 impl<'a, U: UART> Console<'a, U> {
     pub fn new(uart: &'a U,
                baud_rate: u32,
                tx_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8],
+               echo_buffer: &'static mut [u8],
                container: Container<App>)
                -> Console<'a, U> {
         Console {
@@ -45,7 +123,15 @@ impl<'a, U: UART> Console<'a, U> {
             apps: container,
             in_progress: TakeCell::empty(),
             tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            echo_buffer: TakeCell::new(echo_buffer),
+            echo_len: Cell::new(0),
+            reader: Cell::new(None),
+            echo_app: Cell::new(None),
+            last_cr: Cell::new(false),
+            framed: Cell::new(false),
             baud_rate: baud_rate,
+            last_writer: Cell::new(0),
         }
     }
 
@@ -57,6 +143,276 @@ impl<'a, U: UART> Console<'a, U> {
             hw_flow_control: false,
         });
     }
+
+    /// Sets whether output is sent in frames.
+    pub fn set_framed(&self, framed: bool) {
+        self.framed.set(framed);
+    }
+
+    /// How many bytes of `appid` fit in one transmission from a buffer of
+    /// `buffer_len` bytes.
+    fn capacity(&self, buffer_len: usize, appid: AppId) -> usize {
+        if self.framed.get() {
+            let name = process::package_name(appid).unwrap_or(&[]);
+            frame::payload_capacity(buffer_len, name)
+        } else {
+            buffer_len
+        }
+    }
+
+    /// Copies `data` of `appid` into `buffer`, framed in framed mode, and
+    /// returns the number of bytes to transmit. Data that does not fit is
+    /// dropped.
+    fn fill(&self, buffer: &mut [u8], appid: AppId, data: &[u8]) -> usize {
+        if !self.framed.get() {
+            let len = cmp::min(data.len(), buffer.len());
+            buffer[..len].copy_from_slice(&data[..len]);
+            return len;
+        }
+
+        let name = process::package_name(appid).unwrap_or(&[]);
+        let offset = frame::payload_offset(name);
+        let len = cmp::min(data.len(), frame::payload_capacity(buffer.len(), name));
+        buffer[offset..offset + len].copy_from_slice(&data[..len]);
+        self.apps
+            .enter(appid, |app, _| {
+                let seq = app.seq;
+                app.seq = seq.wrapping_add(1);
+                frame::encode(buffer, frame::APP_CHANNEL, Some(appid), seq, name, len)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Starts the next transmission if the UART is idle: echoed input
+    /// first, then pending app writes.
+    fn start_tx(&self) {
+        if self.in_progress.is_some() {
+            return;
+        }
+        let buffer = match self.tx_buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+
+        let echo_len = self.echo_len.get();
+        self.echo_len.set(0);
+        let len = match self.echo_app.get() {
+            Some(appid) if echo_len > 0 => {
+                self.echo_buffer.map_or(0, |echo| self.fill(buffer, appid, &echo[..echo_len]))
+            }
+            _ => 0,
+        };
+        if len > 0 {
+            self.uart.transmit(buffer, len);
+            return;
+        }
+
+        while let Some(appid) = self.next_writer() {
+            // Copy the chunk out first, as framing enters the app's grant
+            let mut data = [0; 64];
+            let capacity = cmp::min(self.capacity(buffer.len(), appid), data.len());
+            let count = self.apps
+                .enter(appid, |app, _| {
+                    let count = app.write_buffer.as_ref().map_or(0, |slice| {
+                        let count = cmp::min(app.write_len - app.write_idx, capacity);
+                        let start = app.write_idx;
+                        data[..count].copy_from_slice(&slice.as_ref()[start..start + count]);
+                        count
+                    });
+                    if count == 0 {
+                        self.complete_write(app);
+                    }
+                    app.write_chunk = count;
+                    count
+                })
+                .unwrap_or(0);
+            if count > 0 {
+                let len = self.fill(buffer, appid, &data[..count]);
+                if len == 0 {
+                    // The app went away while its chunk was being framed
+                    break;
+                }
+                self.in_progress.replace(appid);
+                self.uart.transmit(buffer, len);
+                return;
+            }
+        }
+        self.tx_buffer.replace(buffer);
+    }
+
+    /// Picks the next app with a pending write, in slot order starting after
+    /// the app that wrote the last chunk.
+    fn next_writer(&self) -> Option<AppId> {
+        let last = self.last_writer.get();
+        let mut first = None;
+        let mut next = None;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| if app.pending_write {
+                let appid = app.appid();
+                if first.is_none() {
+                    first = Some(appid);
+                }
+                if next.is_none() && appid.idx() > last {
+                    next = Some(appid);
+                }
+            });
+        }
+        next.or(first)
+    }
+
+    /// Finishes the write of `app`, calling it back with the bytes written.
+    fn complete_write(&self, app: &mut App) {
+        app.pending_write = false;
+        app.write_callback.map(|mut cb| {
+            cb.schedule(app.write_idx, 0, 0);
+        });
+    }
+
+    fn echo(&self, appid: AppId, bytes: &[u8]) {
+        if self.echo_len.get() == 0 {
+            self.echo_app.set(Some(appid));
+        }
+        self.echo_buffer.map(|echo| for b in bytes.iter() {
+            let len = self.echo_len.get();
+            if len >= echo.len() {
+                break;
+            }
+            echo[len] = *b;
+            self.echo_len.set(len + 1);
+        });
+        self.start_tx();
+    }
+
+    /// Starts a read of up to `len` bytes for `appid`.
+    fn start_read(&self, appid: AppId, len: usize, line: bool) -> isize {
+        let ret = self.apps
+            .enter(appid, |app, _| {
+                if app.read_len > 0 {
+                    return -2;
+                }
+                match app.read_buffer {
+                    Some(ref slice) if len > 0 && slice.len() > 0 => {
+                        app.read_len = if len < slice.len() { len } else { slice.len() };
+                        app.read_idx = 0;
+                        app.read_line = line;
+                        0
+                    }
+                    _ => -1,
+                }
+            })
+            .unwrap_or(-3);
+        if ret == 0 {
+            self.start_rx();
+        }
+        ret
+    }
+
+    /// Finishes the read of `app`, calling it back with the bytes read.
+    fn complete_read(&self, app: &mut App) {
+        let count = app.read_idx;
+        app.read_len = 0;
+        app.read_idx = 0;
+        app.read_callback.map(|mut cb| {
+            cb.schedule(count, 0, 0);
+        });
+    }
+
+    /// Picks the app to receive input if none is receiving, and starts
+    /// receiving if any app is.
+    fn start_rx(&self) {
+        if self.reader.get().is_none() {
+            let mut next = None;
+            for cntr in self.apps.iter() {
+                if next.is_some() {
+                    break;
+                }
+                cntr.enter(|app, _| if app.read_len > 0 {
+                    next = Some(app.appid());
+                });
+            }
+            self.reader.set(next);
+        }
+        if self.reader.get().is_some() {
+            self.rx_buffer.take().map(|buffer| self.uart.receive(buffer, 1));
+        }
+    }
+
+    /// Hands a received byte to the reading app.
+    fn received(&self, byte: u8) {
+        let last_cr = self.last_cr.get();
+        self.last_cr.set(byte == b'\r');
+
+        let appid = match self.reader.get() {
+            Some(appid) => appid,
+            None => return,
+        };
+        let mut echo = [0; 3];
+        let echo_len = self.apps
+            .enter(appid, |app, _| {
+                if app.read_len == 0 {
+                    return 0;
+                }
+                let mut echo_len = 0;
+                let mut done = false;
+                if app.read_line && (byte == b'\r' || byte == b'\n') {
+                    if byte == b'\n' && last_cr {
+                        // Second half of a CRLF line ending
+                        return 0;
+                    }
+                    echo[..2].copy_from_slice(b"\r\n");
+                    echo_len = 2;
+                    done = true;
+                } else if app.read_line && (byte == BACKSPACE || byte == DELETE) {
+                    if app.read_idx > 0 {
+                        app.read_idx -= 1;
+                        echo.copy_from_slice(b"\x08 \x08");
+                        echo_len = 3;
+                    }
+                } else {
+                    let idx = app.read_idx;
+                    app.read_buffer.as_mut().map(|slice| slice.as_mut()[idx] = byte);
+                    app.read_idx += 1;
+                    echo[0] = byte;
+                    echo_len = 1;
+                    done = app.read_idx >= app.read_len;
+                }
+
+                if done {
+                    self.complete_read(app);
+                    self.reader.set(None);
+                }
+                if app.echo { echo_len } else { 0 }
+            })
+            .unwrap_or_else(|_| {
+                // The reading app is gone
+                self.reader.set(None);
+                0
+            });
+        if echo_len > 0 {
+            self.echo(appid, &echo[..echo_len]);
+        }
+    }
+
+    fn abort_read(&self, appid: AppId) -> isize {
+        let ret = self.apps
+            .enter(appid, |app, _| if app.read_len == 0 {
+                -2
+            } else {
+                self.complete_read(app);
+                0
+            })
+            .unwrap_or(-3);
+        if ret == 0 && self.is_reader(appid) {
+            // Hand input to the next reading app
+            self.reader.set(None);
+            self.start_rx();
+        }
+        ret
+    }
+
+    fn is_reader(&self, appid: AppId) -> bool {
+        self.reader.get() == Some(appid)
+    }
 }
 
 impl<'a, U: UART> Driver for Console<'a, U> {
@@ -64,7 +420,10 @@ impl<'a, U: UART> Driver for Console<'a, U> {
         match allow_num {
             0 => {
                 self.apps
-                    .enter(appid, |app, _| {
+                    .enter(appid, |app, _| if app.read_len > 0 {
+                        // Keep the buffer of an outstanding read
+                        -2
+                    } else {
                         app.read_buffer = Some(slice);
                         app.read_idx = 0;
                         0
@@ -73,7 +432,10 @@ impl<'a, U: UART> Driver for Console<'a, U> {
             }
             1 => {
                 self.apps
-                    .enter(appid, |app, _| {
+                    .enter(appid, |app, _| if app.pending_write {
+                        // Keep the buffer of an outstanding write
+                        -2
+                    } else {
                         app.write_buffer = Some(slice);
                         0
                     })
@@ -85,103 +447,89 @@ impl<'a, U: UART> Driver for Console<'a, U> {
 
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
-            0 /* read line */ => {
-                // read line is not implemented for console at this time
-                -1
+            0 /* read done */ => {
+                self.apps.enter(callback.app_id(), |app, _| {
+                    app.read_callback = Some(callback);
+                    0
+                }).unwrap_or(-1)
             },
             1 /* putstr/write_done */ => {
-                self.apps.enter(callback.app_id(), |app, _| {
-                    match app.write_buffer.take() {
-                        Some(slice) => {
+                let ret = self.apps.enter(callback.app_id(), |app, _| {
+                    if app.pending_write {
+                        return -2;
+                    }
+                    match app.write_buffer {
+                        Some(ref slice) => {
                             app.write_callback = Some(callback);
                             app.write_len = slice.len();
-                            if self.in_progress.is_none() {
-                                self.in_progress.replace(callback.app_id());
-                                self.tx_buffer.take().map(|buffer| {
-                                    for (i, c) in slice.as_ref().iter().enumerate() {
-                                        if buffer.len() <= i {
-                                            break;
-                                        }
-                                        buffer[i] = *c;
-                                    }
-                                    self.uart.transmit(buffer, app.write_len);
-                                });
-                            } else {
-                                app.pending_write = true;
-                                app.write_buffer = Some(slice);
-                            }
+                            app.write_idx = 0;
+                            app.pending_write = true;
                             0
                         },
                         None => -1
                     }
-                }).unwrap_or(-1)
+                }).unwrap_or(-1);
+                if ret == 0 {
+                    self.start_tx();
+                }
+                ret
             },
             _ => -1
         }
     }
 
-    fn command(&self, cmd_num: usize, arg1: usize, _: AppId) -> isize {
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> isize {
         match cmd_num {
             0 /* putc */ => {
                 self.tx_buffer.take().map(|buffer| {
-                    buffer[0] = arg1 as u8;
-                    self.uart.transmit(buffer, 1);
+                    let len = self.fill(buffer, appid, &[arg1 as u8]);
+                    self.uart.transmit(buffer, len);
                 });
                 1
             },
+            1 /* read */ => self.start_read(appid, arg1, false),
+            2 /* read line */ => self.start_read(appid, arg1, true),
+            3 /* set echo */ => {
+                self.apps.enter(appid, |app, _| {
+                    app.echo = arg1 != 0;
+                    0
+                }).unwrap_or(-3)
+            },
+            4 /* abort read */ => self.abort_read(appid),
             _ => -1
         }
     }
 }
 
 impl<'a, U: UART> Client for Console<'a, U> {
-    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+    fn transmit_complete(&self, buffer: &'static mut [u8], error: uart::Error) {
         // Write TX is done, notify appropriate app and start another
         // transaction if pending
         self.tx_buffer.replace(buffer);
         self.in_progress.take().map(|appid| {
+            self.last_writer.set(appid.idx());
             self.apps.enter(appid, |app, _| {
-                app.write_callback.map(|mut cb| {
-                    cb.schedule(app.write_len, 0, 0);
-                });
-                app.write_len = 0;
+                // A chunk that was not sent is sent again on the app's next
+                // turn
+                if error == uart::Error::CommandComplete {
+                    app.write_idx += app.write_chunk;
+                }
+                app.write_chunk = 0;
+                if app.write_idx >= app.write_len {
+                    self.complete_write(app);
+                }
             })
         });
 
-        for cntr in self.apps.iter() {
-            let started_tx = cntr.enter(|app, _| {
-                if app.pending_write {
-                    app.pending_write = false;
-                    app.write_buffer
-                        .as_ref()
-                        .map(|slice| {
-                            self.tx_buffer.take().map(|buffer| {
-                                for (i, c) in slice.as_ref().iter().enumerate() {
-                                    if buffer.len() <= i {
-                                        break;
-                                    }
-                                    buffer[i] = *c;
-                                }
-                                self.uart.transmit(buffer, app.write_len);
-                            });
-                            self.in_progress.replace(app.appid());
-                            true
-                        })
-                        .unwrap_or(false)
-                } else {
-                    false
-                }
-            });
-            if started_tx {
-                break;
-            }
-        }
+        self.start_tx();
     }
 
-    fn receive_complete(&self,
-                        _rx_buffer: &'static mut [u8],
-                        _rx_len: usize,
-                        _error: uart::Error) {
-        // this is currently unimplemented for console
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        let byte = rx_buffer[0];
+        self.rx_buffer.replace(rx_buffer);
+        if error == uart::Error::CommandComplete && rx_len > 0 {
+            self.received(byte);
+        }
+        self.start_rx();
     }
 }
@@ -7,6 +7,7 @@ use core::cell::Cell;
 use kernel::{AppId, Callback, Driver};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::i2c::{I2CDevice, I2CClient, Error};
+use kernel::hil::sensors::{AccelerationSensor, AccelerationClient};
 
 pub static mut BUF: [u8; 6] = [0; 6];
 
@@ -47,6 +48,10 @@ pub struct Fxos8700cq<'a> {
     state: Cell<State>,
     buffer: TakeCell<&'static mut [u8]>,
     callback: Cell<Option<Callback>>,
+    acceleration_client: Cell<Option<&'static AccelerationClient>>,
+    client_pending: Cell<bool>,
+    /// Most recent reading in milli-g, kept for the acceleration client
+    acceleration: Cell<(i32, i32, i32)>,
 }
 
 impl<'a> Fxos8700cq<'a> {
@@ -56,6 +61,9 @@ impl<'a> Fxos8700cq<'a> {
             state: Cell::new(State::Enabling),
             buffer: TakeCell::new(buffer),
             callback: Cell::new(None),
+            acceleration_client: Cell::new(None),
+            client_pending: Cell::new(false),
+            acceleration: Cell::new((0, 0, 0)),
         }
     }
 
@@ -87,6 +95,16 @@ impl<'a> I2CClient for Fxos8700cq<'a> {
                 self.state.set(State::ReadingAcceleration);
             }
             State::ReadingAcceleration => {
+                // Readings are left-justified 14-bit two's complement values
+                // at 0.244 mg per count in the default +/-2g range
+                let milli_g = |msb: u8, lsb: u8| {
+                    let raw = (((msb as u16) << 8) | lsb as u16) as i16;
+                    ((raw >> 2) as i32 * 244) / 1000
+                };
+                self.acceleration.set((milli_g(buffer[0], buffer[1]),
+                                       milli_g(buffer[2], buffer[3]),
+                                       milli_g(buffer[4], buffer[5])));
+
                 let x = (((buffer[0] as u16) << 8) | buffer[1] as u16) as usize;
                 let y = (((buffer[2] as u16) << 8) | buffer[3] as u16) as usize;
                 let z = (((buffer[4] as u16) << 8) | buffer[5] as u16) as usize;
@@ -104,11 +122,29 @@ impl<'a> I2CClient for Fxos8700cq<'a> {
                 self.state.set(State::Disabled);
                 self.buffer.replace(buffer);
                 self.callback.get().map(|mut cb| cb.schedule(x, y, z));
+                if self.client_pending.get() {
+                    self.client_pending.set(false);
+                    let (x, y, z) = self.acceleration.get();
+                    self.acceleration_client.get().map(|client| client.acceleration_ready(x, y, z));
+                }
             }
         }
     }
 }
 
+impl<'a> AccelerationSensor for Fxos8700cq<'a> {
+    fn set_acceleration_client(&self, client: &'static AccelerationClient) {
+        self.acceleration_client.set(Some(client));
+    }
+
+    fn read_acceleration(&self) -> bool {
+        // A read already in progress will also answer the client
+        self.client_pending.set(true);
+        self.start_read_accel();
+        true
+    }
+}
+
 impl<'a> Driver for Fxos8700cq<'a> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
@@ -1,18 +1,116 @@
+//! Provide capsule driver for GPIO pins.
+//!
+//! Each pin belongs to at most one app. An app claims a pin explicitly with
+//! command 9 or implicitly by using it; commands on a pin another app owns
+//! fail with -2. Interrupts on a pin are delivered only to its owner. Pins
+//! are released with command 10 or when the owning process dies, as the
+//! ownership record lives in the app's grant. Only the first 32 pins can be
+//! claimed.
+//!
+//! A released pin has its interrupt disabled and is disabled itself, so the
+//! next owner starts from a clean pin. Pins of a dead process are reset the
+//! same way the next time any app uses the driver or one of the pins
+//! interrupts.
+
 use core::cell::Cell;
-use kernel::{AppId, Callback, Driver};
+use kernel::{AppId, Callback, Container, Driver};
 use kernel::hil::gpio::{Pin, PinCtl, InputMode, InterruptMode, Client};
 
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    /// Bit mask of the pins the app owns.
+    pins: u32,
+}
+
 pub struct GPIO<'a, G: Pin + 'a> {
     pins: &'a [&'a G],
-    callback: Cell<Option<Callback>>,
+    apps: Container<App>,
+    /// Bit mask of the pins owned by some app, including apps that died
+    /// since.
+    claimed: Cell<u32>,
 }
 
 impl<'a, G: Pin + PinCtl> GPIO<'a, G> {
-    pub fn new(pins: &'a [&'a G]) -> GPIO<'a, G> {
+    pub fn new(pins: &'a [&'a G], container: Container<App>) -> GPIO<'a, G> {
         GPIO {
             pins: pins,
-            callback: Cell::new(None),
+            apps: container,
+            claimed: Cell::new(0),
+        }
+    }
+
+    /// Disables `pin_num` and its interrupt.
+    fn reset_pin(&self, pin_num: usize) {
+        self.pins[pin_num].disable_interrupt();
+        self.pins[pin_num].disable();
+        self.claimed.set(self.claimed.get() & !(1 << pin_num));
+    }
+
+    /// Resets the pins whose owner has died.
+    fn reset_orphaned_pins(&self) {
+        let mut owned = 0;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| owned |= app.pins);
         }
+        let orphaned = self.claimed.get() & !owned;
+        for pin_num in 0..self.pins.len() {
+            if pin_num < 32 && orphaned & (1 << pin_num) != 0 {
+                self.reset_pin(pin_num);
+            }
+        }
+    }
+
+    /// The live app that owns `pin_num`, if any.
+    fn owner(&self, pin_num: usize) -> Option<AppId> {
+        if pin_num >= 32 {
+            return None;
+        }
+        let mut owner = None;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| if app.pins & (1 << pin_num) != 0 {
+                owner = Some(app.appid());
+            });
+        }
+        owner
+    }
+
+    /// Claims `pin_num` for `appid` unless another app owns it.
+    fn claim(&self, pin_num: usize, appid: AppId) -> isize {
+        if pin_num >= self.pins.len() || pin_num >= 32 {
+            return -1;
+        }
+        match self.owner(pin_num) {
+            Some(owner) if owner != appid => -2,
+            Some(_) => 0,
+            None => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.pins |= 1 << pin_num;
+                        self.claimed.set(self.claimed.get() | 1 << pin_num);
+                        0
+                    })
+                    .unwrap_or(-3)
+            }
+        }
+    }
+
+    fn release(&self, pin_num: usize, appid: AppId) -> isize {
+        if pin_num >= self.pins.len() || pin_num >= 32 {
+            return -1;
+        }
+        let ret = self.apps
+            .enter(appid, |app, _| if app.pins & (1 << pin_num) == 0 {
+                -2
+            } else {
+                app.pins &= !(1 << pin_num);
+                0
+            })
+            .unwrap_or(-3);
+        if ret == 0 {
+            self.reset_pin(pin_num);
+        }
+        ret
     }
 
     fn configure_input_pin(&self, pin_num: usize, config: usize) -> isize {
@@ -61,15 +159,21 @@ impl<'a, G: Pin + PinCtl> GPIO<'a, G> {
     }
 }
 
-impl<'a, G: Pin> Client for GPIO<'a, G> {
+impl<'a, G: Pin + PinCtl> Client for GPIO<'a, G> {
     fn fired(&self, pin_num: usize) {
         // read the value of the pin
         let pins = self.pins.as_ref();
         let pin_state = pins[pin_num].read();
 
-        // schedule callback with the pin number and value
-        if self.callback.get().is_some() {
-            self.callback.get().unwrap().schedule(pin_num, pin_state as usize, 0);
+        // schedule callback with the pin number and value for the pin's
+        // owner only
+        match self.owner(pin_num) {
+            Some(owner) => {
+                let _ = self.apps.enter(owner, |app, _| {
+                    app.callback.map(|mut cb| cb.schedule(pin_num, pin_state as usize, 0));
+                });
+            }
+            None => self.reset_orphaned_pins(),
         }
     }
 }
@@ -77,11 +181,15 @@ impl<'a, G: Pin> Client for GPIO<'a, G> {
 impl<'a, G: Pin + PinCtl> Driver for GPIO<'a, G> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
-            // subscribe to all pin interrupts
+            // subscribe to interrupts on the pins the app owns
             // (no affect or reliance on individual pins being configured as interrupts)
             0 => {
-                self.callback.set(Some(callback));
-                0
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-1)
             }
 
             // default
@@ -89,8 +197,21 @@ impl<'a, G: Pin + PinCtl> Driver for GPIO<'a, G> {
         }
     }
 
-    fn command(&self, command_num: usize, data: usize, _: AppId) -> isize {
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
         let pins = self.pins.as_ref();
+        self.reset_orphaned_pins();
+
+        // Every command acts on a pin, which the app must own or be able to
+        // claim
+        let ownership = match command_num {
+            4 | 6 => self.claim(data & 0xFF, appid),
+            0...9 => self.claim(data, appid),
+            _ => 0,
+        };
+        if ownership != 0 {
+            return ownership;
+        }
+
         match command_num {
             // enable output
             0 => {
@@ -199,6 +320,12 @@ impl<'a, G: Pin + PinCtl> Driver for GPIO<'a, G> {
                 }
             }
 
+            // claim pin
+            9 => 0,
+
+            // release pin
+            10 => self.release(data, appid),
+
             // default
             _ => -1,
         }
@@ -0,0 +1,38 @@
+//! Provide capsule driver for relative humidity sensors.
+//!
+//! The reading is passed to the callback in hundredths of a percent. See
+//! `sensor_readings` for the system call interface.
+
+use kernel::{AppId, Callback, Container, Driver};
+use kernel::hil::sensors::{HumiditySensor, HumidityClient};
+use sensor_readings::{App, SensorReadings};
+
+pub struct HumidityDriver<'a> {
+    sensor: &'a HumiditySensor,
+    readings: SensorReadings,
+}
+
+impl<'a> HumidityDriver<'a> {
+    pub fn new(sensor: &'a HumiditySensor, container: Container<App>) -> HumidityDriver<'a> {
+        HumidityDriver {
+            sensor: sensor,
+            readings: SensorReadings::new(container),
+        }
+    }
+}
+
+impl<'a> HumidityClient for HumidityDriver<'a> {
+    fn humidity_ready(&self, humidity: u32) {
+        self.readings.reading_done(humidity as usize, 0, 0);
+    }
+}
+
+impl<'a> Driver for HumidityDriver<'a> {
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        self.readings.subscribe(subscribe_num, callback)
+    }
+
+    fn command(&self, command_num: usize, _: usize, appid: AppId) -> isize {
+        self.readings.command(command_num, appid, || self.sensor.read_humidity())
+    }
+}
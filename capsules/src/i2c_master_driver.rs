@@ -0,0 +1,266 @@
+//! Provides userspace with I2C master access to a bus shared with kernel
+//! drivers.
+//!
+//! Transactions go through `virtual_i2c::MuxI2C`, where they are queued
+//! alongside those of the kernel's `I2CDevice`s. Each app may have one
+//! transaction queued or in flight, and transactions of different apps are
+//! issued one at a time.
+//!
+//! An app may only address the devices listed for its package name in the
+//! board's `Permissions`. Apps that are not listed cannot use the bus.
+//!
+//! Allow 0 sets the buffer data is written from and read into. A write-read
+//! writes the first bytes of the buffer and then reads into its start.
+//!
+//! Subscribe 0 is called when a transaction completes, with the command
+//! number of the transaction and 0 on success or a negative error: -1 if the
+//! address was not acknowledged, -2 if data was not acknowledged, -3 if
+//! arbitration was lost and -4 if the transaction timed out.
+//!
+//! Commands take the device address in bits 0-7 of their argument, the
+//! write length in bits 8-15 and the read length in bits 16-23:
+//!
+//! - 0: write
+//! - 1: read
+//! - 2: write, then read after a repeated start
+//!
+//! They return -1 if the app may not access the address or the lengths do
+//! not fit the buffers, and -2 if the app has a transaction outstanding.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::i2c::{self, Error, I2CClient};
+use kernel::process;
+use virtual_i2c::I2CDevice;
+
+pub static mut BUFFER: [u8; 64] = [0; 64];
+
+/// The device addresses an app may access, by package name.
+pub struct Permissions {
+    pub package_name: &'static [u8],
+    pub addresses: &'static [u8],
+}
+
+/// A transaction with the address of its device and its lengths.
+#[derive(Clone,Copy,PartialEq)]
+enum Transaction {
+    Write(u8, u8),
+    Read(u8, u8),
+    WriteRead(u8, u8, u8),
+}
+
+impl Transaction {
+    fn command_num(&self) -> usize {
+        match *self {
+            Transaction::Write(..) => 0,
+            Transaction::Read(..) => 1,
+            Transaction::WriteRead(..) => 2,
+        }
+    }
+
+    fn addr(&self) -> u8 {
+        match *self {
+            Transaction::Write(addr, _) |
+            Transaction::Read(addr, _) |
+            Transaction::WriteRead(addr, _, _) => addr,
+        }
+    }
+
+    fn write_len(&self) -> usize {
+        match *self {
+            Transaction::Write(_, len) |
+            Transaction::WriteRead(_, len, _) => len as usize,
+            Transaction::Read(..) => 0,
+        }
+    }
+
+    fn read_len(&self) -> usize {
+        match *self {
+            Transaction::Read(_, len) |
+            Transaction::WriteRead(_, _, len) => len as usize,
+            Transaction::Write(..) => 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+    /// The transaction queued or in flight.
+    transaction: Option<Transaction>,
+}
+
+pub struct I2CMasterDriver<'a> {
+    i2c: &'a I2CDevice<'a>,
+    permissions: &'a [Permissions],
+    apps: Container<App>,
+    /// The app whose transaction is in flight.
+    current: Cell<Option<AppId>>,
+    buffer: TakeCell<&'static mut [u8]>,
+    buffer_len: usize,
+}
+
+impl<'a> I2CMasterDriver<'a> {
+    pub fn new(i2c: &'a I2CDevice<'a>,
+               permissions: &'a [Permissions],
+               buffer: &'static mut [u8],
+               container: Container<App>)
+               -> I2CMasterDriver<'a> {
+        I2CMasterDriver {
+            i2c: i2c,
+            permissions: permissions,
+            apps: container,
+            current: Cell::new(None),
+            buffer_len: buffer.len(),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    fn permitted(&self, appid: AppId, addr: u8) -> bool {
+        process::package_name(appid).map_or(false, |name| {
+            self.permissions
+                .iter()
+                .any(|p| p.package_name == name && p.addresses.contains(&addr))
+        })
+    }
+
+    /// Issues the next queued transaction if none is in flight.
+    fn do_next_transaction(&self) {
+        if self.current.get().is_some() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            if self.current.get().is_some() {
+                break;
+            }
+            cntr.enter(|app, _| if let Some(transaction) = app.transaction {
+                self.current.set(Some(app.appid()));
+                self.start(app, transaction);
+            });
+        }
+    }
+
+    fn start(&self, app: &mut App, transaction: Transaction) {
+        self.buffer.take().map(|buffer| {
+            app.buffer.as_ref().map(|src| {
+                let len = cmp::min(transaction.write_len(), src.len());
+                buffer[..len].copy_from_slice(&src.as_ref()[..len]);
+            });
+
+            self.i2c.set_address(transaction.addr());
+            i2c::I2CDevice::enable(self.i2c);
+            let (write_len, read_len) = (transaction.write_len(), transaction.read_len());
+            match transaction {
+                Transaction::Write(..) => i2c::I2CDevice::write(self.i2c, buffer, write_len),
+                Transaction::Read(..) => i2c::I2CDevice::read(self.i2c, buffer, read_len),
+                Transaction::WriteRead(..) => {
+                    i2c::I2CDevice::write_read(self.i2c, buffer, write_len, read_len)
+                }
+            }
+        });
+    }
+}
+
+impl<'a> I2CClient for I2CMasterDriver<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        i2c::I2CDevice::disable(self.i2c);
+
+        // Map I2C error to a number we can pass back to the application
+        let err: isize = match error {
+            Error::AddressNak => -1,
+            Error::DataNak => -2,
+            Error::ArbitrationLost => -3,
+            Error::Timeout => -4,
+            Error::CommandComplete => 0,
+        };
+
+        self.current.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let transaction = app.transaction.take();
+                transaction.map(|transaction| {
+                    app.buffer.as_mut().map(|dest| {
+                        let len = cmp::min(transaction.read_len(), dest.len());
+                        dest.as_mut()[..len].copy_from_slice(&buffer[..len]);
+                    });
+                    app.callback.map(|mut cb| {
+                        cb.schedule(transaction.command_num(), err as usize, 0);
+                    });
+                });
+            });
+        });
+
+        self.buffer.replace(buffer);
+        self.current.set(None);
+        self.do_next_transaction();
+    }
+}
+
+impl<'a> Driver for I2CMasterDriver<'a> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        match allow_num {
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| if app.transaction.is_some() {
+                        // Keep the buffer of an outstanding transaction
+                        -2
+                    } else {
+                        app.buffer = Some(slice);
+                        0
+                    })
+                    .unwrap_or(-3)
+            }
+            _ => -1,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-3)
+            }
+            _ => -1,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        let addr = data as u8;
+        let write_len = (data >> 8) as u8;
+        let read_len = (data >> 16) as u8;
+        let transaction = match command_num {
+            0 => Transaction::Write(addr, write_len),
+            1 => Transaction::Read(addr, read_len),
+            2 => Transaction::WriteRead(addr, write_len, read_len),
+            _ => return -1,
+        };
+        if !self.permitted(appid, addr) {
+            return -1;
+        }
+
+        let len = cmp::max(transaction.write_len(), transaction.read_len());
+        let result = self.apps
+            .enter(appid, |app, _| {
+                if app.transaction.is_some() {
+                    return -2;
+                }
+                let app_len = app.buffer.as_ref().map_or(0, |buffer| buffer.len());
+                if len == 0 || len > app_len || len > self.buffer_len {
+                    return -1;
+                }
+                app.transaction = Some(transaction);
+                0
+            })
+            .unwrap_or(-3);
+        if result == 0 {
+            self.do_next_transaction();
+        }
+        result
+    }
+}
@@ -32,7 +32,7 @@ pub struct AppState {
 
 #[derive(Clone,Copy,PartialEq)]
 enum MasterAction {
-    Read(u8),
+    Read(usize),
     Write,
 }
 
@@ -81,6 +81,7 @@ impl<'a> hil::i2c::I2CHwMasterClient for I2CMasterSlaveDriver<'a> {
             hil::i2c::Error::AddressNak => -1,
             hil::i2c::Error::DataNak => -2,
             hil::i2c::Error::ArbitrationLost => -3,
+            hil::i2c::Error::Timeout => -4,
             hil::i2c::Error::CommandComplete => 0,
         };
 
@@ -100,9 +101,9 @@ impl<'a> hil::i2c::I2CHwMasterClient for I2CMasterSlaveDriver<'a> {
             MasterAction::Read(read_len) => {
                 self.app_state.map(|app_state| {
                     app_state.master_rx_buffer.map(move |app_buffer| {
-                        let len = cmp::min(app_buffer.len(), read_len as usize);
+                        let len = cmp::min(app_buffer.len(), read_len);
 
-                        let d = &mut app_buffer.as_mut()[0..(len as usize)];
+                        let d = &mut app_buffer.as_mut()[0..len];
                         for (i, c) in buffer[0..len].iter().enumerate() {
                             d[i] = *c;
                         }
@@ -273,7 +274,7 @@ impl<'a> Driver for I2CMasterSlaveDriver<'a> {
                             hil::i2c::I2CMaster::write(self.i2c,
                                                        address,
                                                        kernel_tx,
-                                                       write_len as u8);
+                                                       write_len);
                         });
                     });
                 });
@@ -298,10 +299,10 @@ impl<'a> Driver for I2CMasterSlaveDriver<'a> {
                                 *c = d[i];
                             }
 
-                            self.master_action.set(MasterAction::Read(read_len as u8));
+                            self.master_action.set(MasterAction::Read(read_len));
 
                             hil::i2c::I2CMaster::enable(self.i2c);
-                            hil::i2c::I2CMaster::read(self.i2c, address, kernel_tx, read_len as u8);
+                            hil::i2c::I2CMaster::read(self.i2c, address, kernel_tx, read_len);
                         });
                     });
                 });
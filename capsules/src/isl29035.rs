@@ -4,6 +4,7 @@ use core::cell::Cell;
 use kernel::{AppId, Callback, Driver};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::i2c::{I2CDevice, I2CClient, Error};
+use kernel::hil::sensors::{LightSensor, LightClient};
 
 pub static mut BUF: [u8; 3] = [0; 3];
 
@@ -20,6 +21,8 @@ pub struct Isl29035<'a> {
     state: Cell<State>,
     buffer: TakeCell<&'static mut [u8]>,
     callback: Cell<Option<Callback>>,
+    light_client: Cell<Option<&'static LightClient>>,
+    client_pending: Cell<bool>,
 }
 
 impl<'a> Isl29035<'a> {
@@ -29,6 +32,8 @@ impl<'a> Isl29035<'a> {
             state: Cell::new(State::Disabled),
             buffer: TakeCell::new(buffer),
             callback: Cell::new(None),
+            light_client: Cell::new(None),
+            client_pending: Cell::new(false),
         }
     }
 
@@ -56,6 +61,19 @@ impl<'a> Isl29035<'a> {
     }
 }
 
+impl<'a> LightSensor for Isl29035<'a> {
+    fn set_light_client(&self, client: &'static LightClient) {
+        self.light_client.set(Some(client));
+    }
+
+    fn read_light(&self) -> bool {
+        // A read already in progress will also answer the client
+        self.client_pending.set(true);
+        self.start_read_lux();
+        true
+    }
+}
+
 impl<'a> Driver for Isl29035<'a> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
@@ -107,6 +125,10 @@ impl<'a> I2CClient for Isl29035<'a> {
                 self.state.set(State::Disabled);
                 self.buffer.replace(buffer);
                 self.callback.get().map(|mut cb| cb.schedule(lux, 0, 0));
+                if self.client_pending.get() {
+                    self.client_pending.set(false);
+                    self.light_client.get().map(|client| client.light_ready(lux as u32));
+                }
             }
             _ => {}
         }
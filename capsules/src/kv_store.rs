@@ -0,0 +1,987 @@
+//! Provide capsule driver for a persistent key-value store.
+//!
+//! Apps store short keys (up to `MAX_KEY_LEN` bytes) with bounded values (up
+//! to `MAX_VALUE_LEN` bytes). Keys are namespaced per app by the app's package
+//! name, so apps with different names cannot see each other's keys, while an
+//! app keeps its keys across restarts and reflashing. Apps without a package
+//! name, or with one longer than `MAX_NAME_LEN` bytes, cannot use the store.
+//!
+//! # Layout
+//!
+//! The store is a log of records kept in a range of flash pages. Every page
+//! image starts with a header holding a magic number, a write sequence
+//! number, the page's position in the log (its "logical" number), the number
+//! of record bytes in use, the oldest logical page still in use when the
+//! image was written and a checksum over all of these. Records follow the
+//! header:
+//!
+//! ```text
+//! +------+----------+---------+-----------+------+-----+-------+
+//! | kind | name len | key len | value len | name | key | value |
+//! +------+----------+---------+-----------+------+-----+-------+
+//! ```
+//!
+//! where `name` is the package name of the app owning the key.
+//!
+//! A `set` or `delete` appends a record to the last logical page (the tail)
+//! and writes the whole tail image to a free physical page with a higher
+//! sequence number. The previous image of the tail is only abandoned once the
+//! new one has been written, so a write interrupted by power loss leaves a
+//! page with an invalid checksum that is ignored on recovery, and the store
+//! falls back to the state before the write. Since every write goes to a
+//! different physical page, wear is spread over the whole range.
+//!
+//! When the tail is full, a new logical page is started. If that would leave
+//! no free page for the next write, the oldest logical page is garbage
+//! collected first: its records that are still current are moved to the tail
+//! and its physical page becomes free. The tail image written by the
+//! collection records that the page is no longer in use, so its stale records
+//! (and stale images of any older logical page) are ignored on recovery even
+//! though they are never erased.
+//!
+//! On boot, `initialize` scans all pages, keeps the newest valid image of
+//! every logical page still in use and replays their records in log order to
+//! rebuild the in-memory index of keys. If the pages hold more keys than the
+//! index can track, the store refuses all requests instead of running with
+//! keys missing from the index, which garbage collection would then drop.
+//!
+//! # System call interface
+//!
+//! Allow:
+//!
+//!   * 0: key buffer
+//!   * 1: value buffer
+//!
+//! Subscribe:
+//!
+//!   * 0: operation complete, called with the command number and the result:
+//!        the length of the value for `get`, 0 for `set` and `delete`, or a
+//!        negative error code (-1 no such key, -2 store full, -3 flash error
+//!        or unusable store)
+//!
+//! Commands:
+//!
+//!   * 0: driver check
+//!   * 1: get the value of the key of length `data`
+//!   * 2: set the key of length `data & 0xff` to the value of length
+//!        `data >> 8`
+//!   * 3: delete the key of length `data`
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::{self, Flash};
+use kernel::process;
+use page_image::{self, read_u32, write_u32};
+
+pub const MAX_KEY_LEN: usize = 16;
+pub const MAX_VALUE_LEN: usize = 32;
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Maximum number of keys in the store, across all apps.
+const MAX_KEYS: usize = 32;
+
+/// Maximum number of flash pages the store can manage.
+const MAX_PAGES: usize = 16;
+
+const MAGIC: u32 = 0x4b565331; // "KVS1"
+const HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 4;
+
+const RECORD_SET: u8 = 0x01;
+const RECORD_DELETE: u8 = 0x02;
+
+const ERROR_NOT_FOUND: isize = -1;
+const ERROR_FULL: isize = -2;
+const ERROR_FLASH: isize = -3;
+
+/// Whether an app with package name `name` can use the store.
+fn is_valid_name(name: &[u8]) -> bool {
+    name.len() > 0 && name.len() <= MAX_NAME_LEN
+}
+
+/// Header of a page image.
+#[derive(Copy, Clone)]
+struct Header {
+    seq: u32,
+    logical: u32,
+    used: usize,
+    /// Oldest logical page in use when the image was written.
+    base: u32,
+}
+
+/// Parses the header of a page image, if the image is valid.
+fn parse_header(page: &[u8]) -> Option<Header> {
+    let used = (page[12] as usize) | (page[13] as usize) << 8;
+    if read_u32(page, 0) != MAGIC || HEADER_LEN + used > page.len() ||
+       read_u32(page, 20) != page_image::checksum(page, HEADER_LEN, used) {
+        None
+    } else {
+        Some(Header {
+            seq: read_u32(page, 4),
+            logical: read_u32(page, 8),
+            used: used,
+            base: read_u32(page, 16),
+        })
+    }
+}
+
+fn write_header(page: &mut [u8], header: Header) {
+    let used = header.used;
+    write_u32(page, 0, MAGIC);
+    write_u32(page, 4, header.seq);
+    write_u32(page, 8, header.logical);
+    page[12] = used as u8;
+    page[13] = (used >> 8) as u8;
+    page[14] = 0;
+    page[15] = 0;
+    write_u32(page, 16, header.base);
+    let checksum = page_image::checksum(page, HEADER_LEN, used);
+    write_u32(page, 20, checksum);
+}
+
+/// A record as stored in a page.
+#[derive(Copy, Clone)]
+struct Record {
+    kind: u8,
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    key: [u8; MAX_KEY_LEN],
+    key_len: usize,
+    value: [u8; MAX_VALUE_LEN],
+    value_len: usize,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        RECORD_HEADER_LEN + self.name_len + self.key_len + self.value_len
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key[..self.key_len]
+    }
+
+    /// Reads the record at `offset` in `page`, if any.
+    fn parse(page: &[u8], offset: usize, end: usize) -> Option<Record> {
+        if offset + RECORD_HEADER_LEN > end {
+            return None;
+        }
+        let name_len = page[offset + 1] as usize;
+        let key_len = page[offset + 2] as usize;
+        let value_len = page[offset + 3] as usize;
+        if name_len > MAX_NAME_LEN || key_len > MAX_KEY_LEN || value_len > MAX_VALUE_LEN ||
+           offset + RECORD_HEADER_LEN + name_len + key_len + value_len > end {
+            return None;
+        }
+        let mut record = Record {
+            kind: page[offset],
+            name: [0; MAX_NAME_LEN],
+            name_len: name_len,
+            key: [0; MAX_KEY_LEN],
+            key_len: key_len,
+            value: [0; MAX_VALUE_LEN],
+            value_len: value_len,
+        };
+        let name_start = offset + RECORD_HEADER_LEN;
+        record.name[..name_len].copy_from_slice(&page[name_start..name_start + name_len]);
+        let key_start = name_start + name_len;
+        record.key[..key_len].copy_from_slice(&page[key_start..key_start + key_len]);
+        let value_start = key_start + key_len;
+        record.value[..value_len].copy_from_slice(&page[value_start..value_start + value_len]);
+        Some(record)
+    }
+
+    fn write(&self, page: &mut [u8], offset: usize) {
+        page[offset] = self.kind;
+        page[offset + 1] = self.name_len as u8;
+        page[offset + 2] = self.key_len as u8;
+        page[offset + 3] = self.value_len as u8;
+        let name_start = offset + RECORD_HEADER_LEN;
+        page[name_start..name_start + self.name_len].copy_from_slice(self.name());
+        let key_start = name_start + self.name_len;
+        page[key_start..key_start + self.key_len].copy_from_slice(self.key());
+        let value_start = key_start + self.key_len;
+        page[value_start..value_start + self.value_len]
+            .copy_from_slice(&self.value[..self.value_len]);
+    }
+}
+
+/// Location of the current record of a key.
+#[derive(Copy, Clone)]
+struct Entry {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    key: [u8; MAX_KEY_LEN],
+    key_len: usize,
+    logical: u32,
+    offset: usize,
+}
+
+impl Entry {
+    fn is_for(&self, name: &[u8], key: &[u8]) -> bool {
+        &self.name[..self.name_len] == name && &self.key[..self.key_len] == key
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum PageState {
+    Free,
+    Live { logical: u32, seq: u32 },
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Uninitialized,
+    /// Recovery found more keys than fit in the index, all requests fail.
+    Unusable,
+    /// Reading physical page n to find valid page images.
+    Scanning(usize),
+    /// Reading logical page n to rebuild the index.
+    Replaying(u32),
+    Idle,
+    /// Reading the page holding the value of the current `get`.
+    Reading,
+    /// Reading the oldest logical page to garbage collect it.
+    Collecting(u32),
+    /// Writing a new image of the tail to a physical page.
+    Committing {
+        page: usize,
+        /// Page of the garbage collected logical page, freed once the write
+        /// completes.
+        collected: Option<usize>,
+        /// Whether the image contains the record of the current request.
+        with_record: bool,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Op {
+    Get,
+    Set,
+    Delete,
+}
+
+impl Op {
+    fn command_num(&self) -> usize {
+        match *self {
+            Op::Get => 1,
+            Op::Set => 2,
+            Op::Delete => 3,
+        }
+    }
+}
+
+/// The app request being served.
+#[derive(Copy, Clone)]
+struct Request {
+    appid: AppId,
+    op: Op,
+    record: Record,
+    /// Number of garbage collection passes done for this request.
+    collections: usize,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    value: Option<AppSlice<Shared, u8>>,
+    pending: Option<(Op, usize, usize)>,
+}
+
+pub struct KVStore<'a, F: Flash + 'static> {
+    driver: &'a F,
+    first_page: usize,
+    num_pages: usize,
+    state: Cell<State>,
+    pages: Cell<[PageState; MAX_PAGES]>,
+    index: TakeCell<[Option<Entry>; MAX_KEYS]>,
+    /// In-memory image of the tail page, also used to write it.
+    tail: TakeCell<&'static mut F::Page>,
+    /// Logical number and physical page of the tail, `None` while the store
+    /// is empty.
+    tail_logical: Cell<u32>,
+    tail_page: Cell<Option<usize>>,
+    tail_used: Cell<usize>,
+    next_seq: Cell<u32>,
+    /// Oldest logical page in use according to the newest image on flash.
+    base: Cell<u32>,
+    /// Physical page written last, to rotate writes over all pages.
+    last_written: Cell<usize>,
+    /// Buffer for reading pages other than the tail.
+    scratch: TakeCell<&'static mut F::Page>,
+    request: Cell<Option<Request>>,
+    apps: Container<App>,
+}
+
+impl<'a, F: Flash> KVStore<'a, F> {
+    /// Creates a store over the `num_pages` flash pages starting at
+    /// `first_page`. At least three pages are needed.
+    pub fn new(driver: &'a F,
+               first_page: usize,
+               num_pages: usize,
+               tail: &'static mut F::Page,
+               scratch: &'static mut F::Page,
+               container: Container<App>)
+               -> KVStore<'a, F> {
+        KVStore {
+            driver: driver,
+            first_page: first_page,
+            num_pages: cmp::min(num_pages, MAX_PAGES),
+            state: Cell::new(State::Uninitialized),
+            pages: Cell::new([PageState::Free; MAX_PAGES]),
+            index: TakeCell::new([None; MAX_KEYS]),
+            tail: TakeCell::new(tail),
+            tail_logical: Cell::new(0),
+            tail_page: Cell::new(None),
+            tail_used: Cell::new(0),
+            next_seq: Cell::new(0),
+            base: Cell::new(0),
+            last_written: Cell::new(0),
+            scratch: TakeCell::new(scratch),
+            request: Cell::new(None),
+            apps: container,
+        }
+    }
+
+    /// Recovers the store from flash. Must be called once by the board;
+    /// requests are queued until recovery is done.
+    pub fn initialize(&self) {
+        self.pages.set([PageState::Free; MAX_PAGES]);
+        self.index.map(|index| for entry in index.iter_mut() {
+            *entry = None;
+        });
+        self.tail_page.set(None);
+        self.tail_logical.set(0);
+        self.tail_used.set(0);
+        self.next_seq.set(0);
+        self.base.set(0);
+        self.scan(0);
+    }
+
+    // Recovery
+
+    fn scan(&self, page: usize) {
+        if page >= self.num_pages {
+            // Drop images of logical pages that were garbage collected
+            let mut pages = self.pages.get();
+            for p in pages.iter_mut() {
+                if let PageState::Live { logical, .. } = *p {
+                    if logical < self.base.get() {
+                        *p = PageState::Free;
+                    }
+                }
+            }
+            self.pages.set(pages);
+            self.replay(None);
+            return;
+        }
+        self.state.set(State::Scanning(page));
+        self.read(self.first_page + page);
+    }
+
+    fn scanned(&self, page: usize, header: Option<Header>) {
+        header.map(|header| {
+            let (seq, logical) = (header.seq, header.logical);
+            let mut pages = self.pages.get();
+            // Keep only the newest image of each logical page
+            let mut newest = true;
+            for other in pages.iter_mut() {
+                if let PageState::Live { logical: l, seq: s } = *other {
+                    if l == logical {
+                        if s > seq {
+                            newest = false;
+                        } else {
+                            *other = PageState::Free;
+                        }
+                    }
+                }
+            }
+            if newest {
+                pages[page] = PageState::Live {
+                    logical: logical,
+                    seq: seq,
+                };
+            }
+            self.pages.set(pages);
+            if seq >= self.next_seq.get() {
+                self.next_seq.set(seq.wrapping_add(1));
+                self.base.set(header.base);
+                self.last_written.set(page);
+            }
+        });
+        self.scan(page + 1);
+    }
+
+    /// Reads the live logical page following `previous` to replay its
+    /// records, or finishes recovery if there is none.
+    fn replay(&self, previous: Option<u32>) {
+        let next = self.pages
+            .get()
+            .iter()
+            .filter_map(|p| match *p {
+                PageState::Live { logical, .. } if previous.map_or(true, |prev| logical > prev) => {
+                    Some(logical)
+                }
+                _ => None,
+            })
+            .min();
+        match next {
+            Some(logical) => {
+                self.state.set(State::Replaying(logical));
+                self.read(self.first_page + self.physical_page(logical).unwrap_or(0));
+            }
+            None => {
+                self.state.set(State::Idle);
+                self.run_next();
+            }
+        }
+    }
+
+    /// Adds the records of logical page `logical` (in `data`) to the index.
+    /// Returns false if the index is full.
+    fn replayed(&self, logical: u32, data: &[u8]) -> bool {
+        let used = parse_header(data).map_or(0, |header| header.used);
+        let mut offset = HEADER_LEN;
+        while let Some(record) = Record::parse(data, offset, HEADER_LEN + used) {
+            if !self.apply(&record, logical, offset) {
+                return false;
+            }
+            offset += record.len();
+        }
+
+        let is_tail = self.pages.get().iter().all(|p| match *p {
+            PageState::Live { logical: l, .. } => l <= logical,
+            PageState::Free => true,
+        });
+        if is_tail {
+            self.tail_logical.set(logical);
+            self.tail_page.set(self.physical_page(logical));
+            self.tail_used.set(used);
+            self.tail.map(|tail| {
+                let len = tail.as_ref().len();
+                tail.as_mut()[..len].copy_from_slice(&data[..len]);
+            });
+        }
+        true
+    }
+
+    // Index
+
+    fn find(&self, name: &[u8], key: &[u8]) -> Option<Entry> {
+        self.index
+            .map(|index| index.iter().filter_map(|e| *e).find(|e| e.is_for(name, key)))
+            .unwrap_or(None)
+    }
+
+    /// Updates the index with `record`, stored at `offset` of logical page
+    /// `logical`. Returns false if the index is full.
+    fn apply(&self, record: &Record, logical: u32, offset: usize) -> bool {
+        self.index
+            .map(|index| {
+                let existing = index.iter()
+                    .position(|e| e.map_or(false, |e| e.is_for(record.name(), record.key())));
+                if record.kind == RECORD_DELETE {
+                    existing.map(|i| index[i] = None);
+                    return true;
+                }
+                let slot = existing.or_else(|| index.iter().position(|e| e.is_none()));
+                match slot {
+                    Some(i) => {
+                        index[i] = Some(Entry {
+                            name: record.name,
+                            name_len: record.name_len,
+                            key: record.key,
+                            key_len: record.key_len,
+                            logical: logical,
+                            offset: offset,
+                        });
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .unwrap_or(false)
+    }
+
+    fn index_has_room(&self, record: &Record) -> bool {
+        record.kind == RECORD_DELETE || self.find(record.name(), record.key()).is_some() ||
+        self.index.map_or(false, |index| index.iter().any(|e| e.is_none()))
+    }
+
+    // Pages
+
+    fn physical_page(&self, logical: u32) -> Option<usize> {
+        self.pages.get().iter().position(|p| match *p {
+            PageState::Live { logical: l, .. } => l == logical,
+            PageState::Free => false,
+        })
+    }
+
+    fn free_pages(&self) -> usize {
+        self.pages.get()[..self.num_pages].iter().filter(|p| **p == PageState::Free).count()
+    }
+
+    /// Picks the next free physical page, rotating through all pages.
+    fn next_free_page(&self) -> Option<usize> {
+        let pages = self.pages.get();
+        (1..self.num_pages + 1)
+            .map(|i| (self.last_written.get() + i) % self.num_pages)
+            .find(|p| pages[*p] == PageState::Free)
+    }
+
+    fn oldest_logical(&self) -> Option<u32> {
+        self.pages
+            .get()
+            .iter()
+            .filter_map(|p| match *p {
+                PageState::Live { logical, .. } if logical != self.tail_logical.get() => {
+                    Some(logical)
+                }
+                _ => None,
+            })
+            .min()
+    }
+
+    fn read(&self, page: usize) {
+        self.scratch.take().map(|buf| if let Err((_, buf)) = self.driver.read_page(page, buf) {
+            self.scratch.replace(buf);
+            self.read_failed();
+        });
+    }
+
+    fn read_failed(&self) {
+        match self.state.get() {
+            // Treat unreadable pages as invalid
+            State::Scanning(page) => self.scan(page + 1),
+            State::Replaying(logical) => self.replay(Some(logical)),
+            _ => self.fail(ERROR_FLASH),
+        }
+    }
+
+    /// Writes the tail image to a free page, updating its header first.
+    fn commit(&self, collected: Option<usize>, with_record: bool) {
+        // Oldest logical page still in use once the image is written
+        let base = self.pages
+            .get()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| match *p {
+                PageState::Live { logical, .. } if Some(i) != collected => Some(logical),
+                _ => None,
+            })
+            .fold(self.tail_logical.get(), cmp::min);
+        match self.next_free_page() {
+            None => self.fail(ERROR_FULL),
+            Some(page) => {
+                self.tail.take().map(|tail| {
+                    write_header(tail.as_mut(),
+                                 Header {
+                                     seq: self.next_seq.get(),
+                                     logical: self.tail_logical.get(),
+                                     used: self.tail_used.get(),
+                                     base: base,
+                                 });
+                    self.state.set(State::Committing {
+                        page: page,
+                        collected: collected,
+                        with_record: with_record,
+                    });
+                    if let Err((_, tail)) = self.driver.write_page(self.first_page + page, tail) {
+                        self.tail.replace(tail);
+                        self.fail(ERROR_FLASH);
+                    }
+                });
+            }
+        }
+    }
+
+    fn committed(&self, page: usize, collected: Option<usize>) {
+        let mut pages = self.pages.get();
+        self.tail_page.get().map(|old| pages[old] = PageState::Free);
+        collected.map(|old| pages[old] = PageState::Free);
+        pages[page] = PageState::Live {
+            logical: self.tail_logical.get(),
+            seq: self.next_seq.get(),
+        };
+        self.pages.set(pages);
+        self.tail_page.set(Some(page));
+        self.last_written.set(page);
+        self.next_seq.set(self.next_seq.get().wrapping_add(1));
+    }
+
+    /// Abandons the current request, restoring the in-memory state from
+    /// flash since it may not match what was written.
+    fn fail(&self, error: isize) {
+        self.finish_request(error);
+        self.initialize();
+    }
+
+    // Requests
+
+    /// Appends the record of the current request to the tail, starting a new
+    /// tail or garbage collecting first if needed.
+    fn append(&self) {
+        let request = match self.request.get() {
+            Some(request) => request,
+            None => return,
+        };
+        let record = request.record;
+        let capacity = self.tail.map_or(0, |tail| tail.as_ref().len()) - HEADER_LEN;
+        let used = self.tail_used.get();
+
+        if self.tail_page.get().is_some() && used + record.len() <= capacity {
+            self.tail.map(|tail| record.write(tail.as_mut(), HEADER_LEN + used));
+            self.tail_used.set(used + record.len());
+            self.commit(None, true);
+        } else if self.tail_page.get().is_none() || self.free_pages() >= 2 {
+            // Start a new tail. Its first image goes to a free page, so the
+            // old tail stays in place.
+            if self.tail_page.get().is_some() {
+                self.tail_logical.set(self.tail_logical.get().wrapping_add(1));
+            }
+            self.tail_page.set(None);
+            self.tail.map(|tail| record.write(tail.as_mut(), HEADER_LEN));
+            self.tail_used.set(record.len());
+            self.commit(None, true);
+        } else {
+            match self.oldest_logical() {
+                Some(logical) if request.collections < self.num_pages => {
+                    self.request.set(Some(Request {
+                        collections: request.collections + 1,
+                        ..request
+                    }));
+                    self.state.set(State::Collecting(logical));
+                    self.read(self.first_page + self.physical_page(logical).unwrap_or(0));
+                }
+                _ => {
+                    self.state.set(State::Idle);
+                    self.finish_request(ERROR_FULL);
+                    self.run_next();
+                }
+            }
+        }
+    }
+
+    /// Moves the current records of logical page `logical` (in `data`) to
+    /// the tail, or to a new tail if they do not fit. Returns the physical
+    /// page to free once the tail is committed.
+    fn collect(&self, logical: u32, data: &[u8]) -> Option<usize> {
+        let used = parse_header(data).map_or(0, |header| header.used);
+        let capacity = data.len() - HEADER_LEN;
+
+        let mut live_len = 0;
+        let mut offset = HEADER_LEN;
+        while let Some(record) = Record::parse(data, offset, HEADER_LEN + used) {
+            if self.is_current(&record, logical, offset) {
+                live_len += record.len();
+            }
+            offset += record.len();
+        }
+
+        if self.tail_used.get() + live_len > capacity {
+            // Start a new tail with the moved records. The old tail stays
+            // as is, so the number of free pages is unchanged.
+            self.tail_logical.set(self.tail_logical.get().wrapping_add(1));
+            self.tail_page.set(None);
+            self.tail_used.set(0);
+        }
+
+        let mut offset = HEADER_LEN;
+        while let Some(record) = Record::parse(data, offset, HEADER_LEN + used) {
+            if self.is_current(&record, logical, offset) {
+                let new_offset = HEADER_LEN + self.tail_used.get();
+                self.tail.map(|tail| record.write(tail.as_mut(), new_offset));
+                self.tail_used.set(self.tail_used.get() + record.len());
+                self.apply(&record, self.tail_logical.get(), new_offset);
+            }
+            offset += record.len();
+        }
+
+        self.physical_page(logical)
+    }
+
+    /// Whether `record`, at `offset` of logical page `logical`, is the
+    /// current record of its key.
+    fn is_current(&self, record: &Record, logical: u32, offset: usize) -> bool {
+        record.kind == RECORD_SET &&
+        self.find(record.name(), record.key())
+            .map_or(false, |e| e.logical == logical && e.offset == offset)
+    }
+
+    /// Signals the app of the current request with `result`.
+    fn finish_request(&self, result: isize) {
+        self.request.get().map(|request| {
+            self.request.set(None);
+            let _ = self.apps.enter(request.appid, |app, _| {
+                if request.op == Op::Get && result >= 0 {
+                    app.value.as_mut().map(|value| {
+                        let len = cmp::min(value.len(), request.record.value_len);
+                        value.as_mut()[..len].copy_from_slice(&request.record.value[..len]);
+                    });
+                }
+                app.callback
+                    .map(|mut cb| cb.schedule(request.op.command_num(), result as usize, 0));
+            });
+        });
+    }
+
+    /// Starts serving `request`.
+    fn start(&self, request: Request) {
+        self.request.set(Some(request));
+        let record = request.record;
+        match request.op {
+            Op::Get => {
+                match self.find(record.name(), record.key()) {
+                    None => self.finish_request(ERROR_NOT_FOUND),
+                    Some(entry) if entry.logical == self.tail_logical.get() &&
+                                   self.tail_page.get().is_some() => {
+                        let found = self.tail.map(|tail| {
+                            let tail = tail.as_ref();
+                            Record::parse(tail, entry.offset, tail.len())
+                        });
+                        self.found(found.unwrap_or(None));
+                    }
+                    Some(entry) => {
+                        self.state.set(State::Reading);
+                        self.read(self.first_page + self.physical_page(entry.logical).unwrap_or(0));
+                    }
+                }
+            }
+            Op::Set | Op::Delete => {
+                let exists = self.find(record.name(), record.key()).is_some();
+                if record.kind == RECORD_DELETE && !exists {
+                    self.finish_request(ERROR_NOT_FOUND);
+                } else if !self.index_has_room(&record) {
+                    self.finish_request(ERROR_FULL);
+                } else {
+                    self.append();
+                }
+            }
+        }
+    }
+
+    /// Finishes a `get` with the record read from flash.
+    fn found(&self, record: Option<Record>) {
+        match record {
+            Some(record) => {
+                self.request.get().map(|request| {
+                    self.request.set(Some(Request { record: record, ..request }));
+                });
+                self.finish_request(record.value_len as isize);
+            }
+            None => self.finish_request(ERROR_FLASH),
+        }
+    }
+
+    /// Starts the next queued app request, if the store is idle.
+    fn run_next(&self) {
+        for app in self.apps.iter() {
+            if self.state.get() == State::Unusable {
+                app.enter(|app, _| {
+                    app.pending.take().map(|(op, _, _)| {
+                        app.callback
+                            .map(|mut cb| cb.schedule(op.command_num(), ERROR_FLASH as usize, 0));
+                    });
+                });
+                continue;
+            }
+            if self.state.get() != State::Idle || self.request.get().is_some() {
+                return;
+            }
+            let request = app.enter(|app, _| {
+                app.pending.take().and_then(|(op, key_len, value_len)| {
+                    let name = process::package_name(app.appid()).unwrap_or(&[]);
+                    let mut record = Record {
+                        kind: if op == Op::Delete { RECORD_DELETE } else { RECORD_SET },
+                        name: [0; MAX_NAME_LEN],
+                        name_len: name.len(),
+                        key: [0; MAX_KEY_LEN],
+                        key_len: key_len,
+                        value: [0; MAX_VALUE_LEN],
+                        value_len: if op == Op::Set { value_len } else { 0 },
+                    };
+                    let named = is_valid_name(name);
+                    if named {
+                        record.name[..name.len()].copy_from_slice(name);
+                    }
+                    let copied = named && app.key.as_ref().map_or(false, |key| {
+                        if key.len() < key_len {
+                            return false;
+                        }
+                        record.key[..key_len].copy_from_slice(&key.as_ref()[..key_len]);
+                        true
+                    });
+                    let copied = copied && (op != Op::Set ||
+                                            app.value.as_ref().map_or(false, |value| {
+                        if value.len() < value_len {
+                            return false;
+                        }
+                        record.value[..value_len].copy_from_slice(&value.as_ref()[..value_len]);
+                        true
+                    }));
+                    if copied {
+                        Some(Request {
+                            appid: app.appid(),
+                            op: op,
+                            record: record,
+                            collections: 0,
+                        })
+                    } else {
+                        app.callback
+                            .map(|mut cb| cb.schedule(op.command_num(), -1isize as usize, 0));
+                        None
+                    }
+                })
+            });
+            request.map(|request| self.start(request));
+        }
+    }
+}
+
+impl<'a, F: Flash> Driver for KVStore<'a, F> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        self.apps
+            .enter(appid, |app, _| match allow_num {
+                0 => {
+                    app.key = Some(slice);
+                    0
+                }
+                1 => {
+                    app.value = Some(slice);
+                    0
+                }
+                _ => -1,
+            })
+            .unwrap_or(-1)
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+            _ => -1,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        let (op, key_len, value_len) = match command_num {
+            0 => return 0,
+            1 => (Op::Get, data, 0),
+            2 => (Op::Set, data & 0xff, data >> 8),
+            3 => (Op::Delete, data, 0),
+            _ => return -1,
+        };
+        if key_len == 0 || key_len > MAX_KEY_LEN || value_len > MAX_VALUE_LEN ||
+           !process::package_name(appid).map_or(false, is_valid_name) {
+            return -1;
+        }
+
+        let busy = self.request.get().map_or(false, |r| r.appid == appid);
+        let ret = self.apps
+            .enter(appid, |app, _| if app.pending.is_some() || busy {
+                -2
+            } else {
+                app.pending = Some((op, key_len, value_len));
+                0
+            })
+            .unwrap_or(-3);
+        if ret == 0 {
+            self.run_next();
+        }
+        ret
+    }
+}
+
+impl<'a, F: Flash> flash::Client<F> for KVStore<'a, F> {
+    fn read_complete(&self, read_buffer: &'static mut F::Page, error: flash::Error) {
+        if error != flash::Error::CommandComplete {
+            self.scratch.replace(read_buffer);
+            self.read_failed();
+            return;
+        }
+
+        // Put the buffer back before acting on the data, since the next step
+        // may read another page.
+        self.scratch.replace(read_buffer);
+        match self.state.get() {
+            State::Scanning(page) => {
+                let header = self.scratch.map(|data| parse_header(data.as_ref()));
+                self.scanned(page, header.unwrap_or(None));
+            }
+            State::Replaying(logical) => {
+                let indexed = self.scratch
+                    .map_or(false, |data| self.replayed(logical, data.as_ref()));
+                if indexed {
+                    self.replay(Some(logical));
+                } else {
+                    self.state.set(State::Unusable);
+                    self.run_next();
+                }
+            }
+            State::Reading => {
+                let entry = self.request
+                    .get()
+                    .and_then(|r| self.find(r.record.name(), r.record.key()));
+                let record = self.scratch.map(|data| {
+                    let data = data.as_ref();
+                    entry.and_then(|e| Record::parse(data, e.offset, data.len()))
+                });
+                self.state.set(State::Idle);
+                self.found(record.unwrap_or(None));
+            }
+            State::Collecting(logical) => {
+                let collected = self.scratch.map(|data| self.collect(logical, data.as_ref()));
+                // The collected page must not be reused until the moved
+                // records are on flash, so it is only freed once the new tail
+                // image is written. The image is written even if no records
+                // were moved, to record that the page is no longer in use.
+                self.commit(collected.unwrap_or(None), false);
+            }
+            _ => {}
+        }
+        if self.state.get() == State::Idle {
+            self.run_next();
+        }
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut F::Page, error: flash::Error) {
+        self.tail.replace(write_buffer);
+        if let State::Committing { page, collected, with_record } = self.state.get() {
+            if error != flash::Error::CommandComplete {
+                self.fail(ERROR_FLASH);
+                return;
+            }
+            self.committed(page, collected);
+            self.state.set(State::Idle);
+
+            if with_record {
+                // The request's record is on flash, so it is now current
+                self.request.get().map(|request| {
+                    let offset = HEADER_LEN + self.tail_used.get() - request.record.len();
+                    self.apply(&request.record, self.tail_logical.get(), offset);
+                    self.finish_request(0);
+                });
+            } else {
+                // Garbage collection made room, append the request's record
+                self.append();
+            }
+            if self.state.get() == State::Idle {
+                self.run_next();
+            }
+        }
+    }
+
+    fn erase_complete(&self, _error: flash::Error) {}
+}
@@ -9,16 +9,35 @@ pub mod fm25cl;
 pub mod gpio;
 pub mod isl29035;
 pub mod led;
+pub mod log;
+pub mod process_info;
+pub mod pwm;
+pub mod rng;
 pub mod nrf51822_serialization;
 pub mod timer;
 pub mod tmp006;
 pub mod si7021;
 pub mod spi;
+pub mod spi_slave;
+pub mod virtual_adc;
 pub mod virtual_alarm;
+pub mod virtual_flash;
 pub mod virtual_i2c;
 pub mod virtual_spi;
+pub mod virtual_uart;
 pub mod adc;
+pub mod app_watchdog;
+pub mod app_storage;
+pub mod kv_store;
+mod page_image;
+pub mod i2c_master_driver;
 pub mod i2c_master_slave_driver;
 pub mod lps25hb;
 pub mod tsl2561;
 pub mod fxos8700_cq;
+pub mod temperature;
+pub mod humidity;
+pub mod ambient_light;
+pub mod pressure;
+pub mod acceleration;
+pub mod sensor_readings;
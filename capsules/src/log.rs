@@ -0,0 +1,696 @@
+//! Provide an append-only circular log in flash.
+//!
+//! The log keeps variable-length entries in a range of flash pages, used as a
+//! ring: once all pages are full, the page holding the oldest entries is
+//! overwritten. Every entry gets a sequence number, counting up from the
+//! first entry ever appended, so readers can tell when entries were lost.
+//!
+//! # Layout
+//!
+//! New entries are collected in a RAM page buffer, which is written to the
+//! next page of the ring once it is full or when the log is synced. A page is
+//! never rewritten with more entries, so a write interrupted by power loss
+//! only loses the entries of that page. Every page image starts with a header
+//! holding a magic number, the sequence number of its first entry, the number
+//! of entries, the number of entry bytes in use, the read cursor at the time
+//! the page was written and a checksum over all of these. Entries follow the
+//! header:
+//!
+//! ```text
+//! +-----------+---------------------+------+
+//! | len (LE)  | sequence number (LE)| data |
+//! +-----------+---------------------+------+
+//! ```
+//!
+//! On boot, `initialize` scans the pages to find the one written last, which
+//! gives the next page to write, the next sequence number and the read
+//! cursor. Entries read after the last page write are read again after a
+//! reboot.
+//!
+//! # Reading
+//!
+//! There is a single read cursor, shared by the kernel client and apps, that
+//! holds the sequence number of the next entry to read. If the writer wraps
+//! around and overwrites entries that were not read yet, reading continues at
+//! the oldest entry still stored. Entries that are not yet written to flash
+//! can be read as well.
+//!
+//! # System call interface
+//!
+//! Allow:
+//!
+//!   * 0: buffer holding the entry to append
+//!   * 1: buffer to read entries into
+//!
+//! Subscribe:
+//!
+//!   * 0: read complete, called with the command number (2), the length of
+//!        the entry or a negative error code, and its sequence number. Entries
+//!        longer than the read buffer are truncated.
+//!
+//! Commands:
+//!
+//!   * 0: driver check
+//!   * 1: append the first `data` bytes of the append buffer, returning the
+//!        sequence number of the entry
+//!   * 2: read the next entry
+//!   * 3: sync, starting to write entries collected in RAM to flash
+//!
+//! Errors are -1 for invalid arguments, -2 if the log is busy, -3 if the entry
+//! does not fit in a page, -4 if there are no unread entries and -5 if the
+//! flash failed.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::{self, Flash};
+use page_image::{self, read_u32, write_u32};
+
+/// Maximum number of flash pages the log can manage.
+const MAX_PAGES: usize = 64;
+
+const MAGIC: u32 = 0x4c4f4731; // "LOG1"
+const HEADER_LEN: usize = 20;
+const ENTRY_HEADER_LEN: usize = 6;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The RAM page buffers or the read cursor are in use.
+    Busy,
+    /// The entry does not fit in a page.
+    TooLarge,
+    /// All entries have been read.
+    Empty,
+    /// The flash failed to read a page.
+    Flash,
+}
+
+impl Error {
+    fn code(&self) -> isize {
+        match *self {
+            Error::Busy => -2,
+            Error::TooLarge => -3,
+            Error::Empty => -4,
+            Error::Flash => -5,
+        }
+    }
+}
+
+pub trait Client {
+    /// Called when a read started with `read_next` finishes, with the
+    /// sequence number and contents of the entry.
+    fn read_done(&self, entry: Result<(u32, &[u8]), Error>);
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> usize {
+    (buf[offset] as usize) | (buf[offset + 1] as usize) << 8
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, val: usize) {
+    buf[offset] = val as u8;
+    buf[offset + 1] = (val >> 8) as u8;
+}
+
+#[derive(Copy, Clone)]
+struct Header {
+    first_seq: u32,
+    count: usize,
+    used: usize,
+    cursor: u32,
+}
+
+/// Parses the header of a page image, if the image is valid.
+fn parse_header(page: &[u8]) -> Option<Header> {
+    let used = read_u16(page, 10);
+    if read_u32(page, 0) != MAGIC || HEADER_LEN + used > page.len() ||
+       read_u32(page, 16) != page_image::checksum(page, HEADER_LEN, used) {
+        None
+    } else {
+        Some(Header {
+            first_seq: read_u32(page, 4),
+            count: read_u16(page, 8),
+            used: used,
+            cursor: read_u32(page, 12),
+        })
+    }
+}
+
+fn write_header(page: &mut [u8], header: Header) {
+    write_u32(page, 0, MAGIC);
+    write_u32(page, 4, header.first_seq);
+    write_u16(page, 8, header.count);
+    write_u16(page, 10, header.used);
+    write_u32(page, 12, header.cursor);
+    let checksum = page_image::checksum(page, HEADER_LEN, header.used);
+    write_u32(page, 16, checksum);
+}
+
+/// Finds the entry with sequence number `seq` among the entries of `page`
+/// ending at `end`, returning its offset and length.
+fn find_entry(page: &[u8], end: usize, seq: u32) -> Option<(usize, usize)> {
+    let mut offset = HEADER_LEN;
+    while offset + ENTRY_HEADER_LEN <= end {
+        let len = read_u16(page, offset);
+        let start = offset + ENTRY_HEADER_LEN;
+        if start + len > end {
+            break;
+        }
+        if read_u32(page, offset + 2) == seq {
+            return Some((start, len));
+        }
+        offset = start + len;
+    }
+    None
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum FlashOp {
+    Idle,
+    /// Reading physical page n to recover the log.
+    Scanning(usize),
+    /// Reading physical page n to read entries.
+    Reading(usize),
+    /// Writing the pending page buffer to physical page n.
+    Writing(usize),
+}
+
+#[derive(Copy, Clone)]
+enum Reader {
+    Kernel,
+    App(AppId),
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    append_buffer: Option<AppSlice<Shared, u8>>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct Log<'a, F: Flash + 'static> {
+    driver: &'a F,
+    first_page: usize,
+    num_pages: usize,
+    page_size: usize,
+    op: Cell<FlashOp>,
+    /// Sequence number of the first entry of each page with a valid image.
+    pages: TakeCell<[Option<u32>; MAX_PAGES]>,
+    /// Next physical page of the ring to write.
+    write_page: Cell<usize>,
+    next_seq: Cell<u32>,
+    /// Page buffer collecting new entries.
+    tail: TakeCell<&'static mut F::Page>,
+    tail_first_seq: Cell<u32>,
+    tail_count: Cell<usize>,
+    tail_used: Cell<usize>,
+    /// Full page buffer waiting for (or being written to) flash, and the
+    /// sequence number of its first entry.
+    pending: TakeCell<&'static mut F::Page>,
+    pending_first_seq: Cell<Option<u32>>,
+    /// Page buffer that becomes the tail once the pending page is written.
+    spare: TakeCell<&'static mut F::Page>,
+    read_buffer: TakeCell<&'static mut F::Page>,
+    /// Physical page currently held in `read_buffer`.
+    cached_page: Cell<Option<usize>>,
+    cursor: Cell<u32>,
+    reader: Cell<Option<Reader>>,
+    client: Cell<Option<&'a Client>>,
+    initialized: Cell<bool>,
+    apps: Container<App>,
+}
+
+impl<'a, F: Flash> Log<'a, F> {
+    /// Creates a log over the `num_pages` flash pages starting at
+    /// `first_page`. The three page buffers are used to collect new entries,
+    /// to write full pages and to read pages.
+    pub fn new(driver: &'a F,
+               first_page: usize,
+               num_pages: usize,
+               tail: &'static mut F::Page,
+               spare: &'static mut F::Page,
+               read_buffer: &'static mut F::Page,
+               container: Container<App>)
+               -> Log<'a, F> {
+        Log {
+            driver: driver,
+            first_page: first_page,
+            num_pages: cmp::min(num_pages, MAX_PAGES),
+            page_size: tail.as_ref().len(),
+            op: Cell::new(FlashOp::Idle),
+            pages: TakeCell::new([None; MAX_PAGES]),
+            write_page: Cell::new(0),
+            next_seq: Cell::new(0),
+            tail: TakeCell::new(tail),
+            tail_first_seq: Cell::new(0),
+            tail_count: Cell::new(0),
+            tail_used: Cell::new(0),
+            pending: TakeCell::empty(),
+            pending_first_seq: Cell::new(None),
+            spare: TakeCell::new(spare),
+            read_buffer: TakeCell::new(read_buffer),
+            cached_page: Cell::new(None),
+            cursor: Cell::new(0),
+            reader: Cell::new(None),
+            client: Cell::new(None),
+            initialized: Cell::new(false),
+            apps: container,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a Client) {
+        self.client.set(Some(client));
+    }
+
+    /// Recovers the log from flash. Must be called once by the board; the
+    /// log reports `Busy` until recovery is done.
+    pub fn initialize(&self) {
+        self.pages.map(|pages| for page in pages.iter_mut() {
+            *page = None;
+        });
+        self.cached_page.set(None);
+        self.scan(0);
+    }
+
+    /// Appends an entry, returning its sequence number. The entry is written
+    /// to flash once its page is full or the log is synced.
+    pub fn append(&self, data: &[u8]) -> Result<u32, Error> {
+        if !self.initialized.get() {
+            return Err(Error::Busy);
+        }
+        let len = ENTRY_HEADER_LEN + data.len();
+        if HEADER_LEN + len > self.page_size {
+            return Err(Error::TooLarge);
+        }
+        if HEADER_LEN + self.tail_used.get() + len > self.page_size {
+            try!(self.sync());
+        }
+
+        let seq = self.next_seq.get();
+        let offset = HEADER_LEN + self.tail_used.get();
+        self.tail.map(|tail| {
+            let tail = tail.as_mut();
+            write_u16(tail, offset, data.len());
+            write_u32(tail, offset + 2, seq);
+            let start = offset + ENTRY_HEADER_LEN;
+            tail[start..start + data.len()].copy_from_slice(data);
+        });
+        self.tail_used.set(self.tail_used.get() + len);
+        self.tail_count.set(self.tail_count.get() + 1);
+        self.next_seq.set(seq.wrapping_add(1));
+        Ok(seq)
+    }
+
+    /// Starts writing the entries collected in RAM to flash. Fails with
+    /// `Busy` if the previous page is still being written.
+    pub fn sync(&self) -> Result<(), Error> {
+        if !self.initialized.get() {
+            return Err(Error::Busy);
+        }
+        if self.tail_count.get() == 0 {
+            return Ok(());
+        }
+        let spare = match self.spare.take() {
+            Some(spare) => spare,
+            None => return Err(Error::Busy),
+        };
+        self.tail.take().map(|tail| {
+            write_header(tail.as_mut(),
+                         Header {
+                             first_seq: self.tail_first_seq.get(),
+                             count: self.tail_count.get(),
+                             used: self.tail_used.get(),
+                             cursor: self.cursor.get(),
+                         });
+            self.pending.replace(tail);
+        });
+        self.pending_first_seq.set(Some(self.tail_first_seq.get()));
+        self.tail.replace(spare);
+        self.tail_first_seq.set(self.next_seq.get());
+        self.tail_count.set(0);
+        self.tail_used.set(0);
+        self.do_next_op();
+        Ok(())
+    }
+
+    /// Reads the entry at the read cursor and advances the cursor. The entry
+    /// is passed to the client's `read_done`.
+    pub fn read_next(&self) -> Result<(), Error> {
+        self.start_read(Reader::Kernel)
+    }
+
+    fn start_read(&self, reader: Reader) -> Result<(), Error> {
+        if self.reader.get().is_some() {
+            return Err(Error::Busy);
+        }
+        self.reader.set(Some(reader));
+        self.serve_read();
+        Ok(())
+    }
+
+    // Recovery
+
+    fn scan(&self, page: usize) {
+        if page >= self.num_pages {
+            self.op.set(FlashOp::Idle);
+            self.tail_first_seq.set(self.next_seq.get());
+            self.initialized.set(true);
+            self.do_next_op();
+            return;
+        }
+        self.op.set(FlashOp::Scanning(page));
+        self.read_page(page);
+    }
+
+    fn scanned(&self, page: usize, header: Header) {
+        self.pages.map(|pages| pages[page] = Some(header.first_seq));
+        let end = header.first_seq.wrapping_add(header.count as u32);
+        if end > self.next_seq.get() {
+            // Newest page so far
+            self.next_seq.set(end);
+            self.write_page.set((page + 1) % self.num_pages);
+            self.cursor.set(header.cursor);
+        }
+    }
+
+    // Flash
+
+    fn read_page(&self, page: usize) {
+        self.read_buffer.take().map(|buf| {
+            if let Err((_, buf)) = self.driver.read_page(self.first_page + page, buf) {
+                self.read_buffer.replace(buf);
+                self.read_failed();
+            }
+        });
+    }
+
+    fn read_failed(&self) {
+        match self.op.get() {
+            FlashOp::Scanning(page) => self.scan(page + 1),
+            _ => {
+                self.op.set(FlashOp::Idle);
+                self.finish_read(Error::Flash);
+                self.do_next_op();
+            }
+        }
+    }
+
+    /// Starts writing the pending page, or serves the current reader.
+    fn do_next_op(&self) {
+        if self.op.get() != FlashOp::Idle {
+            return;
+        }
+        match self.pending.take() {
+            Some(buf) => {
+                // The oldest entries are lost as soon as their page is
+                // overwritten
+                let page = self.write_page.get();
+                self.pages.map(|pages| pages[page] = None);
+                if self.cached_page.get() == Some(page) {
+                    self.cached_page.set(None);
+                }
+                self.op.set(FlashOp::Writing(page));
+                if let Err((_, buf)) = self.driver.write_page(self.first_page + page, buf) {
+                    self.op.set(FlashOp::Idle);
+                    self.written(buf, false);
+                }
+            }
+            None => self.serve_read(),
+        }
+    }
+
+    /// Finishes writing the pending page. The entries of the page are
+    /// dropped if the write failed.
+    fn written(&self, buf: &'static mut F::Page, success: bool) {
+        let page = self.write_page.get();
+        if success {
+            let first_seq = self.pending_first_seq.get();
+            self.pages.map(|pages| pages[page] = first_seq);
+        }
+        self.pending_first_seq.set(None);
+        self.write_page.set((page + 1) % self.num_pages);
+        self.spare.replace(buf);
+        self.do_next_op();
+    }
+
+    // Reading
+
+    /// Sequence number of the oldest entry still stored.
+    fn oldest_seq(&self) -> u32 {
+        let oldest = self.pages
+            .map(|pages| pages[..self.num_pages].iter().filter_map(|p| *p).min())
+            .unwrap_or(None);
+        [oldest, self.pending_first_seq.get()]
+            .iter()
+            .filter_map(|seq| *seq)
+            .fold(self.tail_first_seq.get(), cmp::min)
+    }
+
+    /// Sequence number of the first entry stored after `seq`, to skip over
+    /// entries lost to failed writes.
+    fn next_stored_seq(&self, seq: u32) -> u32 {
+        let next = self.pages
+            .map(|pages| {
+                pages[..self.num_pages].iter().filter_map(|p| *p).filter(|s| *s > seq).min()
+            })
+            .unwrap_or(None);
+        let pending = self.pending_first_seq
+            .get()
+            .and_then(|s| if s > seq { Some(s) } else { None });
+        [next, pending]
+            .iter()
+            .filter_map(|seq| *seq)
+            .fold(self.tail_first_seq.get(), cmp::min)
+    }
+
+    /// Physical page holding the entry with sequence number `seq`.
+    fn page_of(&self, seq: u32) -> Option<usize> {
+        self.pages
+            .map(|pages| {
+                pages[..self.num_pages]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, p)| p.and_then(|first| if first <= seq {
+                        Some((first, i))
+                    } else {
+                        None
+                    }))
+                    .max()
+                    .map(|(_, page)| page)
+            })
+            .unwrap_or(None)
+    }
+
+    /// Serves the current reader from RAM or the cached page if possible,
+    /// otherwise starts reading the page holding the entry at the cursor.
+    fn serve_read(&self) {
+        if self.reader.get().is_none() || !self.initialized.get() ||
+           self.read_buffer.is_none() {
+            return;
+        }
+
+        // Skip entries lost to wrap-around
+        let oldest = self.oldest_seq();
+        if self.cursor.get() < oldest {
+            self.cursor.set(oldest);
+        }
+        let cursor = self.cursor.get();
+
+        if cursor >= self.next_seq.get() {
+            self.finish_read(Error::Empty);
+        } else if cursor >= self.tail_first_seq.get() {
+            // Copy the tail, so the reader can append while handling the entry
+            let end = HEADER_LEN + self.tail_used.get();
+            self.tail.map(|tail| {
+                self.read_buffer.map(|buf| {
+                    buf.as_mut()[..end].copy_from_slice(&tail.as_ref()[..end]);
+                });
+            });
+            self.cached_page.set(None);
+            self.deliver(end);
+        } else if self.pending_first_seq.get().map_or(false, |first| cursor >= first) {
+            // Wait for the pending page to be written
+        } else {
+            match self.page_of(cursor) {
+                Some(page) if self.cached_page.get() == Some(page) => {
+                    let end = self.read_buffer
+                        .map(|buf| parse_header(buf.as_ref()).map_or(0, |h| HEADER_LEN + h.used))
+                        .unwrap_or(0);
+                    self.deliver(end);
+                }
+                Some(page) if self.op.get() == FlashOp::Idle => {
+                    self.op.set(FlashOp::Reading(page));
+                    self.read_page(page);
+                }
+                Some(_) => {
+                    // Wait for the flash
+                }
+                None => {
+                    self.cursor.set(self.next_stored_seq(cursor));
+                    self.serve_read();
+                }
+            }
+        }
+    }
+
+    /// Passes the entry at the cursor, found in the first `end` bytes of the
+    /// read buffer, to the reader. Skips to the next stored entry if it is
+    /// missing.
+    fn deliver(&self, end: usize) {
+        let cursor = self.cursor.get();
+        let buf = match self.read_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        match find_entry(buf.as_ref(), end, cursor) {
+            Some((offset, len)) => {
+                let reader = self.reader.get();
+                self.reader.set(None);
+                self.cursor.set(cursor.wrapping_add(1));
+                let entry = &buf.as_ref()[offset..offset + len];
+                match reader {
+                    Some(Reader::Kernel) => {
+                        self.client.get().map(|client| client.read_done(Ok((cursor, entry))));
+                    }
+                    Some(Reader::App(appid)) => {
+                        let _ = self.apps.enter(appid, |app, _| {
+                            app.read_buffer.as_mut().map(|dst| {
+                                for (d, b) in dst.as_mut().iter_mut().zip(entry.iter()) {
+                                    *d = *b;
+                                }
+                            });
+                            app.callback.map(|mut cb| cb.schedule(2, len, cursor as usize));
+                        });
+                    }
+                    None => {}
+                }
+                self.read_buffer.replace(buf);
+                // The kernel client may have started another read
+                self.serve_read();
+            }
+            None => {
+                self.read_buffer.replace(buf);
+                let next = self.next_stored_seq(cursor);
+                self.cursor.set(if next > cursor { next } else { self.next_seq.get() });
+                self.serve_read();
+            }
+        }
+    }
+
+    fn finish_read(&self, error: Error) {
+        let reader = self.reader.get();
+        self.reader.set(None);
+        match reader {
+            Some(Reader::Kernel) => {
+                self.client.get().map(|client| client.read_done(Err(error)));
+            }
+            Some(Reader::App(appid)) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback.map(|mut cb| cb.schedule(2, error.code() as usize, 0));
+                });
+            }
+            None => {}
+        }
+    }
+}
+
+impl<'a, F: Flash> Driver for Log<'a, F> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        self.apps
+            .enter(appid, |app, _| match allow_num {
+                0 => {
+                    app.append_buffer = Some(slice);
+                    0
+                }
+                1 => {
+                    app.read_buffer = Some(slice);
+                    0
+                }
+                _ => -1,
+            })
+            .unwrap_or(-1)
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+            _ => -1,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        let result = match command_num {
+            0 => Ok(0),
+
+            // append
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| match app.append_buffer {
+                        Some(ref slice) if data <= slice.len() => {
+                            Some(self.append(&slice.as_ref()[..data]).map(|seq| seq as isize))
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or(None)
+                    .unwrap_or(Ok(-1))
+            }
+
+            // read next
+            2 => self.start_read(Reader::App(appid)).map(|_| 0),
+
+            // sync
+            3 => self.sync().map(|_| 0),
+
+            _ => Ok(-1),
+        };
+        match result {
+            Ok(ret) => ret,
+            Err(error) => error.code(),
+        }
+    }
+}
+
+impl<'a, F: Flash> flash::Client<F> for Log<'a, F> {
+    fn read_complete(&self, read_buffer: &'static mut F::Page, error: flash::Error) {
+        let header = if error == flash::Error::CommandComplete {
+            parse_header(read_buffer.as_ref())
+        } else {
+            None
+        };
+        self.read_buffer.replace(read_buffer);
+
+        match self.op.get() {
+            FlashOp::Scanning(page) => {
+                header.map(|header| self.scanned(page, header));
+                self.scan(page + 1);
+            }
+            FlashOp::Reading(page) => {
+                self.op.set(FlashOp::Idle);
+                if error != flash::Error::CommandComplete {
+                    self.finish_read(Error::Flash);
+                } else if header.is_some() {
+                    self.cached_page.set(Some(page));
+                } else {
+                    // The page was lost, skip its entries
+                    self.pages.map(|pages| pages[page] = None);
+                }
+                self.do_next_op();
+            }
+            _ => {}
+        }
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut F::Page, error: flash::Error) {
+        self.op.set(FlashOp::Idle);
+        self.written(write_buffer, error == flash::Error::CommandComplete);
+    }
+
+    fn erase_complete(&self, _error: flash::Error) {}
+}
@@ -4,6 +4,7 @@ use kernel::{AppId, Callback, Driver};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::gpio;
 use kernel::hil::i2c;
+use kernel::hil::sensors;
 
 // Buffer to use for I2C messages
 pub static mut BUFFER: [u8; 5] = [0; 5];
@@ -74,6 +75,8 @@ pub struct LPS25HB<'a> {
     i2c: &'a i2c::I2CDevice,
     interrupt_pin: &'a gpio::Pin,
     callback: Cell<Option<Callback>>,
+    pressure_client: Cell<Option<&'static sensors::PressureClient>>,
+    client_pending: Cell<bool>,
     state: Cell<State>,
     buffer: TakeCell<&'static mut [u8]>,
 }
@@ -88,6 +91,8 @@ impl<'a> LPS25HB<'a> {
             i2c: i2c,
             interrupt_pin: interrupt_pin,
             callback: Cell::new(None),
+            pressure_client: Cell::new(None),
+            client_pending: Cell::new(false),
             state: Cell::new(State::Idle),
             buffer: TakeCell::new(buffer),
         }
@@ -134,6 +139,11 @@ impl<'a> i2c::I2CClient for LPS25HB<'a> {
                 self.buffer.replace(buffer);
                 self.i2c.disable();
                 self.state.set(State::Idle);
+
+                // A sensor client asked for a reading while WHO_AM_I was read
+                if self.client_pending.get() {
+                    self.take_measurement();
+                }
             }
             State::TakeMeasurementInit => {
                 buffer[0] = Registers::PressOutXl as u8 | REGISTER_AUTO_INCREMENT;
@@ -163,6 +173,10 @@ impl<'a> i2c::I2CClient for LPS25HB<'a> {
                 let pressure_ubar = (pressure * 1000) / 4096;
 
                 self.callback.get().map(|mut cb| cb.schedule(pressure_ubar as usize, 0, 0));
+                if self.client_pending.get() {
+                    self.client_pending.set(false);
+                    self.pressure_client.get().map(|client| client.pressure_ready(pressure_ubar));
+                }
 
                 buffer[0] = Registers::CtrlReg1 as u8;
                 buffer[1] = 0;
@@ -193,6 +207,21 @@ impl<'a> gpio::Client for LPS25HB<'a> {
     }
 }
 
+impl<'a> sensors::PressureSensor for LPS25HB<'a> {
+    fn set_pressure_client(&self, client: &'static sensors::PressureClient) {
+        self.pressure_client.set(Some(client));
+    }
+
+    fn read_pressure(&self) -> bool {
+        // Only start a measurement if the client isn't already waiting on one
+        if !self.client_pending.get() {
+            self.client_pending.set(true);
+            self.take_measurement();
+        }
+        true
+    }
+}
+
 impl<'a> Driver for LPS25HB<'a> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
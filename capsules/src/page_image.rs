@@ -0,0 +1,25 @@
+//! Helpers for page images kept in flash by the key-value store and the log.
+//!
+//! Both store little-endian integers in their pages and protect every image
+//! with an FNV-1a checksum stored in the last four bytes of its header.
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    (buf[offset] as u32) | (buf[offset + 1] as u32) << 8 | (buf[offset + 2] as u32) << 16 |
+    (buf[offset + 3] as u32) << 24
+}
+
+pub fn write_u32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset] = val as u8;
+    buf[offset + 1] = (val >> 8) as u8;
+    buf[offset + 2] = (val >> 16) as u8;
+    buf[offset + 3] = (val >> 24) as u8;
+}
+
+/// Checksum over the header of `page`, which is `header_len` bytes long and
+/// ends with the checksum itself, and the `used` bytes following it.
+pub fn checksum(page: &[u8], header_len: usize, used: usize) -> u32 {
+    page[..header_len - 4]
+        .iter()
+        .chain(page[header_len..header_len + used].iter())
+        .fold(0x811c9dc5, |h, b| (h ^ (*b as u32)).wrapping_mul(0x01000193))
+}
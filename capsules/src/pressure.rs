@@ -0,0 +1,40 @@
+//! Provide capsule driver for barometric pressure sensors.
+//!
+//! The reading is passed to the callback in microbars. See `sensor_readings`
+//! for the system call interface. No board in the tree has a pressure sensor
+//! yet; one that does should provide the capsule as driver 24, which libtock
+//! expects.
+
+use kernel::{AppId, Callback, Container, Driver};
+use kernel::hil::sensors::{PressureSensor, PressureClient};
+use sensor_readings::{App, SensorReadings};
+
+pub struct PressureDriver<'a> {
+    sensor: &'a PressureSensor,
+    readings: SensorReadings,
+}
+
+impl<'a> PressureDriver<'a> {
+    pub fn new(sensor: &'a PressureSensor, container: Container<App>) -> PressureDriver<'a> {
+        PressureDriver {
+            sensor: sensor,
+            readings: SensorReadings::new(container),
+        }
+    }
+}
+
+impl<'a> PressureClient for PressureDriver<'a> {
+    fn pressure_ready(&self, pressure: u32) {
+        self.readings.reading_done(pressure as usize, 0, 0);
+    }
+}
+
+impl<'a> Driver for PressureDriver<'a> {
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        self.readings.subscribe(subscribe_num, callback)
+    }
+
+    fn command(&self, command_num: usize, _: usize, appid: AppId) -> isize {
+        self.readings.command(command_num, appid, || self.sensor.read_pressure())
+    }
+}
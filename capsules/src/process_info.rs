@@ -0,0 +1,49 @@
+//! Provide capsule driver for reading the scheduler's per-process accounting
+//! counters (CPU time, context switches and system calls).
+//!
+//! Every command takes the process to query as its argument: `0` selects the
+//! calling process, `n` selects the process in slot `n - 1`. Counters are
+//! truncated to the width of the return value.
+
+use kernel::{AppId, Driver};
+use kernel::process;
+
+pub struct ProcessInfo;
+
+impl ProcessInfo {
+    pub const fn new() -> ProcessInfo {
+        ProcessInfo
+    }
+}
+
+impl Driver for ProcessInfo {
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        if command_num == 0 {
+            // number of process slots
+            return process::num_slots() as isize;
+        }
+
+        let idx = if data == 0 { appid.idx() } else { data - 1 };
+        let stats = match process::statistics(idx) {
+            Some(stats) => stats,
+            None => return -1,
+        };
+
+        match command_num {
+            // CPU time in microseconds
+            1 => stats.cpu_time_us as isize,
+
+            // context switches
+            2 => stats.context_switches as isize,
+
+            // timeslice expirations
+            3 => stats.timeslice_expirations as isize,
+
+            // system calls of class `command_num - 4`: yield, subscribe,
+            // command, allow, memop
+            4...8 => stats.syscalls[command_num - 4] as isize,
+
+            _ => -1,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Provide capsule driver for PWM outputs, e.g. to dim LEDs, drive buzzers
+//! or position servos.
+//!
+//! Commands take the index of the pin in the lowest 8 bits of `data` and an
+//! argument in the remaining bits.
+//!
+//!   * 0: get the number of PWM pins
+//!   * 1: set the frequency of the pin to the argument, in Hz
+//!   * 2: set the duty cycle of the pin to the argument, in hundredths of a
+//!        percent (0 to 10000)
+//!   * 3: start the signal on the pin
+//!   * 4: stop the signal on the pin, leaving it low
+//!   * 5: get the highest frequency the pin can generate, in Hz
+
+use kernel::{AppId, Driver};
+use kernel::hil::pwm::{MAX_DUTY_CYCLE, PwmPin};
+
+pub struct Pwm<'a, P: PwmPin + 'a> {
+    pins: &'a [&'a P],
+}
+
+impl<'a, P: PwmPin> Pwm<'a, P> {
+    pub fn new(pins: &'a [&'a P]) -> Pwm<'a, P> {
+        Pwm { pins: pins }
+    }
+}
+
+impl<'a, P: PwmPin> Driver for Pwm<'a, P> {
+    fn command(&self, command_num: usize, data: usize, _: AppId) -> isize {
+        if command_num == 0 {
+            return self.pins.len() as isize;
+        }
+
+        let pin = match self.pins.get(data & 0xff) {
+            Some(pin) => pin,
+            None => return -1,
+        };
+        let arg = data >> 8;
+        match command_num {
+            // set frequency
+            1 => if pin.set_frequency(arg) { 0 } else { -1 },
+
+            // set duty cycle
+            2 => {
+                if arg > MAX_DUTY_CYCLE {
+                    -1
+                } else {
+                    pin.set_duty_cycle(arg);
+                    0
+                }
+            }
+
+            // start
+            3 => {
+                pin.enable();
+                0
+            }
+
+            // stop
+            4 => {
+                pin.disable();
+                0
+            }
+
+            // max frequency
+            5 => pin.max_frequency() as isize,
+
+            _ => -1,
+        }
+    }
+}
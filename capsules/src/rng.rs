@@ -0,0 +1,173 @@
+//! Provide capsule driver for random numbers.
+//!
+//! Apps ask for a number of random bytes, which are written into a buffer they
+//! allowed. Requests of different apps are served from the same kernel
+//! buffer: every time the generator fills it, its bytes are handed out to the
+//! waiting apps in process slot order, and the generator is restarted until
+//! every request is satisfied.
+//!
+//! Allow:
+//!
+//!   * 0: buffer to fill with random bytes
+//!
+//! Subscribe:
+//!
+//!   * 0: request complete, called with the number of bytes written
+//!
+//! Commands:
+//!
+//!   * 0: driver check
+//!   * 1: fill the first `data` bytes of the buffer (or all of it, if it is
+//!        shorter)
+
+use core::cell::Cell;
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::rng::{self, RNG};
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+    /// Bytes requested and bytes written so far.
+    len: usize,
+    written: usize,
+}
+
+pub struct Rng<'a, R: RNG + 'a> {
+    rng: &'a R,
+    buffer: TakeCell<&'static mut [u8]>,
+    busy: Cell<bool>,
+    apps: Container<App>,
+}
+
+impl<'a, R: RNG> Rng<'a, R> {
+    pub fn new(rng: &'a R,
+               buffer: &'static mut [u8],
+               container: Container<App>)
+               -> Rng<'a, R> {
+        Rng {
+            rng: rng,
+            buffer: TakeCell::new(buffer),
+            busy: Cell::new(false),
+            apps: container,
+        }
+    }
+
+    fn start(&self) {
+        if self.busy.get() {
+            return;
+        }
+        self.buffer.take().map(|buf| {
+            let len = buf.len();
+            match self.rng.fill(buf, len) {
+                Ok(()) => self.busy.set(true),
+                Err(buf) => {
+                    self.buffer.replace(buf);
+                }
+            }
+        });
+    }
+}
+
+impl<'a, R: RNG> rng::Client for Rng<'a, R> {
+    fn fill_done(&self, buf: &'static mut [u8], len: usize) {
+        self.busy.set(false);
+
+        let mut offset = 0;
+        let mut waiting = false;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.written >= app.len {
+                    return;
+                }
+                let count = {
+                    let src = &buf[offset..len];
+                    let dst = match app.buffer {
+                        Some(ref mut slice) => &mut slice.as_mut()[app.written..app.len],
+                        None => return,
+                    };
+                    let count = if src.len() < dst.len() { src.len() } else { dst.len() };
+                    dst[..count].copy_from_slice(&src[..count]);
+                    count
+                };
+                offset += count;
+                app.written += count;
+                if app.written >= app.len {
+                    app.callback.map(|mut cb| cb.schedule(app.len, 0, 0));
+                } else {
+                    waiting = true;
+                }
+            });
+        }
+
+        self.buffer.replace(buf);
+        if waiting {
+            self.start();
+        }
+    }
+}
+
+impl<'a, R: RNG> Driver for Rng<'a, R> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        match allow_num {
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.buffer = Some(slice);
+                        app.len = 0;
+                        app.written = 0;
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+            _ => -1,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-1)
+            }
+            _ => -1,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        match command_num {
+            0 => 0,
+
+            // fill buffer
+            1 => {
+                let ret = self.apps
+                    .enter(appid, |app, _| {
+                        if app.written < app.len {
+                            return -2;
+                        }
+                        match app.buffer {
+                            Some(_) if data == 0 => -1,
+                            Some(ref slice) => {
+                                app.len = if data < slice.len() { data } else { slice.len() };
+                                app.written = 0;
+                                0
+                            }
+                            None => -1,
+                        }
+                    })
+                    .unwrap_or(-3);
+                if ret == 0 {
+                    self.start();
+                }
+                ret
+            }
+
+            _ => -1,
+        }
+    }
+}
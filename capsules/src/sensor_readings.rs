@@ -0,0 +1,91 @@
+//! Share the readings of a sensor between apps.
+//!
+//! Used by the board-independent sensor drivers (`temperature`, `humidity`,
+//! `ambient_light`, `pressure` and `acceleration`), which give apps the same
+//! interface to whichever chip a board has. They all offer:
+//!
+//! Subscribe:
+//!
+//!   * 0: reading done, called with the reading in the units of the driver
+//!
+//! Commands:
+//!
+//!   * 0: driver check
+//!   * 1: take a reading
+//!
+//! A reading requested while another one is underway is not started again:
+//! every app waiting when a reading completes gets that reading.
+
+use core::cell::Cell;
+use kernel::{AppId, Callback, Container};
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct SensorReadings {
+    apps: Container<App>,
+    busy: Cell<bool>,
+}
+
+impl SensorReadings {
+    pub fn new(container: Container<App>) -> SensorReadings {
+        SensorReadings {
+            apps: container,
+            busy: Cell::new(false),
+        }
+    }
+
+    pub fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-3)
+            }
+            _ => -1,
+        }
+    }
+
+    /// Handles `command_num` for `appid`. `read` starts a reading on the
+    /// sensor and returns false if it cannot take one.
+    pub fn command<F: Fn() -> bool>(&self, command_num: usize, appid: AppId, read: F) -> isize {
+        match command_num {
+            0 => 0,
+
+            // take a reading, or share the one underway
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        if !self.busy.get() {
+                            if !read() {
+                                return -1;
+                            }
+                            self.busy.set(true);
+                        }
+                        app.pending = true;
+                        0
+                    })
+                    .unwrap_or(-3)
+            }
+
+            _ => -1,
+        }
+    }
+
+    /// Passes a completed reading to every app waiting for one.
+    pub fn reading_done(&self, r0: usize, r1: usize, r2: usize) {
+        self.busy.set(false);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| if app.pending {
+                app.pending = false;
+                app.callback.map(|mut cb| cb.schedule(r0, r1, r2));
+            });
+        }
+    }
+}
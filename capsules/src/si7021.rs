@@ -1,8 +1,10 @@
 use core::cell::Cell;
+use core::cmp;
 use kernel::{AppId, Callback, Driver};
 
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::i2c;
+use kernel::hil::sensors;
 use kernel::hil::time;
 use kernel::hil::time::Frequency;
 
@@ -51,6 +53,10 @@ pub struct SI7021<'a, A: time::Alarm + 'a> {
     i2c: &'a i2c::I2CDevice,
     alarm: &'a A,
     callback: Cell<Option<Callback>>,
+    temperature_client: Cell<Option<&'static sensors::TemperatureClient>>,
+    humidity_client: Cell<Option<&'static sensors::HumidityClient>>,
+    temperature_pending: Cell<bool>,
+    humidity_pending: Cell<bool>,
     state: Cell<State>,
     buffer: TakeCell<&'static mut [u8]>,
 }
@@ -62,6 +68,10 @@ impl<'a, A: time::Alarm + 'a> SI7021<'a, A> {
             i2c: i2c,
             alarm: alarm,
             callback: Cell::new(None),
+            temperature_client: Cell::new(None),
+            humidity_client: Cell::new(None),
+            temperature_pending: Cell::new(false),
+            humidity_pending: Cell::new(false),
             state: Cell::new(State::Idle),
             buffer: TakeCell::new(buffer),
         }
@@ -89,6 +99,16 @@ impl<'a, A: time::Alarm + 'a> SI7021<'a, A> {
             self.state.set(State::TakeMeasurementInit);
         });
     }
+
+    /// Starts a measurement for a sensor client unless one is already
+    /// underway, in which case that measurement serves the client too.
+    fn start_client_measurement(&self) {
+        let measuring = self.state.get() != State::Idle || self.buffer.is_none() ||
+                        self.alarm.is_armed();
+        if !measuring {
+            self.take_measurement();
+        }
+    }
 }
 
 impl<'a, A: time::Alarm + 'a> i2c::I2CClient for SI7021<'a, A> {
@@ -120,6 +140,11 @@ impl<'a, A: time::Alarm + 'a> i2c::I2CClient for SI7021<'a, A> {
                 self.buffer.replace(buffer);
                 self.i2c.disable();
                 self.state.set(State::Idle);
+
+                // A sensor client asked for a reading while the ID was read
+                if self.temperature_pending.get() || self.humidity_pending.get() {
+                    self.take_measurement();
+                }
             }
             State::TakeMeasurementInit => {
 
@@ -159,6 +184,17 @@ impl<'a, A: time::Alarm + 'a> i2c::I2CClient for SI7021<'a, A> {
                 self.buffer.replace(buffer);
                 self.i2c.disable();
                 self.state.set(State::Idle);
+
+                if self.temperature_pending.get() {
+                    self.temperature_pending.set(false);
+                    self.temperature_client.get().map(|c| c.temperature_ready(temp as i32));
+                }
+                if self.humidity_pending.get() {
+                    self.humidity_pending.set(false);
+                    // Readings just below 0% come out negative
+                    let humidity = cmp::max(((humidity_raw * 125 * 100) / 65536) as i32 - 600, 0);
+                    self.humidity_client.get().map(|c| c.humidity_ready(humidity as u32));
+                }
             }
             _ => {}
         }
@@ -177,6 +213,30 @@ impl<'a, A: time::Alarm + 'a> time::Client for SI7021<'a, A> {
     }
 }
 
+impl<'a, A: time::Alarm + 'a> sensors::TemperatureSensor for SI7021<'a, A> {
+    fn set_temperature_client(&self, client: &'static sensors::TemperatureClient) {
+        self.temperature_client.set(Some(client));
+    }
+
+    fn read_temperature(&self) -> bool {
+        self.temperature_pending.set(true);
+        self.start_client_measurement();
+        true
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> sensors::HumiditySensor for SI7021<'a, A> {
+    fn set_humidity_client(&self, client: &'static sensors::HumidityClient) {
+        self.humidity_client.set(Some(client));
+    }
+
+    fn read_humidity(&self) -> bool {
+        self.humidity_pending.set(true);
+        self.start_client_measurement();
+        true
+    }
+}
+
 impl<'a, A: time::Alarm + 'a> Driver for SI7021<'a, A> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
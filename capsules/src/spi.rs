@@ -1,11 +1,29 @@
+//! Provides userspace with access to SPI devices on a shared bus.
+//!
+//! Apps share the bus with each other and with kernel drivers through
+//! `virtual_spi::MuxSPIMaster`. The board hands the capsule one virtual
+//! device per chip select. Each app chooses its own chip select, rate, clock
+//! polarity and phase, which are applied to each of its transfers. Transfers
+//! of different apps are queued and run one at a time.
+//!
+//! Allow 0 sets the buffer read into, which is optional, and allow 1 the
+//! buffer written from. Subscribe 0 is called with the length of a transfer
+//! once it is complete.
+//!
+//! An app that holds its chip select low between transfers has the bus to
+//! itself among apps until it releases the chip select, though transfers of
+//! kernel drivers may still run in between.
+
 use core::cell::Cell;
 use core::cmp;
-use kernel::{AppId, Driver, Callback, AppSlice, Shared};
+use kernel::{AppId, AppSlice, Callback, Container, Driver, Shared};
 use kernel::common::take_cell::TakeCell;
-use kernel::hil::spi::{SpiMaster, SpiMasterClient};
+use kernel::hil::spi::{SPIMasterDevice, SpiMasterClient};
 use kernel::hil::spi::ClockPhase;
 use kernel::hil::spi::ClockPolarity;
 
+/// Rate of apps that did not set one, in bps.
+pub const DEFAULT_RATE: u32 = 1000000;
 
 // SPI operations are handled by coping into a kernel buffer for
 // writes and copying out of a kernel buffer for reads.
@@ -14,33 +32,58 @@ use kernel::hil::spi::ClockPolarity;
 // the driver issues multiple HAL operations. The len field
 // of an application keeps track of the length of the desired
 // operation, while the index variable keeps track of the
-// index an ongoing operation is at in the buffers.
+// index an ongoing operation is at in the buffers. A non-zero
+// len marks a queued or ongoing transfer.
 
-struct App {
+pub struct App {
     callback: Option<Callback>,
     app_read: Option<AppSlice<Shared, u8>>,
     app_write: Option<AppSlice<Shared, u8>>,
+    chip_select: usize,
+    polarity: ClockPolarity,
+    phase: ClockPhase,
+    rate: u32,
     len: usize,
     index: usize,
 }
 
-pub struct Spi<'a, S: SpiMaster + 'a> {
-    spi_master: &'a mut S,
-    busy: Cell<bool>,
-    app: TakeCell<App>,
-    chip_selects: &'a [S::ChipSelect],
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            app_read: None,
+            app_write: None,
+            chip_select: 0,
+            polarity: ClockPolarity::IdleLow,
+            phase: ClockPhase::SampleLeading,
+            rate: DEFAULT_RATE,
+            len: 0,
+            index: 0,
+        }
+    }
+}
+
+pub struct Spi<'a> {
+    devices: &'a [&'a SPIMasterDevice],
+    apps: Container<App>,
+    /// The app whose transfer is on the bus.
+    current: Cell<Option<AppId>>,
+    /// The app holding its chip select low, whose transfers go first.
+    holder: Cell<Option<AppId>>,
     kernel_read: TakeCell<&'static mut [u8]>,
     kernel_write: TakeCell<&'static mut [u8]>,
     kernel_len: Cell<usize>,
 }
 
-impl<'a, S: SpiMaster> Spi<'a, S> {
-    pub fn new(spi_master: &'a mut S, chip_selects: &'a [S::ChipSelect]) -> Spi<'a, S> {
+impl<'a> Spi<'a> {
+    /// `devices` holds one virtual device per chip select, in the order apps
+    /// select them.
+    pub fn new(devices: &'a [&'a SPIMasterDevice], container: Container<App>) -> Spi<'a> {
         Spi {
-            spi_master: spi_master,
-            busy: Cell::new(false),
-            app: TakeCell::empty(),
-            chip_selects: chip_selects,
+            devices: devices,
+            apps: container,
+            current: Cell::new(None),
+            holder: Cell::new(None),
             kernel_len: Cell::new(0),
             kernel_read: TakeCell::empty(),
             kernel_write: TakeCell::empty(),
@@ -54,9 +97,48 @@ impl<'a, S: SpiMaster> Spi<'a, S> {
         self.kernel_write.replace(write);
     }
 
+    /// Starts the next queued transfer if the bus is free. While an app
+    /// holds its chip select low, only its transfers are started.
+    fn do_next_transfer(&self) {
+        if self.current.get().is_some() {
+            return;
+        }
+        match self.holder.get() {
+            Some(holder) => {
+                let alive = self.apps
+                    .enter(holder, |app, _| if app.len > 0 {
+                        self.current.set(Some(holder));
+                        self.do_next_read_write(app, holder);
+                    })
+                    .is_ok();
+                if !alive {
+                    // The holder is gone, so let the others go ahead
+                    self.holder.set(None);
+                    self.do_next_transfer();
+                }
+            }
+            None => {
+                for cntr in self.apps.iter() {
+                    if self.current.get().is_some() {
+                        break;
+                    }
+                    cntr.enter(|app, _| if app.len > 0 {
+                        let appid = app.appid();
+                        self.current.set(Some(appid));
+                        self.do_next_read_write(app, appid);
+                    });
+                }
+            }
+        }
+    }
+
+    fn is_holder(&self, appid: AppId) -> bool {
+        self.holder.get() == Some(appid)
+    }
+
     // Assumes checks for busy/etc. already done
     // Updates app.index to be index + length of op
-    fn do_next_read_write(&self, app: &mut App) {
+    fn do_next_read_write(&self, app: &mut App, appid: AppId) {
         let start = app.index;
         let len = cmp::min(app.len - start, self.kernel_len.get());
         let end = start + len;
@@ -70,52 +152,37 @@ impl<'a, S: SpiMaster> Spi<'a, S> {
             });
         });
 
-        self.spi_master.read_write_bytes(self.kernel_write.take().unwrap(),
-                                         self.kernel_read.take(),
-                                         len);
+        let device = self.devices[app.chip_select];
+        device.configure(app.polarity, app.phase, app.rate);
+        if self.is_holder(appid) {
+            device.hold_low();
+        } else {
+            device.release_low();
+        }
+        self.kernel_write.take().map(|kwbuf| {
+            device.read_write_bytes(kwbuf, self.kernel_read.take(), len);
+        });
     }
 }
 
-impl<'a, S: SpiMaster> Driver for Spi<'a, S> {
-    fn allow(&self, _appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+impl<'a> Driver for Spi<'a> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
         match allow_num {
             0 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            callback: None,
-                            app_read: Some(slice),
-                            app_write: None,
-                            len: 0,
-                            index: 0,
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_read = Some(slice);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                0
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.app_read = Some(slice);
+                        0
+                    })
+                    .unwrap_or(-3)
             }
             1 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            callback: None,
-                            app_read: None,
-                            app_write: Some(slice),
-                            len: 0,
-                            index: 0,
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_write = Some(slice);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                0
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.app_write = Some(slice);
+                        0
+                    })
+                    .unwrap_or(-3)
             }
             _ => -1,
         }
@@ -125,139 +192,156 @@ impl<'a, S: SpiMaster> Driver for Spi<'a, S> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
             0 /* read_write */ => {
-                let appc = match self.app.take() {
-                    None => App {
-                        callback: Some(callback),
-                        app_read: None,
-                        app_write: None,
-                        len: 0,
-                        index: 0,
-                    },
-                    Some(mut appc) => {
-                        appc.callback = Some(callback);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                0
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        0
+                    })
+                    .unwrap_or(-3)
             },
             _ => -1
         }
     }
     // 0: read/write a single byte (blocking)
+    //   - no longer supported, as the bus is shared; use a
+    //     one-byte transfer
     // 1: read/write buffers
     //   - requires write buffer registered with allow
     //   - read buffer optional
+    //   - queued behind transfers of other apps
+    //   - -2 if the app already has a transfer queued
     // 2: set chip select
     //   - selects which peripheral (CS line) the SPI should
-    //     activate
-    //   - valid values are 0-3 for SAM4L
-    //   - invalid value will result in CS 0
+    //     activate for this app's transfers
+    //   - valid values are 0 to the number of devices the
+    //     board provides, minus one
     // 3: get chip select
-    //   - returns current selected peripheral
-    //   - If none selected, returns 255
-    // 4: set rate on current peripheral
+    //   - returns the chip select of this app
+    // 4: set rate for this app
     //   - parameter in bps
-    // 5: get rate on current peripheral
-    //   - value in bps
-    // 6: set clock phase on current peripheral
+    //   - the rate the hardware achieves may be lower
+    // 5: get rate for this app
+    //   - value in bps, as requested
+    // 6: set clock phase for this app
     //   - 0 is sample leading
     //   - non-zero is sample trailing
-    // 7: get clock phase on current peripheral
+    // 7: get clock phase for this app
     //   - 0 is sample leading
     //   - non-zero is sample trailing
-    // 8: set clock polarity on current peripheral
+    // 8: set clock polarity for this app
     //   - 0 is idle low
     //   - non-zero is idle high
-    // 9: get clock polarity on current peripheral
+    // 9: get clock polarity for this app
     //   - 0 is idle low
     //   - non-zero is idle high
     // 10: hold CS line low between transfers
-    //   - set CSAAT bit of control register
+    //   - set CSAAT bit of control register for this app's
+    //     transfers
+    //   - transfers of other apps wait until the CS line is
+    //     released
+    //   - -2 if another app holds its CS line low
     // 11: release CS line (high) between transfers
     //   - clear CSAAT bit of control register
     //
-    // x: lock spi
-    //   - if you perform an operation without the lock,
-    //     it implicitly acquires the lock before the
-    //     operation and releases it after
-    //   - while an app holds the lock no other app can issue
-    //     operations on SPI (they are buffered)
-    // x+1: unlock spi
-    //   - does nothing if lock not held
-    //
 
-    fn command(&self, cmd_num: usize, arg1: usize, _: AppId) -> isize {
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> isize {
         match cmd_num {
-            0 /* read_write_byte */ => {
-                self.spi_master.read_write_byte(arg1 as u8) as isize
-            },
             1 /* read_write_bytes */ => {
-                if self.busy.get() {
-                    return -1;
+                let result = self.apps
+                    .enter(appid, |app, _| {
+                        if app.len > 0 {
+                            return -2;
+                        }
+                        if app.chip_select >= self.devices.len() {
+                            return -1;
+                        }
+                        let mut mlen = 0;
+                        // If write buffer too small, return
+                        app.app_write.as_mut().map(|w| {
+                            mlen = w.len();
+                        });
+                        app.app_read.as_mut().map(|r| {
+                            mlen = cmp::min(mlen, r.len());
+                        });
+                        if arg1 > 0 && mlen >= arg1 {
+                            app.len = arg1;
+                            app.index = 0;
+                            0
+                        } else {
+                            -1
+                        }
+                    })
+                    .unwrap_or(-3);
+                if result == 0 {
+                    self.do_next_transfer();
                 }
-                let mut result = -1;
-                self.app.map(|app| {
-                    let mut mlen = 0;
-                    // If write buffer too small, return
-                    app.app_write.as_mut().map(|w| {
-                        mlen = w.len();
-                    });
-                    app.app_read.as_mut().map(|r| {
-                        mlen = cmp::min(mlen, r.len());
-                    });
-                    if mlen >= arg1 {
-                        app.len = arg1;
-                        app.index = 0;
-                        self.busy.set(true);
-                        self.do_next_read_write(app);
-                        result = 0;
-                    }
-                });
-                return result;
+                result
             }
             2 /* set chip select */ => {
-                let cs = arg1;
-                self.chip_selects.get(cs).map_or(-1, |cs_line| {
-                    self.spi_master.specify_chip_select(*cs_line);
-                    0
-                })
+                if arg1 >= self.devices.len() {
+                    return -1;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.chip_select = arg1;
+                        0
+                    })
+                    .unwrap_or(-3)
             }
             3 /* get chip select */ => {
-                0
+                self.apps.enter(appid, |app, _| app.chip_select as isize).unwrap_or(-3)
             }
             4 /* set baud rate */ => {
-                self.spi_master.set_rate(arg1 as u32) as isize
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.rate = arg1 as u32;
+                        0
+                    })
+                    .unwrap_or(-3)
             }
             5 /* get baud rate */ => {
-                self.spi_master.get_rate() as isize
+                self.apps.enter(appid, |app, _| app.rate as isize).unwrap_or(-3)
             }
             6 /* set phase */ => {
-                match arg1 {
-                    0 => self.spi_master.set_phase(ClockPhase::SampleLeading),
-                    _ => self.spi_master.set_phase(ClockPhase::SampleTrailing),
-                };
-                0
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.phase = match arg1 {
+                            0 => ClockPhase::SampleLeading,
+                            _ => ClockPhase::SampleTrailing,
+                        };
+                        0
+                    })
+                    .unwrap_or(-3)
             }
             7 /* get phase */ => {
-                self.spi_master.get_phase() as isize
+                self.apps.enter(appid, |app, _| app.phase as isize).unwrap_or(-3)
             }
             8 /* set polarity */ => {
-                match arg1 {
-                    0 => self.spi_master.set_clock(ClockPolarity::IdleLow),
-                    _ => self.spi_master.set_clock(ClockPolarity::IdleHigh),
-                };
-                0
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.polarity = match arg1 {
+                            0 => ClockPolarity::IdleLow,
+                            _ => ClockPolarity::IdleHigh,
+                        };
+                        0
+                    })
+                    .unwrap_or(-3)
             }
             9 /* get polarity */ => {
-                self.spi_master.get_clock() as isize
+                self.apps.enter(appid, |app, _| app.polarity as isize).unwrap_or(-3)
             }
             10 /* hold low */ => {
-                self.spi_master.hold_low();
+                if self.holder.get().is_some() && !self.is_holder(appid) {
+                    return -2;
+                }
+                self.holder.set(Some(appid));
                 0
             }
             11 /* release low */ => {
-                self.spi_master.release_low();
+                if self.is_holder(appid) {
+                    self.holder.set(None);
+                    self.do_next_transfer();
+                }
                 0
             }
             _ => -1
@@ -265,36 +349,46 @@ impl<'a, S: SpiMaster> Driver for Spi<'a, S> {
     }
 }
 
-impl<'a, S: SpiMaster> SpiMasterClient for Spi<'a, S> {
+impl<'a> SpiMasterClient for Spi<'a> {
     fn read_write_done(&self,
                        writebuf: &'static mut [u8],
                        readbuf: Option<&'static mut [u8]>,
                        length: usize) {
-        self.app.map(move |app| {
-            if app.app_read.is_some() {
-                let src = readbuf.as_ref().unwrap();
-                let dest = app.app_read.as_mut().unwrap();
-                let start = app.index - length;
-                let end = start + length;
+        self.kernel_read.put(readbuf);
+        self.kernel_write.replace(writebuf);
 
-                let d = &mut dest.as_mut()[start..end];
-                for (i, c) in src[0..length].iter().enumerate() {
-                    d[i] = *c;
-                }
-            }
+        self.current.get().map(|appid| {
+            let done = self.apps
+                .enter(appid, |app, _| {
+                    let start = app.index - length;
+                    let end = start + length;
+                    self.kernel_read.map(|src| {
+                        app.app_read.as_mut().map(|dest| if dest.len() >= end {
+                            let d = &mut dest.as_mut()[start..end];
+                            for (i, c) in src[0..length].iter().enumerate() {
+                                d[i] = *c;
+                            }
+                        });
+                    });
 
-            self.kernel_read.put(readbuf);
-            self.kernel_write.replace(writebuf);
+                    if app.index == app.len {
+                        let len = app.len;
+                        app.len = 0;
+                        app.index = 0;
+                        app.callback.take().map(|mut cb| {
+                            cb.schedule(len, 0, 0);
+                        });
+                        true
+                    } else {
+                        self.do_next_read_write(app, appid);
+                        false
+                    }
+                })
+                .unwrap_or(true);
 
-            if app.index == app.len {
-                self.busy.set(false);
-                app.len = 0;
-                app.index = 0;
-                app.callback.take().map(|mut cb| {
-                    cb.schedule(app.len, 0, 0);
-                });
-            } else {
-                self.do_next_read_write(app);
+            if done {
+                self.current.set(None);
+                self.do_next_transfer();
             }
         });
     }
@@ -0,0 +1,235 @@
+//! Provides userspace with access to an SPI bus on which this device is a
+//! slave, for boards whose SPI master is another processor.
+//!
+//! The master decides when transfers happen, so an app sets up buffers in
+//! advance and is told when the master selects the device and when a
+//! transfer is over. A transfer ends after the requested number of bytes or
+//! when the master deselects the device, whichever comes first. Outside of
+//! transfers the device sends the write byte set with command 1.
+//!
+//! Only one app can use the bus, as there is a single chip select: the first
+//! app to call the driver owns it until it exits, and every system call of
+//! other apps returns -2 meanwhile. Transfers are copied through kernel
+//! buffers and cannot be longer than them. No board in the tree uses its SPI
+//! as a slave yet; one that does should provide the capsule as driver 19,
+//! which libtock expects.
+//!
+//! Allow 0 sets the buffer received bytes are copied into, allow 1 the buffer
+//! of bytes to send. Either may be left out of a transfer by allowing an
+//! empty buffer.
+//!
+//! Subscribe 0 is called with the number of bytes exchanged when a transfer
+//! ends. Subscribe 1 is called when the master selects the device while no
+//! transfer is set up.
+//!
+//! Commands:
+//!
+//! - 0: set up a transfer of `data` bytes; -1 if both buffers are empty or
+//!   one is too short, -2 if a transfer is already set up
+//! - 1: set the byte sent outside of transfers
+//! - 2: set the clock phase, 0 to sample on the leading edge and non-zero to
+//!   sample on the trailing edge
+//! - 3: get the clock phase
+//! - 4: set the clock polarity, 0 for idle low and non-zero for idle high
+//! - 5: get the clock polarity
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Driver, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::spi;
+use kernel::hil::spi::{ClockPhase, ClockPolarity};
+
+pub static mut READ_BUF: [u8; 64] = [0; 64];
+pub static mut WRITE_BUF: [u8; 64] = [0; 64];
+
+#[derive(Default)]
+struct App {
+    callback: Option<Callback>,
+    selected_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct SpiSlave<'a, S: spi::SpiSlave + 'a> {
+    spi_slave: &'a S,
+    busy: Cell<bool>,
+    owner: Cell<Option<AppId>>,
+    app: TakeCell<App>,
+    kernel_read: TakeCell<&'static mut [u8]>,
+    kernel_write: TakeCell<&'static mut [u8]>,
+    kernel_len: usize,
+}
+
+impl<'a, S: spi::SpiSlave> SpiSlave<'a, S> {
+    pub fn new(spi_slave: &'a S,
+               read_buffer: &'static mut [u8],
+               write_buffer: &'static mut [u8])
+               -> SpiSlave<'a, S> {
+        SpiSlave {
+            spi_slave: spi_slave,
+            busy: Cell::new(false),
+            owner: Cell::new(None),
+            app: TakeCell::new(App::default()),
+            kernel_len: cmp::min(read_buffer.len(), write_buffer.len()),
+            kernel_read: TakeCell::new(read_buffer),
+            kernel_write: TakeCell::new(write_buffer),
+        }
+    }
+
+    /// Makes `appid` the owner of the bus unless another app that is still
+    /// alive owns it. Returns false if it does.
+    fn claim(&self, appid: AppId) -> bool {
+        match self.owner.get() {
+            Some(owner) if owner == appid => true,
+            Some(owner) if owner.is_live() => false,
+            _ => {
+                // Drop the callbacks and buffers of an owner that exited
+                self.owner.set(Some(appid));
+                self.app.map(|app| *app = App::default());
+                true
+            }
+        }
+    }
+
+    fn start_transfer(&self, app: &mut App, len: usize) -> isize {
+        // Empty buffers are left out of the transfer
+        let reading = app.app_read.as_ref().map_or(false, |slice| slice.len() > 0);
+        let writing = app.app_write.as_ref().map_or(false, |slice| slice.len() > 0);
+        if !reading && !writing {
+            return -1;
+        }
+        let too_short = |slice: &Option<AppSlice<Shared, u8>>| {
+            slice.as_ref().map_or(false, |slice| slice.len() > 0 && slice.len() < len)
+        };
+        if len > self.kernel_len || too_short(&app.app_read) || too_short(&app.app_write) {
+            return -1;
+        }
+
+        let write_buffer = if writing {
+            app.app_write.as_ref().and_then(|src| {
+                self.kernel_write.take().map(|kwbuf| {
+                    kwbuf[..len].copy_from_slice(&src.as_ref()[..len]);
+                    kwbuf
+                })
+            })
+        } else {
+            None
+        };
+        let read_buffer = if reading {
+            self.kernel_read.take()
+        } else {
+            None
+        };
+
+        self.busy.set(true);
+        if self.spi_slave.read_write_bytes(write_buffer, read_buffer, len) {
+            0
+        } else {
+            self.busy.set(false);
+            -2
+        }
+    }
+}
+
+impl<'a, S: spi::SpiSlave> Driver for SpiSlave<'a, S> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> isize {
+        if !self.claim(appid) {
+            return -2;
+        }
+        match allow_num {
+            0 => {
+                self.app.map(|app| app.app_read = Some(slice));
+                0
+            }
+            1 => {
+                self.app.map(|app| app.app_write = Some(slice));
+                0
+            }
+            _ => -1,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        if !self.claim(callback.app_id()) {
+            return -2;
+        }
+        match subscribe_num {
+            0 /* transfer done */ => {
+                self.app.map(|app| app.callback = Some(callback));
+                0
+            }
+            1 /* chip selected */ => {
+                self.app.map(|app| app.selected_callback = Some(callback));
+                0
+            }
+            _ => -1,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> isize {
+        if !self.claim(appid) {
+            return -2;
+        }
+        match command_num {
+            0 /* read_write_bytes */ => {
+                if self.busy.get() {
+                    return -2;
+                }
+                self.app.map_or(-1, |app| self.start_transfer(app, data))
+            }
+            1 /* set write byte */ => {
+                self.spi_slave.set_write_byte(data as u8);
+                0
+            }
+            2 /* set phase */ => {
+                match data {
+                    0 => self.spi_slave.set_phase(ClockPhase::SampleLeading),
+                    _ => self.spi_slave.set_phase(ClockPhase::SampleTrailing),
+                };
+                0
+            }
+            3 /* get phase */ => {
+                self.spi_slave.get_phase() as isize
+            }
+            4 /* set polarity */ => {
+                match data {
+                    0 => self.spi_slave.set_clock(ClockPolarity::IdleLow),
+                    _ => self.spi_slave.set_clock(ClockPolarity::IdleHigh),
+                };
+                0
+            }
+            5 /* get polarity */ => {
+                self.spi_slave.get_clock() as isize
+            }
+            _ => -1,
+        }
+    }
+}
+
+impl<'a, S: spi::SpiSlave> spi::SpiSlaveClient for SpiSlave<'a, S> {
+    fn chip_selected(&self) {
+        self.app.map(|app| {
+            app.selected_callback.as_mut().map(|cb| cb.schedule(0, 0, 0));
+        });
+    }
+
+    fn read_write_done(&self,
+                       writebuf: Option<&'static mut [u8]>,
+                       readbuf: Option<&'static mut [u8]>,
+                       length: usize) {
+        self.app.map(move |app| {
+            readbuf.as_ref().map(|src| {
+                app.app_read.as_mut().map(|dest| {
+                    let len = cmp::min(length, dest.len());
+                    dest.as_mut()[..len].copy_from_slice(&src[..len]);
+                });
+            });
+
+            self.kernel_read.put(readbuf);
+            self.kernel_write.put(writebuf);
+            self.busy.set(false);
+            app.callback.as_mut().map(|cb| cb.schedule(length, 0, 0));
+        });
+    }
+}
@@ -0,0 +1,39 @@
+//! Provide capsule driver for temperature sensors.
+//!
+//! The reading is passed to the callback as a signed temperature in
+//! hundredths of degrees Celsius. See `sensor_readings` for the system call
+//! interface.
+
+use kernel::{AppId, Callback, Container, Driver};
+use kernel::hil::sensors::{TemperatureSensor, TemperatureClient};
+use sensor_readings::{App, SensorReadings};
+
+pub struct TemperatureDriver<'a> {
+    sensor: &'a TemperatureSensor,
+    readings: SensorReadings,
+}
+
+impl<'a> TemperatureDriver<'a> {
+    pub fn new(sensor: &'a TemperatureSensor, container: Container<App>) -> TemperatureDriver<'a> {
+        TemperatureDriver {
+            sensor: sensor,
+            readings: SensorReadings::new(container),
+        }
+    }
+}
+
+impl<'a> TemperatureClient for TemperatureDriver<'a> {
+    fn temperature_ready(&self, temperature: i32) {
+        self.readings.reading_done(temperature as usize, 0, 0);
+    }
+}
+
+impl<'a> Driver for TemperatureDriver<'a> {
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
+        self.readings.subscribe(subscribe_num, callback)
+    }
+
+    fn command(&self, command_num: usize, _: usize, appid: AppId) -> isize {
+        self.readings.command(command_num, appid, || self.sensor.read_temperature())
+    }
+}
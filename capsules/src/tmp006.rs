@@ -4,6 +4,7 @@ use kernel::common::math::{sqrtf32, get_errno};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::gpio::{Pin, InterruptMode, Client};
 use kernel::hil::i2c;
+use kernel::hil::sensors;
 
 pub static mut BUFFER: [u8; 3] = [0; 3];
 
@@ -91,6 +92,9 @@ pub struct TMP006<'a> {
     sampling_period: Cell<u8>,
     repeated_mode: Cell<bool>,
     callback: Cell<Option<Callback>>,
+    temperature_client: Cell<Option<&'static sensors::TemperatureClient>>,
+    client_pending: Cell<bool>,
+    sensing: Cell<bool>,
     protocol_state: Cell<ProtocolState>,
     buffer: TakeCell<&'static mut [u8]>,
 }
@@ -108,6 +112,9 @@ impl<'a> TMP006<'a> {
             sampling_period: Cell::new(DEFAULT_SAMPLING_RATE),
             repeated_mode: Cell::new(false),
             callback: Cell::new(None),
+            temperature_client: Cell::new(None),
+            client_pending: Cell::new(false),
+            sensing: Cell::new(false),
             protocol_state: Cell::new(ProtocolState::Idle),
             buffer: TakeCell::new(buffer),
         }
@@ -124,6 +131,7 @@ impl<'a> TMP006<'a> {
             buf[1] = ((config & 0xFF00) >> 8) as u8;
             buf[2] = (config & 0x00FF) as u8;
             self.i2c.write(buf, 3);
+            self.sensing.set(true);
             self.protocol_state.set(ProtocolState::Configure);
         });
     }
@@ -139,6 +147,7 @@ impl<'a> TMP006<'a> {
             buf[1] = ((config & 0xFF00) >> 8) as u8;
             buf[2] = (config & 0x00FF) as u8;
             self.i2c.write(buf, 3);
+            self.sensing.set(false);
             self.protocol_state.set(ProtocolState::Deconfigure(temperature));
         });
     }
@@ -154,6 +163,15 @@ impl<'a> TMP006<'a> {
         self.interrupt_pin.disable_interrupt();
         self.interrupt_pin.disable();
     }
+
+    fn report_to_client(&self, temperature: f32) {
+        if self.client_pending.get() {
+            self.client_pending.set(false);
+            self.temperature_client
+                .get()
+                .map(|client| client.temperature_ready((temperature * 100.0) as i32));
+        }
+    }
 }
 
 fn calculate_temperature(sensor_voltage: i16, die_temperature: i16) -> f32 {
@@ -196,7 +214,14 @@ impl<'a> i2c::I2CClient for TMP006<'a> {
                         .get()
                         .map(|mut cb| cb.schedule(temp_val as usize, get_errno() as usize, 0));
                     self.callback.set(None);
+                    self.report_to_client(temp_val);
                 });
+
+                // A sensor client asked for a reading while the sensor was being turned off
+                if self.client_pending.get() {
+                    self.repeated_mode.set(false);
+                    self.enable_sensor(MAX_SAMPLING_RATE);
+                }
             }
             ProtocolState::SetRegSensorVoltage => {
                 // Read sensor voltage register
@@ -233,6 +258,7 @@ impl<'a> i2c::I2CClient for TMP006<'a> {
                     self.callback
                         .get()
                         .map(|mut cb| cb.schedule(temp_val as usize, get_errno() as usize, 0));
+                    self.report_to_client(temp_val);
 
                     self.i2c.disable();
                 }
@@ -256,6 +282,23 @@ impl<'a> Client for TMP006<'a> {
     }
 }
 
+impl<'a> sensors::TemperatureSensor for TMP006<'a> {
+    fn set_temperature_client(&self, client: &'static sensors::TemperatureClient) {
+        self.temperature_client.set(Some(client));
+    }
+
+    fn read_temperature(&self) -> bool {
+        self.client_pending.set(true);
+
+        // If the sensor is already on, its next reading is shared with the client
+        if !self.sensing.get() && self.protocol_state.get() == ProtocolState::Idle {
+            self.repeated_mode.set(false);
+            self.enable_sensor(MAX_SAMPLING_RATE);
+        }
+        true
+    }
+}
+
 impl<'a> Driver for TMP006<'a> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
@@ -8,6 +8,7 @@ use kernel::{AppId, Callback, Driver};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::gpio;
 use kernel::hil::i2c;
+use kernel::hil::sensors;
 
 // Buffer to use for I2C messages
 pub static mut BUFFER: [u8; 4] = [0; 4];
@@ -194,6 +195,8 @@ pub struct TSL2561<'a> {
     i2c: &'a i2c::I2CDevice,
     interrupt_pin: &'a gpio::Pin,
     callback: Cell<Option<Callback>>,
+    light_client: Cell<Option<&'static sensors::LightClient>>,
+    client_pending: Cell<bool>,
     state: Cell<State>,
     buffer: TakeCell<&'static mut [u8]>,
 }
@@ -208,6 +211,8 @@ impl<'a> TSL2561<'a> {
             i2c: i2c,
             interrupt_pin: interrupt_pin,
             callback: Cell::new(None),
+            light_client: Cell::new(None),
+            client_pending: Cell::new(false),
             state: Cell::new(State::Idle),
             buffer: TakeCell::new(buffer),
         }
@@ -343,6 +348,11 @@ impl<'a> i2c::I2CClient for TSL2561<'a> {
                 self.buffer.replace(buffer);
                 self.i2c.disable();
                 self.state.set(State::Idle);
+
+                // A sensor client asked for a reading while the ID was read
+                if self.client_pending.get() {
+                    self.take_measurement();
+                }
             }
             State::TakeMeasurementTurnOn => {
                 buffer[0] = Registers::Timing as u8 | COMMAND_REG;
@@ -392,6 +402,10 @@ impl<'a> i2c::I2CClient for TSL2561<'a> {
                 let lux = self.calculate_lux(chan0, chan1);
 
                 self.callback.get().map(|mut cb| cb.schedule(0, lux, 0));
+                if self.client_pending.get() {
+                    self.client_pending.set(false);
+                    self.light_client.get().map(|client| client.light_ready(lux as u32));
+                }
 
                 buffer[0] = Registers::Control as u8 | COMMAND_REG;
                 buffer[1] = POWER_OFF;
@@ -423,6 +437,21 @@ impl<'a> gpio::Client for TSL2561<'a> {
     }
 }
 
+impl<'a> sensors::LightSensor for TSL2561<'a> {
+    fn set_light_client(&self, client: &'static sensors::LightClient) {
+        self.light_client.set(Some(client));
+    }
+
+    fn read_light(&self) -> bool {
+        // Only start a measurement if the client isn't already waiting on one
+        if !self.client_pending.get() {
+            self.client_pending.set(true);
+            self.take_measurement();
+        }
+        true
+    }
+}
+
 impl<'a> Driver for TSL2561<'a> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> isize {
         match subscribe_num {
@@ -0,0 +1,299 @@
+//! Virtualize the ADC so that multiple capsules can take single samples.
+//! Requests of the different users are queued and issued to the ADC one at a
+//! time, and each sample is returned to the user that asked for it.
+//!
+//! If the ADC refuses a request, for example because of an invalid channel,
+//! `sample` returns false when the ADC was idle. A refused request that had
+//! to wait for other users is reported through `Client::sample_failed`.
+//!
+//! One user at a time can also sample continuously through its `AdcUser`.
+//! Single samples requested meanwhile wait until continuous sampling stops.
+//! Continuous sampling requested while single samples are queued or in
+//! progress starts once they are done; if the ADC refuses it then, the user
+//! is told through `Client::sample_failed` and gets its buffers back from
+//! `stop_sampling`.
+
+use core::cell::Cell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::adc::{Client, ContinuousClient, AdcContinuous, AdcSingle};
+
+pub struct MuxAdc<'a, A: AdcSingle + AdcContinuous + 'a> {
+    adc: &'a A,
+    users: List<'a, AdcUser<'a, A>>,
+    inflight: TakeCell<&'a AdcUser<'a, A>>,
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> Client for MuxAdc<'a, A> {
+    fn sample_done(&self, sample: u16) {
+        self.inflight.take().map(|user| user.sample_done(sample));
+        self.do_next_op();
+    }
+
+    fn sample_failed(&self) {
+        self.inflight.take().map(|user| user.sample_failed());
+        self.do_next_op();
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> ContinuousClient for MuxAdc<'a, A> {
+    fn buffer_ready(&self, buffer: &'static mut [u16], length: usize) {
+        self.sampling_user().map(move |user| user.buffer_ready(buffer, length));
+        self.do_next_op();
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> MuxAdc<'a, A> {
+    pub const fn new(adc: &'a A) -> MuxAdc<'a, A> {
+        MuxAdc {
+            adc: adc,
+            users: List::new(),
+            inflight: TakeCell::empty(),
+        }
+    }
+
+    /// The user sampling continuously or waiting to, if any.
+    fn sampling_user(&self) -> Option<&'a AdcUser<'a, A>> {
+        self.users.iter().find(|node| node.continuous.get() != Continuous::Idle)
+    }
+
+    fn do_next_op(&self) {
+        if self.inflight.is_some() {
+            return;
+        }
+        let sampling = self.sampling_user();
+        if sampling.map_or(false, |user| user.is_sampling()) {
+            // Single samples wait until continuous sampling stops
+            return;
+        }
+        let mnode = self.users.iter().find(|node| node.pending.get());
+        match mnode {
+            Some(node) => {
+                node.pending.set(false);
+                self.inflight.replace(node);
+                if !self.adc.sample(node.channel.get()) {
+                    self.inflight.take();
+                    if node.starting.get() {
+                        // Refused from the user's own `sample` call, which
+                        // returns false
+                        node.busy.set(false);
+                    } else {
+                        node.sample_failed();
+                    }
+                    self.do_next_op();
+                }
+            }
+            None => {
+                sampling.map(|user| user.start_waiting());
+            }
+        }
+    }
+}
+
+/// Continuous sampling state of a user.
+#[derive(Copy, Clone, PartialEq)]
+enum Continuous {
+    Idle,
+    /// Waiting for single samples to sample `channel` at `frequency` Hz into
+    /// the first `length` samples of the held buffer.
+    Waiting(u8, u32, usize),
+    /// Sampling, with the number of buffers handed to the ADC.
+    Sampling(usize),
+}
+
+pub struct AdcUser<'a, A: AdcSingle + AdcContinuous + 'a> {
+    mux: &'a MuxAdc<'a, A>,
+    channel: Cell<u8>,
+    pending: Cell<bool>,
+    busy: Cell<bool>,
+    /// Whether the user is inside its `sample` call.
+    starting: Cell<bool>,
+    next: ListLink<'a, AdcUser<'a, A>>,
+    client: Cell<Option<&'a Client>>,
+    continuous: Cell<Continuous>,
+    // Buffers given to `sample_continuous` and `provide_buffer` before
+    // sampling could start
+    buffer: TakeCell<&'static mut [u16]>,
+    queued: TakeCell<&'static mut [u16]>,
+    queued_length: Cell<usize>,
+    continuous_client: Cell<Option<&'a ContinuousClient>>,
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> AdcUser<'a, A> {
+    pub const fn new(mux: &'a MuxAdc<'a, A>) -> AdcUser<'a, A> {
+        AdcUser {
+            mux: mux,
+            channel: Cell::new(0),
+            pending: Cell::new(false),
+            busy: Cell::new(false),
+            starting: Cell::new(false),
+            next: ListLink::empty(),
+            client: Cell::new(None),
+            continuous: Cell::new(Continuous::Idle),
+            buffer: TakeCell::empty(),
+            queued: TakeCell::empty(),
+            queued_length: Cell::new(0),
+            continuous_client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&'a self, client: &'a Client) {
+        self.mux.users.push_head(self);
+        self.client.set(Some(client));
+    }
+
+    /// Sets the client for continuous sampling. `set_client` must be called
+    /// as well, since it registers the user with the mux.
+    pub fn set_continuous_client(&self, client: &'a ContinuousClient) {
+        self.continuous_client.set(Some(client));
+    }
+
+    fn is_sampling(&self) -> bool {
+        match self.continuous.get() {
+            Continuous::Sampling(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Starts continuous sampling that had to wait for single samples,
+    /// reporting a refusal through `Client::sample_failed`.
+    fn start_waiting(&self) {
+        if let Continuous::Waiting(channel, frequency, length) = self.continuous.get() {
+            let started = self.buffer.take().map_or(false, |buffer| {
+                match self.mux.adc.sample_continuous(channel, frequency, buffer, length) {
+                    Ok(()) => true,
+                    Err(buffer) => {
+                        self.buffer.replace(buffer);
+                        false
+                    }
+                }
+            });
+            if !started {
+                self.continuous.set(Continuous::Idle);
+                self.sample_failed();
+                return;
+            }
+            self.continuous.set(Continuous::Sampling(1));
+            self.queued.take().map(|buffer| {
+                let length = self.queued_length.get();
+                if let Err(buffer) = self.provide_buffer(buffer, length) {
+                    self.queued.replace(buffer);
+                }
+            });
+        }
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> Client for AdcUser<'a, A> {
+    fn sample_done(&self, sample: u16) {
+        self.busy.set(false);
+        self.client.get().map(|client| client.sample_done(sample));
+    }
+
+    fn sample_failed(&self) {
+        self.busy.set(false);
+        self.client.get().map(|client| client.sample_failed());
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> ContinuousClient for AdcUser<'a, A> {
+    fn buffer_ready(&self, buffer: &'static mut [u16], length: usize) {
+        if let Continuous::Sampling(buffers) = self.continuous.get() {
+            // The ADC stops once it fills a buffer with none queued behind it
+            if buffers > 1 {
+                self.continuous.set(Continuous::Sampling(buffers - 1));
+            } else {
+                self.continuous.set(Continuous::Idle);
+            }
+        }
+        self.continuous_client.get().map(|client| client.buffer_ready(buffer, length));
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> ListNode<'a, AdcUser<'a, A>> for AdcUser<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, AdcUser<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> AdcSingle for AdcUser<'a, A> {
+    fn initialize(&self) -> bool {
+        self.mux.adc.initialize()
+    }
+
+    fn sample(&self, channel: u8) -> bool {
+        if self.busy.get() {
+            return false;
+        }
+        self.busy.set(true);
+        self.channel.set(channel);
+        self.pending.set(true);
+        self.starting.set(true);
+        self.mux.do_next_op();
+        self.starting.set(false);
+        // Cleared again if the ADC refused the request straight away
+        self.busy.get()
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous> AdcContinuous for AdcUser<'a, A> {
+    fn sample_continuous(&self,
+                         channel: u8,
+                         frequency: u32,
+                         buffer: &'static mut [u16],
+                         length: usize)
+                         -> Result<(), &'static mut [u16]> {
+        if self.mux.sampling_user().is_some() {
+            return Err(buffer);
+        }
+        if self.mux.inflight.is_none() && !self.mux.users.iter().any(|node| node.pending.get()) {
+            match self.mux.adc.sample_continuous(channel, frequency, buffer, length) {
+                Ok(()) => {
+                    self.continuous.set(Continuous::Sampling(1));
+                    Ok(())
+                }
+                Err(buffer) => Err(buffer),
+            }
+        } else {
+            // Start once the single samples ahead of this request are done
+            self.continuous.set(Continuous::Waiting(channel, frequency, length));
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+    }
+
+    fn provide_buffer(&self,
+                      buffer: &'static mut [u16],
+                      length: usize)
+                      -> Result<(), &'static mut [u16]> {
+        match self.continuous.get() {
+            Continuous::Sampling(buffers) => {
+                match self.mux.adc.provide_buffer(buffer, length) {
+                    Ok(()) => {
+                        self.continuous.set(Continuous::Sampling(buffers + 1));
+                        Ok(())
+                    }
+                    Err(buffer) => Err(buffer),
+                }
+            }
+            Continuous::Waiting(..) if self.queued.is_none() => {
+                self.queued.replace(buffer);
+                self.queued_length.set(length);
+                Ok(())
+            }
+            _ => Err(buffer),
+        }
+    }
+
+    fn stop_sampling(&self) -> (Option<&'static mut [u16]>, Option<&'static mut [u16]>) {
+        let buffers = if self.is_sampling() {
+            let (current, queued) = self.mux.adc.stop_sampling();
+            (current, queued.or_else(|| self.queued.take()))
+        } else {
+            (self.buffer.take(), self.queued.take())
+        };
+        self.continuous.set(Continuous::Idle);
+        self.mux.do_next_op();
+        buffers
+    }
+}
@@ -0,0 +1,193 @@
+//! Virtualize a flash so that multiple capsules can use it. Operations of the
+//! different users are queued and issued to the flash one at a time.
+
+use core::cell::Cell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::{self, Error, Flash};
+
+pub struct MuxFlash<'a, F: Flash + 'static> {
+    driver: &'a F,
+    users: List<'a, FlashUser<'a, F>>,
+    inflight: TakeCell<&'a FlashUser<'a, F>>,
+}
+
+impl<'a, F: Flash> flash::Client<F> for MuxFlash<'a, F> {
+    fn read_complete(&self, read_buffer: &'static mut F::Page, error: Error) {
+        self.inflight.take().map(move |user| user.read_complete(read_buffer, error));
+        self.do_next_op();
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut F::Page, error: Error) {
+        self.inflight.take().map(move |user| user.write_complete(write_buffer, error));
+        self.do_next_op();
+    }
+
+    fn erase_complete(&self, error: Error) {
+        self.inflight.take().map(|user| user.erase_complete(error));
+        self.do_next_op();
+    }
+}
+
+impl<'a, F: Flash> MuxFlash<'a, F> {
+    pub const fn new(driver: &'a F) -> MuxFlash<'a, F> {
+        MuxFlash {
+            driver: driver,
+            users: List::new(),
+            inflight: TakeCell::empty(),
+        }
+    }
+
+    fn do_next_op(&self) {
+        if self.inflight.is_none() {
+            let mnode = self.users.iter().find(|node| node.operation.get() != Op::Idle);
+            mnode.map(|node| {
+                let op = node.operation.get();
+                node.operation.set(Op::Idle);
+                self.inflight.replace(node);
+                let started = match op {
+                    Op::Read(page) => {
+                        node.buffer.take().map_or(false, |buf| {
+                            self.driver
+                                .read_page(page, buf)
+                                .map_err(|(error, buf)| node.read_complete(buf, error))
+                                .is_ok()
+                        })
+                    }
+                    Op::Write(page) => {
+                        node.buffer.take().map_or(false, |buf| {
+                            self.driver
+                                .write_page(page, buf)
+                                .map_err(|(error, buf)| node.write_complete(buf, error))
+                                .is_ok()
+                        })
+                    }
+                    Op::Erase(page) => {
+                        self.driver
+                            .erase_page(page)
+                            .map_err(|error| node.erase_complete(error))
+                            .is_ok()
+                    }
+                    Op::Idle => false, // Can't get here...
+                };
+                if !started {
+                    self.inflight.take();
+                    self.do_next_op();
+                }
+            });
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Op {
+    Idle,
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+pub struct FlashUser<'a, F: Flash + 'static> {
+    mux: &'a MuxFlash<'a, F>,
+    buffer: TakeCell<&'static mut F::Page>,
+    operation: Cell<Op>,
+    busy: Cell<bool>,
+    next: ListLink<'a, FlashUser<'a, F>>,
+    client: Cell<Option<&'a flash::Client<FlashUser<'a, F>>>>,
+}
+
+impl<'a, F: Flash> FlashUser<'a, F> {
+    pub const fn new(mux: &'a MuxFlash<'a, F>) -> FlashUser<'a, F> {
+        FlashUser {
+            mux: mux,
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Op::Idle),
+            busy: Cell::new(false),
+            next: ListLink::empty(),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&'a self, client: &'a flash::Client<FlashUser<'a, F>>) {
+        self.mux.users.push_head(self);
+        self.client.set(Some(client));
+    }
+
+    /// Checks whether a new operation on `page_number` can be queued.
+    fn check(&self, page_number: usize) -> Result<(), Error> {
+        if self.busy.get() {
+            Err(Error::Busy)
+        } else if page_number >= self.mux.driver.num_pages() {
+            Err(Error::InvalidPage)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, F: Flash> flash::Client<F> for FlashUser<'a, F> {
+    fn read_complete(&self, read_buffer: &'static mut F::Page, error: Error) {
+        self.busy.set(false);
+        self.client.get().map(move |client| client.read_complete(read_buffer, error));
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut F::Page, error: Error) {
+        self.busy.set(false);
+        self.client.get().map(move |client| client.write_complete(write_buffer, error));
+    }
+
+    fn erase_complete(&self, error: Error) {
+        self.busy.set(false);
+        self.client.get().map(|client| client.erase_complete(error));
+    }
+}
+
+impl<'a, F: Flash> ListNode<'a, FlashUser<'a, F>> for FlashUser<'a, F> {
+    fn next(&'a self) -> &'a ListLink<'a, FlashUser<'a, F>> {
+        &self.next
+    }
+}
+
+impl<'a, F: Flash> Flash for FlashUser<'a, F> {
+    type Page = F::Page;
+
+    fn num_pages(&self) -> usize {
+        self.mux.driver.num_pages()
+    }
+
+    fn read_page(&self,
+                 page_number: usize,
+                 buf: &'static mut F::Page)
+                 -> Result<(), (Error, &'static mut F::Page)> {
+        if let Err(error) = self.check(page_number) {
+            return Err((error, buf));
+        }
+        self.buffer.replace(buf);
+        self.busy.set(true);
+        self.operation.set(Op::Read(page_number));
+        self.mux.do_next_op();
+        Ok(())
+    }
+
+    fn write_page(&self,
+                  page_number: usize,
+                  buf: &'static mut F::Page)
+                  -> Result<(), (Error, &'static mut F::Page)> {
+        if let Err(error) = self.check(page_number) {
+            return Err((error, buf));
+        }
+        self.buffer.replace(buf);
+        self.busy.set(true);
+        self.operation.set(Op::Write(page_number));
+        self.mux.do_next_op();
+        Ok(())
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), Error> {
+        try!(self.check(page_number));
+        self.busy.set(true);
+        self.operation.set(Op::Erase(page_number));
+        self.mux.do_next_op();
+        Ok(())
+    }
+}
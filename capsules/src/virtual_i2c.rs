@@ -1,7 +1,9 @@
 use core::cell::Cell;
+use core::cmp;
 use kernel::common::{List, ListLink, ListNode};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::i2c::{self, I2CClient, I2CHwMasterClient, Error};
+use kernel::hil::i2c::{Direction, MAX_SEGMENTS, Segment};
 
 pub struct MuxI2C<'a> {
     i2c: &'a i2c::I2CMaster,
@@ -51,10 +53,14 @@ impl<'a> MuxI2C<'a> {
             mnode.map(|node| {
                 node.buffer.take().map(|buf| {
                     match node.operation.get() {
-                        Op::Write(len) => self.i2c.write(node.addr, buf, len),
-                        Op::Read(len) => self.i2c.read(node.addr, buf, len),
+                        Op::Write(len) => self.i2c.write(node.addr.get(), buf, len),
+                        Op::Read(len) => self.i2c.read(node.addr.get(), buf, len),
                         Op::WriteRead(wlen, rlen) => {
-                            self.i2c.write_read(node.addr, buf, wlen, rlen)
+                            self.i2c.write_read(node.addr.get(), buf, wlen, rlen)
+                        }
+                        Op::Transaction(count) => {
+                            let segments = node.segments.get();
+                            self.i2c.transaction(node.addr.get(), buf, &segments[..count])
                         }
                         Op::Idle => {} // Can't get here...
                     }
@@ -69,17 +75,27 @@ impl<'a> MuxI2C<'a> {
 #[derive(Copy, Clone,PartialEq)]
 enum Op {
     Idle,
-    Write(u8),
-    Read(u8),
-    WriteRead(u8, u8),
+    Write(usize),
+    Read(usize),
+    WriteRead(usize, usize),
+    /// The given number of segments, kept in `I2CDevice::segments`.
+    Transaction(usize),
 }
 
+const NO_SEGMENT: Segment = Segment {
+    direction: Direction::Write,
+    stop: true,
+    start: 0,
+    len: 0,
+};
+
 pub struct I2CDevice<'a> {
     mux: &'a MuxI2C<'a>,
-    addr: u8,
+    addr: Cell<u8>,
     enabled: Cell<bool>,
     buffer: TakeCell<&'static mut [u8]>,
     operation: Cell<Op>,
+    segments: Cell<[Segment; MAX_SEGMENTS]>,
     next: ListLink<'a, I2CDevice<'a>>,
     client: Cell<Option<&'a I2CClient>>,
 }
@@ -88,10 +104,11 @@ impl<'a> I2CDevice<'a> {
     pub const fn new(mux: &'a MuxI2C<'a>, addr: u8) -> I2CDevice<'a> {
         I2CDevice {
             mux: mux,
-            addr: addr,
+            addr: Cell::new(addr),
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
+            segments: Cell::new([NO_SEGMENT; MAX_SEGMENTS]),
             next: ListLink::empty(),
             client: Cell::new(None),
         }
@@ -101,6 +118,12 @@ impl<'a> I2CDevice<'a> {
         self.mux.devices.push_head(self);
         self.client.set(Some(client));
     }
+
+    /// Changes the address of the device, for clients that talk to several
+    /// devices in turn. Must not be called while an operation is queued.
+    pub fn set_address(&self, addr: u8) {
+        self.addr.set(addr);
+    }
 }
 
 impl<'a> I2CClient for I2CDevice<'a> {
@@ -132,21 +155,31 @@ impl<'a> i2c::I2CDevice for I2CDevice<'a> {
         }
     }
 
-    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8) {
+    fn write_read(&self, data: &'static mut [u8], write_len: usize, read_len: usize) {
         self.buffer.replace(data);
         self.operation.set(Op::WriteRead(write_len, read_len));
         self.mux.do_next_op();
     }
 
-    fn write(&self, data: &'static mut [u8], len: u8) {
+    fn write(&self, data: &'static mut [u8], len: usize) {
         self.buffer.replace(data);
         self.operation.set(Op::Write(len));
         self.mux.do_next_op();
     }
 
-    fn read(&self, buffer: &'static mut [u8], len: u8) {
+    fn read(&self, buffer: &'static mut [u8], len: usize) {
         self.buffer.replace(buffer);
         self.operation.set(Op::Read(len));
         self.mux.do_next_op();
     }
+
+    fn transaction(&self, data: &'static mut [u8], segments: &[Segment]) {
+        let mut stored = [NO_SEGMENT; MAX_SEGMENTS];
+        let count = cmp::min(segments.len(), MAX_SEGMENTS);
+        stored[..count].copy_from_slice(&segments[..count]);
+        self.segments.set(stored);
+        self.buffer.replace(data);
+        self.operation.set(Op::Transaction(count));
+        self.mux.do_next_op();
+    }
 }
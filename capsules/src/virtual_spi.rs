@@ -38,17 +38,24 @@ impl<'a, SPI: hil::spi::SpiMaster> MuxSPIMaster<'a, SPI> {
             mnode.map(|node| {
 
                 match node.operation.get() {
-                    Op::Configure(cpol, cpal, rate) => {
+                    Op::ReadWriteBytes(len) => {
 
                         // The `chip_select` type will be correct based on
                         // what implemented `SpiMaster`.
                         self.spi.specify_chip_select(node.chip_select.get());
 
-                        self.spi.set_clock(cpol);
-                        self.spi.set_phase(cpal);
-                        self.spi.set_rate(rate);
-                    }
-                    Op::ReadWriteBytes(len) => {
+                        // Devices may share a chip select with different
+                        // settings, so apply them before every transfer
+                        node.configuration.get().map(|(cpol, cpal, rate)| {
+                            self.spi.set_clock(cpol);
+                            self.spi.set_phase(cpal);
+                            self.spi.set_rate(rate);
+                        });
+                        if node.hold_low.get() {
+                            self.spi.hold_low();
+                        } else {
+                            self.spi.release_low();
+                        }
 
                         node.txbuffer.take().map(|txbuffer| {
                             node.rxbuffer.take().map(move |rxbuffer| {
@@ -71,16 +78,17 @@ impl<'a, SPI: hil::spi::SpiMaster> MuxSPIMaster<'a, SPI> {
 #[derive(Copy, Clone, PartialEq)]
 enum Op {
     Idle,
-    Configure(hil::spi::ClockPolarity, hil::spi::ClockPhase, u32),
     ReadWriteBytes(usize),
 }
 
 pub struct SPIMasterDevice<'a, SPI: hil::spi::SpiMaster + 'a> {
     mux: &'a MuxSPIMaster<'a, SPI>,
     chip_select: Cell<SPI::ChipSelect>,
+    hold_low: Cell<bool>,
     txbuffer: TakeCell<&'static mut [u8]>,
     rxbuffer: TakeCell<Option<&'static mut [u8]>>,
     operation: Cell<Op>,
+    configuration: Cell<Option<(hil::spi::ClockPolarity, hil::spi::ClockPhase, u32)>>,
     next: ListLink<'a, SPIMasterDevice<'a, SPI>>,
     client: Cell<Option<&'a hil::spi::SpiMasterClient>>,
 }
@@ -92,9 +100,11 @@ impl<'a, SPI: hil::spi::SpiMaster> SPIMasterDevice<'a, SPI> {
         SPIMasterDevice {
             mux: mux,
             chip_select: Cell::new(chip_select),
+            hold_low: Cell::new(false),
             txbuffer: TakeCell::empty(),
             rxbuffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
+            configuration: Cell::new(None),
             next: ListLink::empty(),
             client: Cell::new(None),
         }
@@ -125,9 +135,10 @@ impl<'a, SPI: hil::spi::SpiMaster> ListNode<'a, SPIMasterDevice<'a, SPI>>
 }
 
 impl<'a, SPI: hil::spi::SpiMaster> hil::spi::SPIMasterDevice for SPIMasterDevice<'a, SPI> {
+    /// The settings are applied to the bus before each transfer of this
+    /// device.
     fn configure(&self, cpol: hil::spi::ClockPolarity, cpal: hil::spi::ClockPhase, rate: u32) {
-        self.operation.set(Op::Configure(cpol, cpal, rate));
-        self.mux.do_next_op();
+        self.configuration.set(Some((cpol, cpal, rate)));
     }
 
     fn read_write_bytes(&self,
@@ -142,4 +153,11 @@ impl<'a, SPI: hil::spi::SpiMaster> hil::spi::SPIMasterDevice for SPIMasterDevice
 
         true
     }
+    fn hold_low(&self) {
+        self.hold_low.set(true);
+    }
+
+    fn release_low(&self) {
+        self.hold_low.set(false);
+    }
 }
@@ -0,0 +1,158 @@
+//! Virtualize a UART so that multiple kernel clients, such as the console and
+//! kernel debug output, can share it.
+//!
+//! Each `UartDevice` may have one transmission outstanding. Transmissions are
+//! issued to the UART one at a time, and when one finishes, devices that are
+//! already waiting go before the device whose transmission just finished, so
+//! a busy client cannot starve the others.
+//!
+//! Received data goes to the single device created as the receiver. Calls to
+//! `transmit` while a transmission is outstanding, and calls to `receive` on
+//! other devices, are refused by handing the buffer straight back with
+//! `Error::RepeatCallError`.
+
+use core::cell::Cell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::uart::{self, UART, UARTSync};
+
+pub struct MuxUart<'a, U: UART + 'a> {
+    uart: &'a U,
+    devices: List<'a, UartDevice<'a, U>>,
+    inflight: TakeCell<&'a UartDevice<'a, U>>,
+    receiver: Cell<Option<&'a UartDevice<'a, U>>>,
+}
+
+impl<'a, U: UART> uart::Client for MuxUart<'a, U> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: uart::Error) {
+        self.inflight.take().map(move |device| {
+            // Start a waiting device first, so the finished one queues behind it
+            self.do_next_op();
+            device.transmit_complete(tx_buffer, error);
+        });
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        self.receiver.get().map(move |device| device.receive_complete(rx_buffer, rx_len, error));
+    }
+}
+
+impl<'a, U: UART> MuxUart<'a, U> {
+    pub const fn new(uart: &'a U) -> MuxUart<'a, U> {
+        MuxUart {
+            uart: uart,
+            devices: List::new(),
+            inflight: TakeCell::empty(),
+            receiver: Cell::new(None),
+        }
+    }
+
+    fn do_next_op(&self) {
+        if self.inflight.is_none() {
+            let mnode = self.devices.iter().find(|node| node.tx_buffer.is_some());
+            mnode.map(|node| {
+                node.tx_buffer.take().map(|buffer| {
+                    self.inflight.replace(node);
+                    self.uart.transmit(buffer, node.tx_len.get());
+                });
+            });
+        }
+    }
+}
+
+pub struct UartDevice<'a, U: UART + 'a> {
+    mux: &'a MuxUart<'a, U>,
+    /// Buffer of a transmission waiting for the UART.
+    tx_buffer: TakeCell<&'static mut [u8]>,
+    tx_len: Cell<usize>,
+    next: ListLink<'a, UartDevice<'a, U>>,
+    client: Cell<Option<&'static uart::Client>>,
+    receiver: bool,
+    /// Whether a transmission is waiting or in flight.
+    transmitting: Cell<bool>,
+}
+
+impl<'a, U: UART> UartDevice<'a, U> {
+    pub const fn new(mux: &'a MuxUart<'a, U>, receiver: bool) -> UartDevice<'a, U> {
+        UartDevice {
+            mux: mux,
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            next: ListLink::empty(),
+            client: Cell::new(None),
+            receiver: receiver,
+            transmitting: Cell::new(false),
+        }
+    }
+
+    /// Adds the device to its mux. Must be called once before the device is
+    /// used.
+    pub fn setup(&'a self) {
+        self.mux.devices.push_head(self);
+        if self.receiver {
+            self.mux.receiver.set(Some(self));
+        }
+    }
+}
+
+impl<'a, U: UART> uart::Client for UartDevice<'a, U> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: uart::Error) {
+        self.transmitting.set(false);
+        self.client.get().map(move |client| client.transmit_complete(tx_buffer, error));
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        self.client.get().map(move |client| client.receive_complete(rx_buffer, rx_len, error));
+    }
+}
+
+impl<'a, U: UART> ListNode<'a, UartDevice<'a, U>> for UartDevice<'a, U> {
+    fn next(&'a self) -> &'a ListLink<'a, UartDevice<'a, U>> {
+        &self.next
+    }
+}
+
+impl<'a, U: UART> UART for UartDevice<'a, U> {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.client.set(Some(client));
+    }
+
+    /// All devices share the UART's parameters; the last call wins.
+    fn init(&self, params: uart::UARTParams) {
+        self.mux.uart.init(params);
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        if self.transmitting.get() {
+            self.client.get().map(move |client| {
+                client.transmit_complete(tx_data, uart::Error::RepeatCallError)
+            });
+            return;
+        }
+        self.transmitting.set(true);
+        self.tx_buffer.replace(tx_data);
+        self.tx_len.set(tx_len);
+        self.mux.do_next_op();
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        if self.receiver {
+            self.mux.uart.receive(rx_buffer, rx_len);
+        } else {
+            self.client.get().map(move |client| {
+                client.receive_complete(rx_buffer, 0, uart::Error::RepeatCallError)
+            });
+        }
+    }
+}
+
+impl<'a, U: UARTSync> UARTSync for UartDevice<'a, U> {
+    /// Bypasses the mux, for use when interrupts cannot be serviced.
+    fn transmit_sync(&self, tx_data: &[u8]) {
+        self.mux.uart.transmit_sync(tx_data);
+    }
+
+    fn finish_transmit(&self) {
+        self.mux.uart.finish_transmit();
+    }
+}
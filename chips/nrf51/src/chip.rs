@@ -3,6 +3,8 @@ use kernel;
 use kernel::common::{RingBuffer, Queue};
 use nvic;
 use peripheral_interrupts::NvicIdx;
+use power;
+use rng;
 use rtc;
 use timer;
 use uart;
@@ -43,6 +45,7 @@ impl kernel::Chip for NRF51 {
                     NvicIdx::TIMER1 => timer::ALARM1.handle_interrupt(),
                     NvicIdx::TIMER2 => timer::TIMER2.handle_interrupt(),
                     NvicIdx::UART0 => uart::UART0.handle_interrupt(),
+                    NvicIdx::RNG => rng::RNG.handle_interrupt(),
                     _ => {}
                 }
                 nvic::enable(interrupt);
@@ -53,4 +56,11 @@ impl kernel::Chip for NRF51 {
     fn has_pending_interrupts(&self) -> bool {
         unsafe { INTERRUPT_QUEUE.as_mut().unwrap().has_elements() }
     }
+
+    fn sleep(&self) {
+        power::set_sub_power_mode(power::deepest_sub_power_mode());
+        unsafe {
+            kernel::support::wfi();
+        }
+    }
 }
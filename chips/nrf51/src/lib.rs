@@ -18,4 +18,6 @@ pub mod timer;
 pub mod clock;
 pub mod uart;
 pub mod pinmux;
+pub mod power;
+pub mod rng;
 pub use chip::NRF51;
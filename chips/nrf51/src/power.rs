@@ -0,0 +1,43 @@
+//! The POWER peripheral of the nRF51 series (chapter 11 of the nRF51
+//! reference manual v3.0), used to select the System ON sub power mode.
+
+use core::mem;
+use kernel::common::{PowerDomain, VolatileCell};
+
+struct Registers {
+    _reserved1: [VolatileCell<u32>; 30],
+    pub tasks_constlat: VolatileCell<u32>,
+    pub tasks_lowpwr: VolatileCell<u32>,
+}
+
+const POWER_BASE: usize = 0x40000000;
+
+/// Peripherals with operations in flight (e.g. UART transmissions). While any
+/// are active the chip stays in constant latency mode so their interrupts are
+/// serviced promptly.
+pub static mut ACTIVE_PERIPHERALS: PowerDomain = PowerDomain::new();
+
+/// The System ON sub power modes.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SubPowerMode {
+    ConstantLatency,
+    LowPower,
+}
+
+pub fn set_sub_power_mode(mode: SubPowerMode) {
+    let regs: &Registers = unsafe { mem::transmute(POWER_BASE) };
+    match mode {
+        SubPowerMode::ConstantLatency => regs.tasks_constlat.set(1),
+        SubPowerMode::LowPower => regs.tasks_lowpwr.set(1),
+    }
+}
+
+/// Chooses the lowest power mode compatible with the currently active
+/// peripherals.
+pub fn deepest_sub_power_mode() -> SubPowerMode {
+    if unsafe { ACTIVE_PERIPHERALS.is_active() } {
+        SubPowerMode::ConstantLatency
+    } else {
+        SubPowerMode::LowPower
+    }
+}
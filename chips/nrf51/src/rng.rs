@@ -0,0 +1,123 @@
+//! The RNG peripheral of the nRF51 series (chapter 21 of the nRF51 reference
+//! manual v3.0).
+//!
+//! The RNG generates one random byte at a time from thermal noise, signalling
+//! each with the VALRDY event. Bias correction is enabled, which makes the
+//! bytes uniformly distributed at the cost of generating them more slowly.
+
+use chip;
+use core::cell::Cell;
+use core::mem;
+use kernel::common::VolatileCell;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use nvic;
+use peripheral_interrupts::NvicIdx;
+use power;
+
+#[repr(C, packed)]
+struct Registers {
+    pub task_start: VolatileCell<u32>,
+    pub task_stop: VolatileCell<u32>,
+    _reserved0: [VolatileCell<u32>; 62],
+    pub event_valrdy: VolatileCell<u32>, // 0x100
+    _reserved1: [VolatileCell<u32>; 63],
+    pub shorts: VolatileCell<u32>, // 0x200
+    _reserved2: [VolatileCell<u32>; 64],
+    pub intenset: VolatileCell<u32>, // 0x304
+    pub intenclr: VolatileCell<u32>, // 0x308
+    _reserved3: [VolatileCell<u32>; 126],
+    pub config: VolatileCell<u32>, // 0x504
+    pub value: VolatileCell<u32>, // 0x508
+}
+
+const RNG_BASE: usize = 0x4000D000;
+
+const VALRDY: u32 = 1;
+
+pub struct Rng {
+    regs: *const Registers,
+    buffer: TakeCell<&'static mut [u8]>,
+    len: Cell<usize>,
+    index: Cell<usize>,
+    client: Cell<Option<&'static hil::rng::Client>>,
+}
+
+pub static mut RNG: Rng = Rng::new();
+
+impl Rng {
+    const fn new() -> Rng {
+        Rng {
+            regs: RNG_BASE as *const Registers,
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            index: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static hil::rng::Client) {
+        self.client.set(Some(client));
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs: &Registers = unsafe { mem::transmute(self.regs) };
+        if regs.event_valrdy.get() == 0 {
+            return;
+        }
+        regs.event_valrdy.set(0);
+
+        let value = regs.value.get() as u8;
+        let done = self.buffer.map_or(true, |buf| {
+            let index = self.index.get();
+            if index < self.len.get() {
+                buf[index] = value;
+                self.index.set(index + 1);
+            }
+            index + 1 >= self.len.get()
+        });
+
+        if done {
+            regs.task_stop.set(1);
+            regs.intenclr.set(VALRDY);
+            unsafe {
+                power::ACTIVE_PERIPHERALS.release();
+            }
+            self.buffer.take().map(|buf| {
+                self.client.get().map(move |client| client.fill_done(buf, self.len.get()));
+            });
+        }
+    }
+}
+
+impl hil::rng::RNG for Rng {
+    fn fill(&self, buf: &'static mut [u8], len: usize) -> Result<(), &'static mut [u8]> {
+        let len = if len < buf.len() { len } else { buf.len() };
+        if self.buffer.is_some() || len == 0 {
+            return Err(buf);
+        }
+        let regs: &Registers = unsafe { mem::transmute(self.regs) };
+
+        self.len.set(len);
+        self.index.set(0);
+        self.buffer.replace(buf);
+
+        unsafe {
+            power::ACTIVE_PERIPHERALS.acquire();
+        }
+        regs.config.set(1); // Bias correction
+        regs.event_valrdy.set(0);
+        regs.intenset.set(VALRDY);
+        nvic::enable(NvicIdx::RNG);
+        regs.task_start.set(1);
+        Ok(())
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn RNG_Handler() {
+    use kernel::common::Queue;
+    nvic::disable(NvicIdx::RNG);
+    chip::INTERRUPT_QUEUE.as_mut().unwrap().enqueue(NvicIdx::RNG);
+}
@@ -7,6 +7,7 @@ use kernel::hil::uart;
 use nvic;
 use peripheral_interrupts::NvicIdx;
 use pinmux::Pinmux;
+use power;
 
 #[repr(C, packed)]
 pub struct Registers {
@@ -58,6 +59,9 @@ pub struct UART {
     buffer: TakeCell<&'static mut [u8]>,
     len: Cell<usize>,
     index: Cell<usize>,
+    rx_buffer: TakeCell<&'static mut [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
 }
 
 #[derive(Copy, Clone)]
@@ -80,6 +84,9 @@ impl UART {
             buffer: TakeCell::empty(),
             len: Cell::new(0),
             index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
         }
     }
 
@@ -147,17 +154,50 @@ impl UART {
         regs.intenclr.set(1 << 7 as u32);
     }
 
+    /// Stops the current reception, if any, handing its buffer back to the
+    /// client with `error`.
+    fn abort_rx(&self, error: uart::Error) {
+        let regs: &Registers = unsafe { mem::transmute(self.regs) };
+        self.rx_buffer.take().map(|buffer| {
+            regs.task_stoprx.set(1 as u32);
+            self.disable_rx_interrupts();
+            unsafe {
+                power::ACTIVE_PERIPHERALS.release();
+            }
+            let len = self.rx_index.get();
+            self.client.map(move |client| client.receive_complete(buffer, len, error));
+        });
+    }
+
     pub fn handle_interrupt(&mut self) {
         let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        // let rx = regs.event_rxdrdy.get() != 0;
+        let rx = regs.event_rxdrdy.get() != 0;
         let tx = regs.event_txdrdy.get() != 0;
 
-        // if rx {
-        //     let val = regs.rxd.get();
-        //     self.client.map(|client| {
-        //         client.read_done(val as u8);
-        //     });
-        // }
+        if rx {
+            regs.event_rxdrdy.set(0 as u32);
+            let val = regs.rxd.get() as u8;
+
+            let index = self.rx_index.get();
+            self.rx_buffer.map(|buffer| buffer[index] = val);
+            self.rx_index.set(index + 1);
+
+            if self.rx_index.get() >= self.rx_len.get() {
+                regs.task_stoprx.set(1 as u32);
+                self.disable_rx_interrupts();
+
+                // Signal client read done
+                self.rx_buffer.take().map(|buffer| {
+                    unsafe {
+                        power::ACTIVE_PERIPHERALS.release();
+                    }
+                    let len = self.rx_index.get();
+                    self.client.map(move |client| {
+                        client.receive_complete(buffer, len, uart::Error::CommandComplete);
+                    });
+                });
+            }
+        }
         if tx {
             regs.event_txdrdy.set(0 as u32);
 
@@ -165,8 +205,11 @@ impl UART {
                 regs.task_stoptx.set(1 as u32);
 
                 // Signal client write done
-                self.client.map(|client| {
-                    self.buffer.take().map(|buffer| {
+                self.buffer.take().map(|buffer| {
+                    unsafe {
+                        power::ACTIVE_PERIPHERALS.release();
+                    }
+                    self.client.map(move |client| {
                         client.transmit_complete(buffer, uart::Error::CommandComplete);
                     });
                 });
@@ -201,10 +244,6 @@ impl UART {
         regs.event_txdrdy.get() & 0b1 != 0
     }
 
-    fn rx_ready(&self) -> bool {
-        let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        regs.event_rxdrdy.get() & 0b1 != 0
-    }
 }
 
 impl uart::UART for UART {
@@ -227,6 +266,10 @@ impl uart::UART for UART {
         self.index.set(1);
         self.len.set(tx_len);
 
+        unsafe {
+            power::ACTIVE_PERIPHERALS.acquire();
+        }
+
         regs.event_txdrdy.set(0);
         self.enable_tx_interrupts();
         regs.task_starttx.set(1);
@@ -236,15 +279,63 @@ impl uart::UART for UART {
     }
 
     fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
-        let regs: &Registers = unsafe { mem::transmute(self.regs) };
+        let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
+
+        // quit current reception if any
+        self.abort_rx(uart::Error::RepeatCallError);
+
+        // truncate rx_len if necessary
+        let len = if rx_len > rx_buffer.len() {
+            rx_buffer.len()
+        } else {
+            rx_len
+        };
+        if len == 0 {
+            self.client.map(move |client| {
+                client.receive_complete(rx_buffer, 0, uart::Error::CommandComplete);
+            });
+            return;
+        }
+
+        self.rx_index.set(0);
+        self.rx_len.set(len);
+
+        unsafe {
+            power::ACTIVE_PERIPHERALS.acquire();
+        }
+
+        regs.event_rxdrdy.set(0);
+        self.rx_buffer.replace(rx_buffer);
+        self.enable_rx_interrupts();
         regs.task_startrx.set(1);
-        let mut i = 0;
-        while i < rx_len {
-            while !self.rx_ready() {}
-            rx_buffer[i] = regs.rxd.get() as u8;
-            i += 1;
+        self.enable_nvic();
+    }
+}
+
+impl uart::UARTSync for UART {
+    fn transmit_sync(&self, tx_data: &[u8]) {
+        for b in tx_data.iter() {
+            unsafe {
+                self.send_byte(*b);
+            }
+            while !self.tx_ready() {}
         }
     }
+
+    fn finish_transmit(&self) {
+        let regs: &Registers = unsafe { mem::transmute(self.regs) };
+
+        // Send the bytes the interrupt handler would have sent
+        self.buffer.map(|buffer| {
+            while self.index.get() < self.len.get() {
+                while !self.tx_ready() {}
+                regs.event_txdrdy.set(0);
+                regs.txd.set(buffer[self.index.get()] as u32);
+                self.index.set(self.index.get() + 1);
+            }
+            while !self.tx_ready() {}
+        });
+    }
 }
 
 #[no_mangle]
@@ -27,15 +27,23 @@
 //
 //
 //
+// Continuous sampling uses the internal timer to trigger conversions and
+// PDCA channel 13 to move each 16-bit result into memory. While sampling
+// continuously the ADC is clocked from PBA instead of the RCSYS generic
+// clock, so rates up to around 100 kHz are reachable.
+//
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Date: August 5, 2015
 //
 
+use bpm;
 use core::cell::Cell;
 use core::mem;
+use core::slice;
+use dma::{DMAChannel, DMAClient, DMAPeripheral, DMAWidth};
 use kernel::common::volatile_cell::VolatileCell;
 use kernel::hil;
-use kernel::hil::adc::AdcSingle;
+use kernel::hil::adc::{AdcContinuous, AdcSingle};
 use nvic;
 use pm::{self, Clock, PBAClock};
 use scif;
@@ -67,11 +75,35 @@ pub struct AdcRegisters {
 // Page 59 of SAM4L data sheet
 const BASE_ADDRESS: *mut AdcRegisters = 0x40038000 as *mut AdcRegisters;
 
+// Configuration register with the generic clock, a divide-by-4 prescaler,
+// 300 ksps maximum speed and a VCC/2 reference
+const CFG_DEFAULT: u32 = 0x00000008;
+// CFG.CLKSEL: clock the ADC from the APB (PBA) clock
+const CFG_CLKSEL_APB: u32 = 1 << 6;
+// Fastest ADC clock allowed by the electrical characteristics
+const MAX_ADC_CLOCK: u32 = 1500000;
+// Fewest ADC clock cycles between conversions the timer may trigger
+const MIN_TIMER_TICKS: u32 = 15;
+
+// Control register bits
+const CR_TSTOP: u32 = 1 << 1;
+const CR_TSTART: u32 = 1 << 2;
+const CR_STRIG: u32 = 1 << 3;
+
+// SEQCFG.TRGSEL values
+const TRIGGER_SOFTWARE: u32 = 0x00000000;
+const TRIGGER_TIMER: u32 = 0x00000100;
+
 pub struct Adc {
     registers: *mut AdcRegisters,
     enabled: Cell<bool>,
     channel: Cell<u8>,
     client: Cell<Option<&'static hil::adc::Client>>,
+    continuous: Cell<bool>,
+    continuous_client: Cell<Option<&'static hil::adc::ContinuousClient>>,
+    dma: Cell<Option<&'static DMAChannel>>,
+    length: Cell<usize>,
+    next_length: Cell<usize>,
 }
 
 pub static mut ADC: Adc = Adc::new(BASE_ADDRESS);
@@ -83,6 +115,11 @@ impl Adc {
             enabled: Cell::new(false),
             channel: Cell::new(0),
             client: Cell::new(None),
+            continuous: Cell::new(false),
+            continuous_client: Cell::new(None),
+            dma: Cell::new(None),
+            length: Cell::new(0),
+            next_length: Cell::new(0),
         }
     }
 
@@ -90,6 +127,26 @@ impl Adc {
         self.client.set(Some(client));
     }
 
+    pub fn set_continuous_client<C: hil::adc::ContinuousClient>(&self, client: &'static C) {
+        self.continuous_client.set(Some(client));
+    }
+
+    /// Set the DMA channel used for continuous sampling.
+    pub fn set_dma(&self, dma: &'static DMAChannel) {
+        self.dma.set(Some(dma));
+    }
+
+    /// Stop the conversion timer and return to the single sample clock
+    /// configuration.
+    fn stop_timer(&self) {
+        let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
+        regs.cr.set(CR_TSTOP);
+        regs.seqcfg.set(sequencer_config(self.channel.get(), TRIGGER_SOFTWARE));
+        regs.cfg.set(CFG_DEFAULT);
+        self.continuous.set(false);
+        self.next_length.set(0);
+    }
+
     pub fn handle_interrupt(&mut self) {
         let val: u16;
         let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
@@ -100,6 +157,9 @@ impl Adc {
         // Read the value from the LCV register.
         // The sample is 16 bits wide
         val = (regs.lcv.get() & 0xffff) as u16;
+        unsafe {
+            bpm::CLOCKED_PERIPHERALS.release();
+        }
         self.client.get().map(|client| {
             client.sample_done(val);
         });
@@ -141,7 +201,7 @@ impl AdcSingle for Adc {
             //   - the source to be the Generic clock,
             //   - the max speed to be 300 ksps, and
             //   - the reference voltage to be VCC/2
-            regs.cfg.set(0x00000008);
+            regs.cfg.set(CFG_DEFAULT);
             while regs.sr.get() & (0x51000000) != 0x51000000 {}
         }
         return true;
@@ -149,35 +209,193 @@ impl AdcSingle for Adc {
 
     fn sample(&self, channel: u8) -> bool {
         let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
-        if !self.enabled.get() || channel > 14 {
+        if !self.enabled.get() || self.continuous.get() || channel > 14 {
             return false;
         } else {
             self.channel.set(channel);
-            // This configuration sets the ADC to use Pad Ground as the
-            // negative input, and the ADC channel as the positive. Since
-            // this is a single-ended sample, the bipolar bit is set to zero.
-            // Trigger select is set to zero because this denotes a software
-            // sample. Gain is 0.5x (set to 111). Resolution is set to 12 bits
-            // (set to 0).
-
-            let chan_field: u32 = (self.channel.get() as u32) << 16;
-            let mut cfg: u32 = chan_field;
-            cfg |= 0x00700000; // MUXNEG   = 111 (ground pad)
-            cfg |= 0x00008000; // INTERNAL =  10 (int neg, ext pos)
-            cfg |= 0x00000000; // RES      =   0 (12-bit)
-            cfg |= 0x00000000; // TRGSEL   =   0 (software)
-            cfg |= 0x00000000; // GCOMP    =   0 (no gain error corr)
-            cfg |= 0x00000070; // GAIN     = 111 (0.5x gain)
-            cfg |= 0x00000000; // BIPOLAR  =   0 (not bipolar)
-            cfg |= 0x00000000; // HWLA     =   0 (no left justify value)
-            regs.seqcfg.set(cfg);
+            regs.seqcfg.set(sequencer_config(channel, TRIGGER_SOFTWARE));
+            // The ADC runs off a generic clock, so stay out of deep sleep
+            // until the conversion completes
+            unsafe {
+                bpm::CLOCKED_PERIPHERALS.acquire();
+            }
             // Enable end of conversion interrupt
             regs.ier.set(1);
             // Initiate conversion
-            regs.cr.set(8);
+            regs.cr.set(CR_STRIG);
             return true;
         }
     }
 }
 
+impl AdcContinuous for Adc {
+    fn sample_continuous(&self,
+                         channel: u8,
+                         frequency: u32,
+                         buffer: &'static mut [u16],
+                         length: usize)
+                         -> Result<(), &'static mut [u16]> {
+        let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
+        let dma = match self.dma.get() {
+            Some(dma) => dma,
+            None => return Err(buffer),
+        };
+        if !self.enabled.get() || self.continuous.get() || channel > 14 || frequency == 0 ||
+           length == 0 || buffer.len() == 0 {
+            return Err(buffer);
+        }
+        // A single sample is still in progress while its interrupt is enabled
+        if regs.imr.get() & 1 != 0 {
+            return Err(buffer);
+        }
+
+        // Pick the smallest prescaler (divide by 4 << prescal) that keeps
+        // the ADC clock in range and the timer period within 16 bits. PBA
+        // runs undivided from the system clock.
+        let pba = unsafe { pm::get_system_frequency() };
+        let mut prescal: u32 = 0;
+        let mut clock = pba / 4;
+        while prescal < 7 && (clock > MAX_ADC_CLOCK || clock / frequency > 0x10000) {
+            prescal += 1;
+            clock = pba / (4 << prescal);
+        }
+        let ticks = clock / frequency;
+        if ticks < MIN_TIMER_TICKS || ticks > 0x10000 {
+            return Err(buffer);
+        }
+
+        let length = if length > buffer.len() {
+            buffer.len()
+        } else {
+            length
+        };
+        self.channel.set(channel);
+        self.continuous.set(true);
+        self.length.set(length);
+        self.next_length.set(0);
+
+        regs.cfg.set(CFG_DEFAULT | CFG_CLKSEL_APB | (prescal << 8));
+        regs.itimer.set(ticks - 1);
+        regs.seqcfg.set(sequencer_config(channel, TRIGGER_TIMER));
+
+        // The DMA channel keeps the chip out of deep sleep until sampling
+        // stops
+        dma.enable();
+        dma.set_width(DMAWidth::Width16Bit);
+        dma.do_xfer(DMAPeripheral::ADCIFE_RX, samples_as_bytes(buffer), length);
+
+        regs.cr.set(CR_TSTART);
+        Ok(())
+    }
+
+    fn provide_buffer(&self,
+                      buffer: &'static mut [u16],
+                      length: usize)
+                      -> Result<(), &'static mut [u16]> {
+        if !self.continuous.get() || self.next_length.get() != 0 || length == 0 ||
+           buffer.len() == 0 {
+            return Err(buffer);
+        }
+        let length = if length > buffer.len() {
+            buffer.len()
+        } else {
+            length
+        };
+        match self.dma.get() {
+            Some(dma) => {
+                self.next_length.set(length);
+                dma.queue_xfer(samples_as_bytes(buffer), length);
+                Ok(())
+            }
+            None => Err(buffer),
+        }
+    }
+
+    fn stop_sampling(&self) -> (Option<&'static mut [u16]>, Option<&'static mut [u16]>) {
+        if !self.continuous.get() {
+            return (None, None);
+        }
+        self.stop_timer();
+        self.dma.get().map_or((None, None), |dma| {
+            // Drop the queued buffer first so aborting the current transfer
+            // does not reload it
+            let queued = dma.abort_queued_xfer();
+            let current = dma.abort_xfer();
+            dma.disable();
+            (current.map(bytes_as_samples), queued.map(bytes_as_samples))
+        })
+    }
+}
+
+impl DMAClient for Adc {
+    fn xfer_done(&self, _pid: DMAPeripheral) {
+        let dma = match self.dma.get() {
+            Some(dma) => dma,
+            None => return,
+        };
+        if !self.continuous.get() {
+            return;
+        }
+
+        match dma.take_completed() {
+            Some(buf) => {
+                // The channel has moved on to the queued buffer
+                let length = self.length.get();
+                self.length.set(self.next_length.get());
+                self.next_length.set(0);
+                self.continuous_client.get().map(|client| {
+                    client.buffer_ready(bytes_as_samples(buf), length);
+                });
+            }
+            None => {
+                if dma.transfer_counter() != 0 {
+                    return;
+                }
+                // The buffer filled with nothing queued behind it, so
+                // sampling stops here
+                let length = self.length.get();
+                self.stop_timer();
+                let buf = dma.abort_xfer();
+                dma.disable();
+                buf.map(|buf| {
+                    self.continuous_client.get().map(|client| {
+                        client.buffer_ready(bytes_as_samples(buf), length);
+                    });
+                });
+            }
+        }
+    }
+}
+
+/// Sequencer configuration for a 12-bit single-ended conversion on
+/// `channel`.
+fn sequencer_config(channel: u8, trigger: u32) -> u32 {
+    // This configuration sets the ADC to use Pad Ground as the
+    // negative input, and the ADC channel as the positive. Since
+    // this is a single-ended sample, the bipolar bit is set to zero.
+    // Trigger select is either software or the internal timer. Gain is
+    // 0.5x (set to 111). Resolution is set to 12 bits (set to 0).
+    let chan_field: u32 = (channel as u32) << 16;
+    let mut cfg: u32 = chan_field;
+    cfg |= 0x00700000; // MUXNEG   = 111 (ground pad)
+    cfg |= 0x00008000; // INTERNAL =  10 (int neg, ext pos)
+    cfg |= 0x00000000; // RES      =   0 (12-bit)
+    cfg |= trigger; //    TRGSEL   = software or internal timer
+    cfg |= 0x00000000; // GCOMP    =   0 (no gain error corr)
+    cfg |= 0x00000070; // GAIN     = 111 (0.5x gain)
+    cfg |= 0x00000000; // BIPOLAR  =   0 (not bipolar)
+    cfg |= 0x00000000; // HWLA     =   0 (no left justify value)
+    cfg
+}
+
+// The PDCA moves bytes, so sample buffers are handed to it as byte slices
+// covering the same memory and turned back into samples when returned.
+fn samples_as_bytes(buf: &'static mut [u16]) -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 2) }
+}
+
+fn bytes_as_samples(buf: &'static mut [u8]) -> &'static mut [u16] {
+    unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u16, buf.len() / 2) }
+}
+
 interrupt_handler!(adcife_handler, ADCIFE);
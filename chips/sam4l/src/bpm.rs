@@ -1,3 +1,5 @@
+use cortexm4::scb;
+use kernel::common::PowerDomain;
 use kernel::common::volatile_cell::VolatileCell;
 
 #[repr(C, packed)]
@@ -27,6 +29,59 @@ pub enum CK32Source {
     RC32K = 1,
 }
 
+/// Peripherals with operations in flight that need the main clocks running
+/// (e.g. USART, SPI and I2C DMA transfers, GPIO pin interrupts and an SPI
+/// slave waiting for its master). While any are active the chip will not
+/// enter a sleep mode deeper than `Sleep0`.
+pub static mut CLOCKED_PERIPHERALS: PowerDomain = PowerDomain::new();
+
+/// Sleep modes of the SAM4L, from shallowest to deepest (Section 6.1.3).
+///
+/// `Sleep0`-`Sleep3` progressively stop the CPU, AHB, APB and main clocks.
+/// In `Wait` every clock other than the 32KHz oscillators is stopped, and
+/// only asynchronous wake up sources (AST, EIC, ...) can wake the chip. GPIO
+/// pin change interrupts need the GPIO clock, so they do not.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SleepMode {
+    Sleep0 = 0,
+    Sleep1 = 1,
+    Sleep2 = 2,
+    Sleep3 = 3,
+    Wait,
+    Retention,
+}
+
+/// Selects the sleep mode entered on the next `wfi`.
+pub unsafe fn set_sleep_mode(mode: SleepMode) {
+    let control = (*bpm).control.get() & !((0b11 << 12) | (1 << 9) | (1 << 8));
+    let control = match mode {
+        SleepMode::Sleep0 | SleepMode::Sleep1 | SleepMode::Sleep2 | SleepMode::Sleep3 => {
+            scb::unset_sleepdeep();
+            control | ((mode as u32) << 12)
+        }
+        SleepMode::Wait => {
+            scb::set_sleepdeep();
+            control
+        }
+        SleepMode::Retention => {
+            scb::set_sleepdeep();
+            control | (1 << 9) // RET
+        }
+    };
+    unlock_register(0x1c); // Control
+    (*bpm).control.set(control);
+}
+
+/// Chooses the deepest sleep mode compatible with the currently active
+/// peripherals.
+pub unsafe fn deepest_sleep_mode() -> SleepMode {
+    if CLOCKED_PERIPHERALS.is_active() {
+        SleepMode::Sleep0
+    } else {
+        SleepMode::Wait
+    }
+}
+
 #[inline(never)]
 pub unsafe fn set_ck32source(source: CK32Source) {
     let control = (*bpm).control.get();
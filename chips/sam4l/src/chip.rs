@@ -1,16 +1,25 @@
 use adc;
 use ast;
+use bpm;
 use cortexm4;
 use dma;
 use flashcalw;
 use gpio;
 use i2c;
 use kernel::Chip;
-use kernel::common::{RingBuffer, Queue};
+use kernel::common::{DeferredCall, RingBuffer, Queue};
+use kernel::support;
 use nvic;
 use spi;
+use trng;
 use usart;
 
+/// Task numbers of the drivers that use deferred calls.
+#[derive(Copy, Clone)]
+pub enum DeferredCallTask {
+    Flashcalw = 0,
+}
+
 pub struct Sam4l {
     pub mpu: cortexm4::mpu::MPU,
     pub systick: &'static cortexm4::systick::SysTick,
@@ -54,6 +63,9 @@ impl Sam4l {
         i2c::I2C2.set_dma(&dma::DMAChannels[12]);
         dma::DMAChannels[12].client = Some(&mut i2c::I2C2);
 
+        adc::ADC.set_dma(&dma::DMAChannels[13]);
+        dma::DMAChannels[13].client = Some(&mut adc::ADC);
+
         Sam4l {
             mpu: cortexm4::mpu::MPU::new(),
             systick: cortexm4::systick::SysTick::new(),
@@ -69,6 +81,12 @@ impl Chip for Sam4l {
         use nvic::NvicIdx::*;
 
         unsafe {
+            while let Some(task) = DeferredCall::next_pending() {
+                if task == DeferredCallTask::Flashcalw as usize {
+                    flashcalw::flash_controller.handle_deferred_call();
+                }
+            }
+
             let iq = INTERRUPT_QUEUE.as_mut().unwrap();
             while let Some(interrupt) = iq.dequeue() {
                 match interrupt {
@@ -119,6 +137,8 @@ impl Chip for Sam4l {
 
                     HFLASHC => flashcalw::flash_controller.handle_interrupt(),
                     ADCIFE => adc::ADC.handle_interrupt(),
+                    SPI => spi::SPI.handle_interrupt(),
+                    TRNG => trng::TRNG.handle_interrupt(),
                     _ => {}
                 }
                 nvic::enable(interrupt);
@@ -127,7 +147,7 @@ impl Chip for Sam4l {
     }
 
     fn has_pending_interrupts(&self) -> bool {
-        unsafe { INTERRUPT_QUEUE.as_mut().unwrap().has_elements() }
+        unsafe { INTERRUPT_QUEUE.as_mut().unwrap().has_elements() || DeferredCall::has_tasks() }
     }
 
     fn mpu(&self) -> &cortexm4::mpu::MPU {
@@ -137,4 +157,11 @@ impl Chip for Sam4l {
     fn systick(&self) -> &cortexm4::systick::SysTick {
         self.systick
     }
+
+    fn sleep(&self) {
+        unsafe {
+            bpm::set_sleep_mode(bpm::deepest_sleep_mode());
+            support::wfi();
+        }
+    }
 }
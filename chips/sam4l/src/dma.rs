@@ -2,6 +2,7 @@ use core::cell::Cell;
 use core::intrinsics;
 use core::mem;
 
+use bpm;
 use kernel::common::take_cell::TakeCell;
 use kernel::common::volatile_cell::VolatileCell;
 use nvic;
@@ -109,6 +110,15 @@ pub enum DMAPeripheral {
     LCDCA_ABMDR_TX = 38,
 }
 
+/// The size of each transfer a channel performs (Section 16.6.7, MR.SIZE).
+/// The transfer counter counts transfers of this size, not bytes.
+#[derive(Copy, Clone)]
+pub enum DMAWidth {
+    Width8Bit = 0,
+    Width16Bit = 1,
+    Width32Bit = 2,
+}
+
 pub static mut DMAChannels: [DMAChannel; 16] =
     [DMAChannel::new(DMAChannelNum::DMAChannel00, nvic::NvicIdx::PDCA0),
      DMAChannel::new(DMAChannelNum::DMAChannel01, nvic::NvicIdx::PDCA1),
@@ -133,6 +143,7 @@ pub struct DMAChannel {
     pub client: Option<&'static mut DMAClient>,
     enabled: Cell<bool>,
     buffer: TakeCell<&'static mut [u8]>,
+    next_buffer: TakeCell<&'static mut [u8]>,
 }
 
 pub trait DMAClient {
@@ -147,6 +158,7 @@ impl DMAChannel {
             client: None,
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
+            next_buffer: TakeCell::empty(),
         }
     }
 
@@ -204,24 +216,98 @@ impl DMAChannel {
         registers.control.set(0x1);
     }
 
-    pub fn prepare_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], mut len: usize) {
+    pub fn prepare_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], len: usize) {
+        self.prepare_xfer_at(pid, buf, 0, len);
+    }
+
+    /// Like `prepare_xfer`, but transfers the bytes of `buf` from `offset`
+    /// on.
+    pub fn prepare_xfer_at(&self,
+                           pid: DMAPeripheral,
+                           buf: &'static mut [u8],
+                           mut offset: usize,
+                           mut len: usize) {
         // TODO(alevy): take care of zero length case
-        if len > buf.len() {
-            len = buf.len();
+        if offset > buf.len() {
+            offset = buf.len();
+        }
+        if len > buf.len() - offset {
+            len = buf.len() - offset;
         }
 
         let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
         registers.peripheral_select.set(pid);
-        registers.memory_address_reload.set(&buf[0] as *const u8 as u32);
+        registers.memory_address_reload.set(buf.as_ptr() as u32 + offset as u32);
         registers.transfer_counter_reload.set(len as u32);
 
         registers.interrupt_enable.set(1 << 1);
 
+        // Keep the chip out of deep sleep until the transfer is aborted
+        if self.buffer.is_none() {
+            unsafe {
+                bpm::CLOCKED_PERIPHERALS.acquire();
+            }
+        }
+
         // Store the buffer reference in the TakeCell so it can be returned to
         // the caller in `handle_interrupt`
         self.buffer.replace(buf);
     }
 
+    /// Queue a buffer for the channel to continue with once the current
+    /// transfer completes, so the peripheral is serviced without a gap. The
+    /// channel interrupts when it switches to the queued buffer, at which
+    /// point the client calls `take_completed`.
+    pub fn queue_xfer(&self, buf: &'static mut [u8], mut len: usize) {
+        if len > buf.len() {
+            len = buf.len();
+        }
+
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        registers.memory_address_reload.set(&buf[0] as *const u8 as u32);
+        registers.transfer_counter_reload.set(len as u32);
+
+        // Interrupt on reload as well as on running out of buffers
+        registers.interrupt_enable.set((1 << 0) | (1 << 1));
+
+        self.next_buffer.replace(buf);
+    }
+
+    /// If the channel has switched to the buffer passed to `queue_xfer`,
+    /// makes it the current buffer and returns the one that was just
+    /// completed. Returns `None` if nothing was queued or the channel is still
+    /// working on the current buffer.
+    pub fn take_completed(&self) -> Option<&'static mut [u8]> {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        if self.next_buffer.is_none() || registers.transfer_counter_reload.get() != 0 {
+            return None;
+        }
+
+        let completed = self.buffer.take();
+        self.next_buffer.take().map(|next| self.buffer.replace(next));
+
+        // The reload counter stays zero until the next `queue_xfer`, so stop
+        // interrupting on it, but keep watching for the end of the new
+        // current buffer
+        registers.interrupt_disable.set(1 << 0);
+        registers.interrupt_enable.set(1 << 1);
+        completed
+    }
+
+    /// Drops a buffer queued with `queue_xfer` that the channel has not yet
+    /// switched to, and returns it.
+    pub fn abort_queued_xfer(&self) -> Option<&'static mut [u8]> {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        registers.transfer_counter_reload.set(0);
+        registers.interrupt_disable.set(1 << 0);
+        self.next_buffer.take()
+    }
+
+    pub fn set_width(&self, width: DMAWidth) {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        registers.mode.set(width as u32);
+    }
+
     pub fn do_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], len: usize) {
         self.prepare_xfer(pid, buf, len);
         self.start_xfer();
@@ -236,7 +322,12 @@ impl DMAChannel {
         // Reset counter
         registers.transfer_counter.set(0);
 
-        self.buffer.take()
+        self.buffer.take().map(|buf| {
+            unsafe {
+                bpm::CLOCKED_PERIPHERALS.release();
+            }
+            buf
+        })
     }
 
     pub fn transfer_counter(&self) -> usize {
@@ -10,11 +10,11 @@
 //! be generated after a command is complete, it doesn't appear to occur for some
 //! commands.
 //!
-//! A clean interface for reading from flash, writing pages and erasing pages is
-//! defined below and should be used to handle the complexity of these tasks.
+//! Reading, writing and erasing pages is exposed through the `hil::flash::Flash`
+//! trait, which handles the complexity of these tasks.
 //!
-//! The driver should be configure()'d before use, and a Client should be set to
-//! enable a callback after a command is completed.
+//! The driver should be configure()'d before use, and a `hil::flash::Client`
+//! should be set to enable a callback after a command is completed.
 //!
 //! Almost all of the flash controller functionality is implemented (except for
 //! general purpose fuse bits, and more granular control of the cache).
@@ -24,9 +24,12 @@
 //!
 
 use core::cell::Cell;
+use chip::DeferredCallTask;
 use core::mem;
+use kernel::common::DeferredCall;
 use kernel::common::VolatileCell;
 use kernel::common::take_cell::TakeCell;
+use kernel::hil;
 use nvic;
 use pm;
 
@@ -77,16 +80,6 @@ enum RegKey {
     GPFRLO,
 }
 
-/// Error codes are used to inform the Client if the command completed successfully
-/// or whether there was an error and what type of error it was.
-pub enum Error {
-    CommandComplete, // Command Complete
-    LockE, // Lock Error (i.e. tried writing to locked page)
-    ProgE, // Program Error (i.e. incorrectly issued flash commands
-    LockProgE, // Lock and Program Error
-    ECC, // Error Correcting Code Error
-}
-
 /// High level commands to issue to the flash. Usually to track the state of
 /// a command especially if it's multiple FlashCMDs.
 ///
@@ -97,6 +90,7 @@ pub enum Error {
 /// continue the steps of the command in handle_interrupt.
 #[derive(Clone, Copy, PartialEq)]
 pub enum Command {
+    Read,
     Write { page: i32 },
     Erase { page: i32 },
     None,
@@ -159,10 +153,32 @@ pub struct FLASHCALW {
     pb_clock: pm::Clock,
     error_status: Cell<u32>,
     ready: Cell<bool>,
-    client: TakeCell<&'static Client>,
+    client: Cell<Option<&'static hil::flash::Client<FLASHCALW>>>,
     current_state: Cell<FlashState>,
     current_command: Cell<Command>,
-    page_buffer: TakeCell<[u8; PAGE_SIZE as usize]>,
+    buffer: TakeCell<&'static mut Sam4lPage>,
+    deferred_call: DeferredCall,
+}
+
+/// A page of the SAM4L's internal flash.
+pub struct Sam4lPage(pub [u8; PAGE_SIZE as usize]);
+
+impl Default for Sam4lPage {
+    fn default() -> Sam4lPage {
+        Sam4lPage([0; PAGE_SIZE as usize])
+    }
+}
+
+impl AsMut<[u8]> for Sam4lPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl AsRef<[u8]> for Sam4lPage {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 // static instance for the board. Only one FLASHCALW on chip.
@@ -195,12 +211,6 @@ macro_rules! bit {
     ($w:expr) => (0x1u32 << $w);
 }
 
-/// Trait for a client of the flash controller.
-pub trait Client {
-    //  Called upon a completed call
-    fn command_complete(&self, err: Error);
-}
-
 impl FLASHCALW {
     const fn new(base_addr: usize,
                  ahb_clk: pm::HSBClock,
@@ -215,10 +225,11 @@ impl FLASHCALW {
             pb_clock: pm::Clock::PBB(pb_clk),
             error_status: Cell::new(0),
             ready: Cell::new(true),
-            client: TakeCell::empty(),
+            client: Cell::new(None),
             current_state: Cell::new(FlashState::Unconfigured),
             current_command: Cell::new(Command::None),
-            page_buffer: TakeCell::new([0; PAGE_SIZE as usize]),
+            buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(DeferredCallTask::Flashcalw as usize),
         }
     }
 
@@ -276,35 +287,28 @@ impl FLASHCALW {
 
         // Check for errors and report to Client if there are any
         if error_status != 0 {
+            let command = self.current_command.get();
+
             // reset commands / ready
             self.current_command.set(Command::None);
             self.current_state.set(FlashState::Ready);
 
             // call command complete with error
-            match error_status {
-                4 => {
-                    self.client.map(|value| {
-                        value.command_complete(Error::LockE);
-                    });
-                }
-                8 => {
-                    self.client.map(|value| {
-                        value.command_complete(Error::ProgE);
-                    });
-                }
-                12 => {
-                    self.client.map(|value| {
-                        value.command_complete(Error::LockProgE);
-                    });
-                }
-                _ => {}
-            }
+            let error = if error_status & bit!(2) != 0 {
+                hil::flash::Error::LockError
+            } else {
+                hil::flash::Error::ProgramError
+            };
+            self.command_complete(command, error);
             return;
         }
 
         //  Part of a command succeeded -- continue onto next steps.
 
-        match self.current_command.get() {
+        let command = self.current_command.get();
+        match command {
+            // Reads complete in `handle_deferred_call`
+            Command::Read => {}
             Command::Write { page } => {
                 match self.current_state.get() {
                     FlashState::Unlocking => {
@@ -366,12 +370,32 @@ impl FLASHCALW {
         //  If the command is finished call the complete CB.
         if self.current_command.get() == Command::None &&
            self.current_state.get() == FlashState::Ready {
-            self.client.map(|value| {
-                value.command_complete(Error::CommandComplete);
-            });
+            self.command_complete(command, hil::flash::Error::CommandComplete);
+        }
+    }
+
+    /// Completes a read started by `read_page`.
+    pub fn handle_deferred_call(&self) {
+        if self.current_command.get() == Command::Read {
+            self.current_command.set(Command::None);
+            self.command_complete(Command::Read, hil::flash::Error::CommandComplete);
         }
     }
 
+    //  Signals the client that `command` finished.
+    fn command_complete(&self, command: Command, error: hil::flash::Error) {
+        self.client.get().map(|client| match command {
+            Command::Read => {
+                self.buffer.take().map(|buffer| client.read_complete(buffer, error));
+            }
+            Command::Write { .. } => {
+                self.buffer.take().map(|buffer| client.write_complete(buffer, error));
+            }
+            Command::Erase { .. } => client.erase_complete(error),
+            Command::None => {}
+        });
+    }
+
 
     /// FLASH properties.
     pub fn get_flash_size(&self) -> u32 {
@@ -726,13 +750,10 @@ impl FLASHCALW {
         let cleared_double_word: [u8; 8] = [255; 8];
         let clr_ptr: *const u8 = &cleared_double_word[0] as *const u8;
 
-        //  borrow the page buffer from the take cell
-        let buffer = self.page_buffer.take().unwrap();
-
-        unsafe {
+        self.buffer.map(|buffer| unsafe {
             use core::ptr;
 
-            let mut start_buffer: *const u8 = &buffer[0] as *const u8;
+            let mut start_buffer: *const u8 = &buffer.0[0] as *const u8;
             let mut data_transfered: u32 = 0;
             while data_transfered < PAGE_SIZE {
 
@@ -745,9 +766,7 @@ impl FLASHCALW {
                 start_buffer = start_buffer.offset(8);
                 data_transfered += 8;
             }
-        }
-        //  replace the page buffer in the take cell
-        self.page_buffer.put(Some(buffer));
+        });
     }
 
     // returns the error_status (useful for debugging).
@@ -758,8 +777,8 @@ impl FLASHCALW {
 
 // Implementation of high level calls using the low-lv functions.
 impl FLASHCALW {
-    pub fn set_client(&self, client: &'static Client) {
-        self.client.put(Some(client));
+    pub fn set_client(&self, client: &'static hil::flash::Client<FLASHCALW>) {
+        self.client.set(Some(client));
     }
 
     pub fn configure(&mut self) {
@@ -808,70 +827,90 @@ impl FLASHCALW {
         self.get_page_count()
     }
 
-    // Address is some raw address in flash that you want to read.
-    pub fn read(&self, address: usize, size: usize, buffer: &mut [u8]) -> i32 {
+    fn is_idle(&self) -> bool {
+        self.current_state.get() == FlashState::Ready && self.current_command.get() == Command::None
+    }
+
+    /// Starts a write or erase of `page_num` by unlocking its region.
+    fn start_modify(&self, command: Command, page_num: i32) {
         // enable clock incase it's off
         unsafe {
             pm::enable_clock(self.ahb_clock);
         }
 
-        //  check that address makes sense and buffer has room
-        if address > (self.get_flash_size() as usize) ||
-           address + size > (self.get_flash_size() as usize) ||
-           address + size < size || buffer.len() < size {
-            // invalid flash address
-            return -1;
+        self.current_state.set(FlashState::Unlocking);
+        self.current_command.set(command);
+        self.lock_page_region(page_num, false);
+    }
+}
+
+impl hil::flash::Flash for FLASHCALW {
+    type Page = Sam4lPage;
+
+    fn num_pages(&self) -> usize {
+        self.get_number_pages() as usize
+    }
+
+    fn read_page(&self,
+                 page_number: usize,
+                 buf: &'static mut Sam4lPage)
+                 -> Result<(), (hil::flash::Error, &'static mut Sam4lPage)> {
+        if !self.is_idle() {
+            return Err((hil::flash::Error::Busy, buf));
+        }
+        if page_number >= self.num_pages() {
+            return Err((hil::flash::Error::InvalidPage, buf));
         }
 
+        // enable clock incase it's off
+        unsafe {
+            pm::enable_clock(self.ahb_clock);
+        }
+
+        //  The flash is memory mapped, so reading is a plain copy. The client
+        //  is signaled from a deferred call, since not every flash command
+        //  raises an interrupt to complete it from.
+        let address = page_number * PAGE_SIZE as usize;
         let mut byte: *const u8 = address as *const u8;
         unsafe {
-            for i in 0..size {
-                buffer[i] = *byte;
+            for b in buf.0.iter_mut() {
+                *b = *byte;
                 byte = byte.offset(1);
             }
         }
-        0
+        self.buffer.replace(buf);
+        self.current_command.set(Command::Read);
+        self.deferred_call.set();
+        Ok(())
     }
 
-    pub fn write_page(&self, page_num: i32, data: &[u8]) -> i32 {
-        // enable clock incase it's off
-        unsafe {
-            pm::enable_clock(self.ahb_clock);
-        }
-
+    fn write_page(&self,
+                  page_number: usize,
+                  buf: &'static mut Sam4lPage)
+                  -> Result<(), (hil::flash::Error, &'static mut Sam4lPage)> {
         // if we're not ready don't take the command.
-        if self.current_state.get() != FlashState::Ready {
-            return -1;
+        if !self.is_idle() {
+            return Err((hil::flash::Error::Busy, buf));
         }
-
-        //  check data length is of size 'page_size'
-        if data.len() != self.get_page_size() as usize {
-            return -1;
+        if page_number >= self.num_pages() {
+            return Err((hil::flash::Error::InvalidPage, buf));
         }
 
-        self.page_buffer.map(|value| {
-            value.clone_from_slice(&data);
-        });
-
-        self.current_state.set(FlashState::Unlocking);
-        self.current_command.set(Command::Write { page: page_num });
-        self.lock_page_region(page_num, false);
-        0
+        self.buffer.replace(buf);
+        self.start_modify(Command::Write { page: page_number as i32 }, page_number as i32);
+        Ok(())
     }
 
-    pub fn erase_page(&self, page_num: i32) -> i32 {
-        // Enable AHB clock (incase it was off).
-        unsafe {
-            pm::enable_clock(self.ahb_clock);
+    fn erase_page(&self, page_number: usize) -> Result<(), hil::flash::Error> {
+        if !self.is_idle() {
+            return Err(hil::flash::Error::Busy);
         }
-        if self.current_state.get() != FlashState::Ready {
-            return -1;
+        if page_number >= self.num_pages() {
+            return Err(hil::flash::Error::InvalidPage);
         }
 
-        self.current_state.set(FlashState::Unlocking);
-        self.current_command.set(Command::Erase { page: page_num });
-        self.lock_page_region(page_num, false);
-        0
+        self.start_modify(Command::Erase { page: page_number as i32 }, page_number as i32);
+        Ok(())
     }
 }
 
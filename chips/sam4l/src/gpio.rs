@@ -6,6 +6,7 @@ use kernel::common::volatile_cell::VolatileCell;
 use kernel::hil;
 use nvic;
 use nvic::NvicIdx::*;
+use bpm;
 
 use self::Pin::*;
 
@@ -394,6 +395,11 @@ impl GPIOPin {
     pub fn enable_interrupt(&self) {
         unsafe {
             let port: &mut Registers = mem::transmute(self.port);
+            // Pin change detection needs the GPIO clock, which Wait mode
+            // stops
+            if port.ier.val.get() & self.pin_mask == 0 {
+                bpm::CLOCKED_PERIPHERALS.acquire();
+            }
             nvic::enable(self.nvic);
             port.ier.set.set(self.pin_mask);
         }
@@ -401,6 +407,11 @@ impl GPIOPin {
 
     pub fn disable_interrupt(&self) {
         let port: &mut Registers = unsafe { mem::transmute(self.port) };
+        if port.ier.val.get() & self.pin_mask != 0 {
+            unsafe {
+                bpm::CLOCKED_PERIPHERALS.release();
+            }
+        }
         port.ier.clear.set(self.pin_mask);
         if port.ier.val.get() == 0 {
             unsafe {
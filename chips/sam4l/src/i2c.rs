@@ -9,16 +9,31 @@
 //! The point is that until this changes, and this notice is taken away: IF YOU
 //! CHANGE THIS DRIVER, TEST RIGOROUSLY!!!
 //!
+//! Transactions are made of segments, each of which becomes one or more TWIM
+//! commands of at most 255 bytes. While a command runs, the one after it
+//! waits in the next command register, and the DMA is pointed at its bytes
+//! once it begins.
+//!
+//! With an alarm set through `set_timeout_alarm`, transactions that take too
+//! long, typically because a slave holds SDA low, are given up on. If the
+//! board also passed the bus pins to `set_bus_pins`, the bus is then freed by
+//! clocking SCL by hand until the slave lets go and issuing a STOP. Clients
+//! get `Error::Timeout` either way.
+//!
 
 
 use core::cell::Cell;
+use core::cmp;
 use core::mem;
 use dma::{DMAChannel, DMAClient, DMAPeripheral};
+use gpio::{GPIOPin, PeripheralFunction};
 use helpers::*;
 use kernel::common::take_cell::TakeCell;
 use kernel::common::volatile_cell::VolatileCell;
 
 use kernel::hil;
+use kernel::hil::i2c::{Direction, MAX_SEGMENTS, Segment};
+use kernel::hil::time::{self, Frequency, Time};
 use nvic;
 use pm;
 
@@ -110,7 +125,17 @@ pub struct I2CHw {
     slave_nvic: Option<nvic::NvicIdx>,
     master_client: TakeCell<&'static hil::i2c::I2CHwMasterClient>,
     slave_client: TakeCell<&'static hil::i2c::I2CHwSlaveClient>,
-    on_deck: TakeCell<(DMAPeripheral, usize)>,
+    /// DMA peripheral, buffer offset and length of the command in the next
+    /// command register.
+    on_deck: TakeCell<(DMAPeripheral, usize, usize)>,
+    chip: Cell<u8>,
+    segments: Cell<[Segment; MAX_SEGMENTS]>,
+    num_segments: Cell<usize>,
+    /// The segment, and the position in it, of the next command to issue.
+    cursor: Cell<(usize, usize)>,
+    timeout_alarm: TakeCell<&'static time::Alarm<Frequency = time::Freq16KHz>>,
+    /// SCL and SDA, along with the function that connects them to the TWIM.
+    bus_pins: Cell<Option<(&'static GPIOPin, &'static GPIOPin, PeripheralFunction)>>,
 
     slave_enabled: Cell<bool>,
     my_slave_address: Cell<u8>,
@@ -159,6 +184,23 @@ pub const START: usize = 1 << 13;
 pub const STOP: usize = 1 << 14;
 pub const ACKLAST: usize = 1 << 24;
 
+/// The most bytes a single command can move (CMDR.NBYTES).
+const MAX_NBYTES: usize = 255;
+
+/// Time a transaction may take besides the time for its bytes, in ms.
+const TIMEOUT_MS: u32 = 20;
+
+/// Bytes a transaction must move per ms of its timeout. This is about a tenth
+/// of the speed of the bus, leaving room for slaves that stretch the clock.
+const TIMEOUT_BYTES_PER_MS: usize = 5;
+
+const NO_SEGMENT: Segment = Segment {
+    direction: Direction::Write,
+    stop: true,
+    start: 0,
+    len: 0,
+};
+
 // Need to implement the `new` function on the I2C device as a constructor.
 // This gets called from the device tree.
 impl I2CHw {
@@ -183,6 +225,12 @@ impl I2CHw {
             master_client: TakeCell::empty(),
             slave_client: TakeCell::empty(),
             on_deck: TakeCell::empty(),
+            chip: Cell::new(0),
+            segments: Cell::new([NO_SEGMENT; MAX_SEGMENTS]),
+            num_segments: Cell::new(0),
+            cursor: Cell::new((0, 0)),
+            timeout_alarm: TakeCell::empty(),
+            bus_pins: Cell::new(None),
 
             slave_enabled: Cell::new(false),
             my_slave_address: Cell::new(0),
@@ -236,6 +284,22 @@ impl I2CHw {
         self.slave_client.replace(client);
     }
 
+    /// Sets the alarm that times out transactions. This `I2CHw` must be the
+    /// client of the alarm.
+    pub fn set_timeout_alarm(&self,
+                             alarm: &'static time::Alarm<Frequency = time::Freq16KHz>) {
+        self.timeout_alarm.replace(alarm);
+    }
+
+    /// Sets the pins used to free the bus after a timeout. `function` is the
+    /// peripheral function that connects them to this TWIM.
+    pub fn set_bus_pins(&self,
+                        scl: &'static GPIOPin,
+                        sda: &'static GPIOPin,
+                        function: PeripheralFunction) {
+        self.bus_pins.set(Some((scl, sda, function)));
+    }
+
     pub fn handle_interrupt(&self) {
         use kernel::hil::i2c::Error;
         let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
@@ -252,12 +316,38 @@ impl I2CHw {
             _ => None
         };
 
-        match self.on_deck.take() {
-            None => {
+        match (err, self.on_deck.take()) {
+            (Some(Error::CommandComplete), Some((dma_periph, start, len))) => {
+                // The command that was on deck has begun. Put the one after
+                // it on deck and point the DMA at the bytes of the one that
+                // began, which stretches the clock until then.
+                self.next_command().map(|(command, pid, next_start, next_len)| {
+                    write_volatile(&mut regs.next_command, command);
+                    self.on_deck.replace((pid, next_start, next_len));
+                });
+
+                // Enable transaction error interrupts
+                write_volatile(&mut regs.interrupt_enable,
+                               (1 << 3)    // CCOMP   - Command completed
+                               | (1 << 8)    // ANAK   - Address not ACKd
+                               | (1 << 9)    // DNAK   - Data not ACKd
+                               | (1 << 10)); // ARBLST - Abitration lost
+                self.dma.map(|dma| {
+                    let buf = dma.abort_xfer().unwrap();
+                    dma.prepare_xfer_at(dma_periph, buf, start, len);
+                    dma.start_xfer();
+                });
+            }
+            (None, Some(on_deck)) => {
+                self.on_deck.replace(on_deck);
+            }
+            (err, _) => {
                 write_volatile(&mut regs.command, 0);
                 write_volatile(&mut regs.next_command, 0);
 
                 err.map(|err| {
+                    self.timeout_alarm.map(|alarm| alarm.disable());
+
                     // enable, reset, disable
                     write_volatile(&mut regs.control, 0x1 << 0);
                     write_volatile(&mut regs.control, 0x1 << 7);
@@ -278,35 +368,57 @@ impl I2CHw {
                     });
                 });
             }
-            Some((dma_periph, len)) => {
-                // Enable transaction error interrupts
-                write_volatile(&mut regs.interrupt_enable,
-                               (1 << 3)    // CCOMP   - Command completed
-                               | (1 << 8)    // ANAK   - Address not ACKd
-                               | (1 << 9)    // DNAK   - Data not ACKd
-                               | (1 << 10)); // ARBLST - Abitration lost
-                self.dma.map(|dma| {
-                    let buf = dma.abort_xfer().unwrap();
-                    dma.prepare_xfer(dma_periph, buf, len);
-                    dma.start_xfer();
-                });
-            }
         }
     }
 
-    fn setup_xfer(&self, chip: u8, flags: usize, read: bool, len: u8) {
+    /// Builds the next command of the transaction and moves the cursor past
+    /// it. Returns the command along with the DMA peripheral, buffer offset
+    /// and length it needs. Segments longer than a command can move are
+    /// split into commands that continue without a START.
+    fn next_command(&self) -> Option<(usize, DMAPeripheral, usize, usize)> {
+        let (index, position) = self.cursor.get();
+        if index >= self.num_segments.get() {
+            return None;
+        }
+        let segment = self.segments.get()[index];
+        let len = cmp::min(segment.len - position, MAX_NBYTES);
+        let segment_done = position + len == segment.len;
+        let last = index + 1 == self.num_segments.get();
+        let read = segment.direction == Direction::Read;
+
+        let mut flags = 0;
+        if position == 0 {
+            flags |= START;
+        }
+        if segment_done && (segment.stop || last) {
+            flags |= STOP;
+        }
+        if read && !segment_done {
+            // Acknowledge the last byte so the slave keeps sending
+            flags |= ACKLAST;
+        }
+        if segment_done {
+            self.cursor.set((index + 1, 0));
+        } else {
+            self.cursor.set((index, position + len));
+        }
+
+        let command = ((self.chip.get() as usize) << 1) // 7 bit address at offset 1 (8th
+                                                        // bit is ignored anyway)
+                    | flags  // START, STOP & ACKLAST flags
+                    | (1 << 15) // VALID
+                    | len << 16 // NBYTES (at most 255)
+                    | if read { 1 } else { 0 };
+        let pid = if read { self.dma_pids.0 } else { self.dma_pids.1 };
+        Some((command, pid, segment.start + position, len))
+    }
+
+    fn setup_xfer(&self, command: usize) {
         let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
 
         // disable before configuring
         write_volatile(&mut regs.control, 0x1 << 1);
 
-        let read = if read { 1 } else { 0 };
-        let command = ((chip as usize) << 1) // 7 bit address at offset 1 (8th
-                                             // bit is ignored anyway)
-                    | flags  // START, STOP & ACKLAST flags
-                    | (1 << 15) // VALID
-                    | (len as usize) << 16 // NBYTES (at most 255)
-                    | read;
         write_volatile(&mut regs.command, command);
         write_volatile(&mut regs.next_command, 0);
 
@@ -318,19 +430,12 @@ impl I2CHw {
                        | (1 << 10)); // ARBLST - Abitration lost
     }
 
-    fn setup_nextfer(&self, chip: u8, flags: usize, read: bool, len: u8) {
+    fn setup_nextfer(&self, command: usize) {
         let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
 
         // disable before configuring
         write_volatile(&mut regs.control, 0x1 << 1);
 
-        let read = if read { 1 } else { 0 };
-        let command = ((chip as usize) << 1) // 7 bit address at offset 1 (8th
-                                             // bit is ignored anyway)
-                    | flags  // START, STOP & ACKLAST flags
-                    | (1 << 15) // VALID
-                    | (len as usize) << 16 // NBYTES (at most 255)
-                    | read;
         write_volatile(&mut regs.next_command, command);
 
         // Enable
@@ -345,34 +450,93 @@ impl I2CHw {
 
     }
 
-    pub fn write(&self, chip: u8, flags: usize, data: &'static mut [u8], len: u8) {
-        self.dma.map(move |dma| {
-            dma.enable();
-            dma.prepare_xfer(self.dma_pids.1, data, len as usize);
-            self.setup_xfer(chip, flags, false, len);
-            self.master_enable();
-            dma.start_xfer();
+    /// Starts a transaction of the first `MAX_SEGMENTS` of `segments`, cut
+    /// to fit in `data`. The first command is issued with the second on
+    /// deck, and each command complete interrupt puts another one on deck.
+    pub fn transaction(&self, chip: u8, data: &'static mut [u8], segments: &[Segment]) {
+        let mut stored = [NO_SEGMENT; MAX_SEGMENTS];
+        let mut count = 0;
+        for (slot, segment) in stored.iter_mut().zip(segments.iter()) {
+            let start = cmp::min(segment.start, data.len());
+            *slot = Segment {
+                start: start,
+                len: cmp::min(segment.len, data.len() - start),
+                ..*segment
+            };
+            count += 1;
+        }
+        if count == 0 {
+            // Just address the slave, with an empty write
+            count = 1;
+        }
+        self.chip.set(chip);
+        self.segments.set(stored);
+        self.num_segments.set(count);
+        self.cursor.set((0, 0));
+
+        let bytes: usize = stored[..count].iter().map(|segment| segment.len).sum();
+        self.timeout_alarm.map(|alarm| {
+            let ms = TIMEOUT_MS + (bytes / TIMEOUT_BYTES_PER_MS) as u32;
+            let tics = ms * time::Freq16KHz::frequency() / 1000;
+            alarm.set_alarm(alarm.now().wrapping_add(tics));
         });
-    }
 
-    pub fn read(&self, chip: u8, flags: usize, data: &'static mut [u8], len: u8) {
         self.dma.map(move |dma| {
             dma.enable();
-            dma.prepare_xfer(self.dma_pids.0, data, len as usize);
-            self.setup_xfer(chip, flags, true, len);
-            self.master_enable();
+            // There is always a first command, as there is a segment
+            self.next_command().map(|(command, pid, start, len)| {
+                dma.prepare_xfer_at(pid, data, start, len);
+                self.setup_xfer(command);
+            });
+            match self.next_command() {
+                Some((command, pid, start, len)) => {
+                    self.setup_nextfer(command);
+                    self.on_deck.replace((pid, start, len));
+                }
+                None => self.master_enable(),
+            }
             dma.start_xfer();
         });
     }
 
-    pub fn write_read(&self, chip: u8, data: &'static mut [u8], split: u8, read_len: u8) {
-        self.dma.map(move |dma| {
-            dma.enable();
-            dma.prepare_xfer(self.dma_pids.1, data, split as usize);
-            self.setup_xfer(chip, START, false, split);
-            self.setup_nextfer(chip, START | STOP, true, read_len);
-            self.on_deck.replace((self.dma_pids.0, read_len as usize));
-            dma.start_xfer();
+    /// Frees the bus from a slave holding SDA low, which happens when the
+    /// slave lost track of the clock in the middle of sending a byte. SCL is
+    /// clocked until the slave lets go of SDA, and then a STOP resets the
+    /// slaves. The pins are driven as open drain GPIOs meanwhile.
+    fn recover_bus(&self) {
+        self.bus_pins.get().map(|(scl, sda, function)| {
+            // Let both lines be pulled high
+            for pin in [scl, sda].iter() {
+                pin.disable_output();
+                pin.clear();
+                pin.enable_schmidtt_trigger();
+                pin.enable();
+            }
+
+            // The slave has at most eight bits left to send, and lets go of
+            // SDA for the acknowledge bit at the latest
+            for _ in 0..9 {
+                if sda.read() {
+                    break;
+                }
+                scl.enable_output();
+                bus_delay();
+                scl.disable_output();
+                bus_delay();
+            }
+
+            // STOP, with SDA rising while SCL is high
+            scl.enable_output();
+            bus_delay();
+            sda.enable_output();
+            bus_delay();
+            scl.disable_output();
+            bus_delay();
+            sda.disable_output();
+            bus_delay();
+
+            scl.select_peripheral(function);
+            sda.select_peripheral(function);
         });
     }
 
@@ -703,6 +867,54 @@ impl I2CHw {
     }
 }
 
+/// Waits for half a clock period of a 100kHz bus, or longer.
+fn bus_delay() {
+    let loops = unsafe { pm::get_system_frequency() } / 200000;
+    for _ in 0..loops {
+        unsafe {
+            asm!("nop" :::: "volatile");
+        }
+    }
+}
+
+impl time::Client for I2CHw {
+    /// The transaction in progress took too long, so give up on it and free
+    /// the bus in case a slave is holding it.
+    fn fired(&self) {
+        use kernel::hil::i2c::Error;
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        // Without a buffer in the DMA, the transaction finished after all
+        let buf = match self.dma.take() {
+            Some(dma) => {
+                let b = dma.abort_xfer();
+                self.dma.replace(dma);
+                b
+            }
+            None => None,
+        };
+        buf.map(|buf| {
+            write_volatile(&mut regs.interrupt_disable, !0);
+            write_volatile(&mut regs.control, 0x1 << 1);
+            write_volatile(&mut regs.command, 0);
+            write_volatile(&mut regs.next_command, 0);
+            self.on_deck.take();
+
+            self.recover_bus();
+
+            // enable, reset, disable
+            write_volatile(&mut regs.control, 0x1 << 0);
+            write_volatile(&mut regs.control, 0x1 << 7);
+            write_volatile(&mut regs.control, 0x1 << 1);
+            write_volatile(&mut regs.status_clear, !0);
+
+            self.master_client.map(|client| {
+                client.command_complete(buf, Error::Timeout);
+            });
+        });
+    }
+}
+
 impl DMAClient for I2CHw {
     fn xfer_done(&self, _pid: DMAPeripheral) {}
 }
@@ -751,16 +963,45 @@ impl hil::i2c::I2CMaster for I2CHw {
         self.disable_interrupts();
     }
 
-    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
-        I2CHw::write(self, addr, START | STOP, data, len);
+    fn write(&self, addr: u8, data: &'static mut [u8], len: usize) {
+        let segment = Segment {
+            direction: Direction::Write,
+            stop: true,
+            start: 0,
+            len: len,
+        };
+        I2CHw::transaction(self, addr, data, &[segment]);
+    }
+
+    fn read(&self, addr: u8, data: &'static mut [u8], len: usize) {
+        let segment = Segment {
+            direction: Direction::Read,
+            stop: true,
+            start: 0,
+            len: len,
+        };
+        I2CHw::transaction(self, addr, data, &[segment]);
     }
 
-    fn read(&self, addr: u8, data: &'static mut [u8], len: u8) {
-        I2CHw::read(self, addr, START | STOP, data, len);
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: usize, read_len: usize) {
+        // The read starts over at the beginning of the buffer
+        let segments = [Segment {
+                            direction: Direction::Write,
+                            stop: false,
+                            start: 0,
+                            len: write_len,
+                        },
+                        Segment {
+                            direction: Direction::Read,
+                            stop: true,
+                            start: 0,
+                            len: read_len,
+                        }];
+        I2CHw::transaction(self, addr, data, &segments);
     }
 
-    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
-        I2CHw::write_read(self, addr, data, write_len, read_len)
+    fn transaction(&self, addr: u8, data: &'static mut [u8], segments: &[Segment]) {
+        I2CHw::transaction(self, addr, data, segments);
     }
 }
 
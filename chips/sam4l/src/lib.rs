@@ -24,6 +24,8 @@ pub mod scif;
 pub mod adc;
 pub mod flashcalw;
 pub mod wdt;
+pub mod trng;
+pub mod tc;
 
 unsafe extern "C" fn unhandled_interrupt() {
     let mut interrupt_number: u32;
@@ -145,7 +147,7 @@ pub static INTERRUPT_TABLE: [Option<unsafe extern fn()>; 80] = [
     /* EIC7 */          Option::Some(unhandled_interrupt),
     /* EIC8 */          Option::Some(unhandled_interrupt),
     /* IISC */          Option::Some(unhandled_interrupt),
-    /* SPI */           Option::Some(unhandled_interrupt),
+    /* SPI */           Option::Some(spi::spi_handler),
     /* TC00 */          Option::Some(unhandled_interrupt),
     /* TC01 */          Option::Some(unhandled_interrupt),
     /* TC02 */          Option::Some(unhandled_interrupt),
@@ -164,7 +166,7 @@ pub static INTERRUPT_TABLE: [Option<unsafe extern fn()>; 80] = [
     /* DACC */          Option::Some(unhandled_interrupt),
     /* ACIFC */         Option::Some(unhandled_interrupt),
     /* ABDACB */        Option::Some(unhandled_interrupt),
-    /* TRNG */          Option::Some(unhandled_interrupt),
+    /* TRNG */          Option::Some(trng::trng_handler),
     /* PARC */          Option::Some(unhandled_interrupt),
     /* CATB */          Option::Some(unhandled_interrupt),
     None,
@@ -13,10 +13,13 @@ use kernel::hil::spi;
 use kernel::hil::spi::ClockPhase;
 use kernel::hil::spi::ClockPolarity;
 use kernel::hil::spi::SpiMasterClient;
+use kernel::hil::spi::SpiSlaveClient;
+use bpm;
+use nvic;
 use pm;
 
 
-/// Implementation of DMA-based SPI master communication for
+/// Implementation of DMA-based SPI master and slave communication for
 /// the Atmel SAM4L CortexM4 microcontroller.
 /// Authors: Sam Crow <samcrow@uw.edu>
 ///          Philip Levis <pal@cs.stanford.edu>
@@ -49,6 +52,11 @@ struct SpiRegisters {
 
 const SPI_BASE: u32 = 0x40008000;
 
+// Status and interrupt register bits
+const SR_RDRF: u32 = 1 << 0;
+const SR_TDRE: u32 = 1 << 1;
+const SR_NSSR: u32 = 1 << 8;
+
 /// Values for selected peripherals
 #[derive(Copy,Clone)]
 pub enum Peripheral {
@@ -60,9 +68,22 @@ pub enum Peripheral {
 
 ///
 /// The SAM4L supports four peripherals.
+///
+/// The SPI acts as a master after `SpiMaster::init` and as a slave after
+/// `SpiSlave::init`. In slave mode the hardware only interrupts when the
+/// master releases NSS, so the start of a selection is noticed when the
+/// first byte arrives while no transfer is set up.
 pub struct Spi {
     registers: *mut SpiRegisters,
     client: TakeCell<&'static SpiMasterClient>,
+    slave_client: TakeCell<&'static SpiSlaveClient>,
+    slave: Cell<bool>,
+    // byte sent in slave mode while no write buffer is set up
+    write_byte: Cell<u8>,
+    // whether chip_selected was reported for the current selection
+    selected: Cell<bool>,
+    // whether the current slave transfer has a read buffer
+    slave_reading: Cell<bool>,
     dma_read: TakeCell<&'static mut DMAChannel>,
     dma_write: TakeCell<&'static mut DMAChannel>,
     // keep track of which how many DMA transfers are pending to correctly
@@ -79,6 +100,11 @@ impl Spi {
         Spi {
             registers: SPI_BASE as *mut SpiRegisters,
             client: TakeCell::empty(),
+            slave_client: TakeCell::empty(),
+            slave: Cell::new(false),
+            write_byte: Cell::new(0),
+            selected: Cell::new(false),
+            slave_reading: Cell::new(false),
             dma_read: TakeCell::empty(),
             dma_write: TakeCell::empty(),
             transfers_in_progress: Cell::new(0),
@@ -218,6 +244,87 @@ impl Spi {
             pm::enable_clock(pm::Clock::PBA(pm::PBAClock::SPI));
         }
     }
+
+    /// Handles the slave mode interrupts: a byte received while no transfer
+    /// is set up, a transmit register to refill while a transfer has no
+    /// write buffer, and the master releasing NSS.
+    pub fn handle_interrupt(&self) {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        // Reading SR clears NSSR
+        let sr = regs.sr.get();
+        let imr = regs.imr.get();
+
+        if sr & imr & SR_RDRF != 0 {
+            // Discard the byte and queue the write byte for the next one
+            regs.rdr.get();
+            regs.tdr.set(self.write_byte.get() as u32);
+            if !self.selected.get() {
+                self.selected.set(true);
+                self.slave_client.map(|client| client.chip_selected());
+            }
+        }
+
+        if sr & imr & SR_TDRE != 0 {
+            regs.tdr.set(self.write_byte.get() as u32);
+        }
+
+        if sr & imr & SR_NSSR != 0 {
+            self.selected.set(false);
+            if self.transfers_in_progress.get() != 0 {
+                // The master ended the selection before the transfer was
+                // complete, so report the bytes exchanged so far
+                let len = self.dma_length.get();
+                let exchanged = if self.slave_reading.get() {
+                    len - self.dma_read.map_or(len, |read| read.transfer_counter())
+                } else {
+                    // The last byte moved into TDR is still there unless
+                    // TDR is empty
+                    let moved = len - self.dma_write.map_or(len, |write| write.transfer_counter());
+                    if moved > 0 && sr & SR_TDRE == 0 {
+                        moved - 1
+                    } else {
+                        moved
+                    }
+                };
+                self.slave_transfer_done(exchanged);
+            }
+        }
+    }
+
+    /// Ends the current slave transfer after `len` bytes and returns to
+    /// sending the write byte.
+    fn slave_transfer_done(&self, len: usize) {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        let txbuf = self.dma_write.map_or(None, |dma| {
+            let buf = dma.abort_xfer();
+            dma.disable();
+            buf
+        });
+
+        let rxbuf = self.dma_read.map_or(None, |dma| {
+            let buf = dma.abort_xfer();
+            dma.disable();
+            buf
+        });
+
+        self.transfers_in_progress.set(0);
+        self.dma_length.set(0);
+        regs.idr.set(SR_TDRE);
+        let sr = regs.sr.get();
+        if sr & SR_NSSR != 0 {
+            // Reading SR cleared NSSR, so the interrupt will not see it
+            self.selected.set(false);
+        }
+        // TDR may still hold the last byte of the write buffer
+        if sr & SR_TDRE != 0 {
+            regs.tdr.set(self.write_byte.get() as u32);
+        }
+        regs.ier.set(SR_RDRF);
+
+        self.slave_client.map(move |client| client.read_write_done(txbuf, rxbuf, len));
+    }
 }
 
 impl spi::SpiMaster for Spi {
@@ -235,6 +342,15 @@ impl spi::SpiMaster for Spi {
         self.enable_clock();
         regs.cr.set(1 << 24);
 
+        // Drop the interrupts and the clock a previous slave configuration
+        // needed
+        if self.slave.get() {
+            unsafe {
+                bpm::CLOCKED_PERIPHERALS.release();
+            }
+        }
+        self.slave.set(false);
+        regs.idr.set(SR_RDRF | SR_NSSR);
         let mut mode = regs.mr.get();
         mode |= 1; // Enable master mode
         mode |= 1 << 4; // Disable mode fault detection (open drain outputs not supported)
@@ -407,6 +523,137 @@ impl spi::SpiMaster for Spi {
     }
 }
 
+impl spi::SpiSlave for Spi {
+    fn set_client(&self, client: &'static SpiSlaveClient) {
+        self.slave_client.replace(client);
+    }
+
+    /// Initialize SPI in slave mode: the clock is idle on low, data is
+    /// sampled on the leading edge and 0 is sent while no transfer is set
+    /// up.
+    fn init(&self) {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        self.enable_clock();
+        regs.cr.set(1 << 24);
+
+        // Stay out of Wait mode, which stops the clock needed to notice the
+        // master selecting the device
+        if !self.slave.get() {
+            unsafe {
+                bpm::CLOCKED_PERIPHERALS.acquire();
+            }
+        }
+
+        // Slave mode is the reset value of MR; the slave uses CSR0
+        self.slave.set(true);
+        regs.mr.set(0);
+        regs.csr0.set(1 << 1);
+
+        self.enable();
+        regs.tdr.set(self.write_byte.get() as u32);
+        regs.ier.set(SR_RDRF | SR_NSSR);
+        unsafe {
+            nvic::enable(nvic::NvicIdx::SPI);
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.transfers_in_progress.get() != 0
+    }
+
+    fn set_write_byte(&self, write_byte: u8) {
+        self.write_byte.set(write_byte);
+    }
+
+    /// Sets up the buffers for the next transfer. Without a write buffer,
+    /// the write byte is sent for every byte; without a read buffer, the
+    /// received bytes are discarded. Returns false if a transfer is already
+    /// set up or if neither buffer is given.
+    fn read_write_bytes(&self,
+                        write_buffer: Option<&'static mut [u8]>,
+                        read_buffer: Option<&'static mut [u8]>,
+                        len: usize)
+                        -> bool {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        if self.is_busy() || (write_buffer.is_none() && read_buffer.is_none()) {
+            return false;
+        }
+
+        let mut count = len;
+        write_buffer.as_ref().map(|buf| count = cmp::min(count, buf.len()));
+        read_buffer.as_ref().map(|buf| count = cmp::min(count, buf.len()));
+        self.dma_length.set(count);
+        self.slave_reading.set(read_buffer.is_some());
+
+        // Bytes received from now on go to the read buffer
+        regs.idr.set(SR_RDRF);
+
+        let mut transfers = 0;
+        match write_buffer {
+            Some(wbuf) => {
+                transfers += 1;
+                self.dma_write.map(move |write| {
+                    write.enable();
+                    write.do_xfer(DMAPeripheral::SPI_TX, wbuf, count);
+                });
+            }
+            None => regs.ier.set(SR_TDRE),
+        }
+        read_buffer.map(|rbuf| {
+            transfers += 1;
+            self.dma_read.map(move |read| {
+                read.enable();
+                read.do_xfer(DMAPeripheral::SPI_RX, rbuf, count);
+            });
+        });
+        self.transfers_in_progress.set(transfers);
+
+        true
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        let mut csr = regs.csr0.get();
+        match polarity {
+            ClockPolarity::IdleHigh => csr |= 1,
+            ClockPolarity::IdleLow => csr &= 0xFFFFFFFE,
+        };
+        regs.csr0.set(csr);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        match regs.csr0.get() & 0x1 {
+            0 => ClockPolarity::IdleLow,
+            _ => ClockPolarity::IdleHigh,
+        }
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        let mut csr = regs.csr0.get();
+        match phase {
+            ClockPhase::SampleLeading => csr |= 1 << 1,
+            ClockPhase::SampleTrailing => csr &= 0xFFFFFFFD,
+        };
+        regs.csr0.set(csr);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        let regs: &mut SpiRegisters = unsafe { mem::transmute(self.registers) };
+
+        match (regs.csr0.get() >> 1) & 0x1 {
+            0 => ClockPhase::SampleTrailing,
+            _ => ClockPhase::SampleLeading,
+        }
+    }
+}
+
 impl DMAClient for Spi {
     fn xfer_done(&self, _pid: DMAPeripheral) {
         // Only callback that the transfer is done if either:
@@ -416,9 +663,16 @@ impl DMAClient for Spi {
         //    data over to/from the controller at the same time, so we don't want to abort
         //    prematurely.
 
+        // A slave transfer may already have been ended by the master
+        // releasing NSS
+        if self.transfers_in_progress.get() == 0 {
+            return;
+        }
         self.transfers_in_progress.set(self.transfers_in_progress.get() - 1);
 
-        if self.transfers_in_progress.get() == 0 {
+        if self.transfers_in_progress.get() == 0 && self.slave.get() {
+            self.slave_transfer_done(self.dma_length.get());
+        } else if self.transfers_in_progress.get() == 0 {
             let txbuf = self.dma_write.map_or(None, |dma| {
                 let buf = dma.abort_xfer();
                 dma.disable();
@@ -441,3 +695,5 @@ impl DMAClient for Spi {
         }
     }
 }
+
+interrupt_handler!(spi_handler, SPI);
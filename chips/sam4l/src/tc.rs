@@ -0,0 +1,250 @@
+//! PWM outputs of the SAM4L Timer/Counters (TC).
+//!
+//! The SAM4L has two TC peripherals with three 16-bit channels each, and every
+//! channel drives two outputs, A and B. In waveform mode a channel counts up
+//! from 0 to the value of its RC register, which sets the period. Each output
+//! is set when the counter restarts and cleared when it reaches the output's
+//! own compare register (RA or RB), which sets the duty cycle. Since both
+//! outputs of a channel share the counter, they also share the frequency.
+//!
+//! The counters run off the PBA clock, divided by 2, 8, 32 or 128. The
+//! smallest divider that fits the period in 16 bits is used, giving the most
+//! duty cycle resolution.
+
+use bpm;
+use core::cell::Cell;
+use core::mem;
+use kernel::common::volatile_cell::VolatileCell;
+use kernel::hil;
+use kernel::hil::pwm::MAX_DUTY_CYCLE;
+use pm::{self, Clock, PBAClock};
+
+#[repr(C, packed)]
+struct ChannelRegisters {
+    ccr: VolatileCell<u32>, // Channel control      (0x00)
+    cmr: VolatileCell<u32>, // Channel mode         (0x04)
+    smmr: VolatileCell<u32>, // Stepper motor mode  (0x08)
+    _reserved0: VolatileCell<u32>,
+    cv: VolatileCell<u32>, // Counter value         (0x10)
+    ra: VolatileCell<u32>, // Register A            (0x14)
+    rb: VolatileCell<u32>, // Register B            (0x18)
+    rc: VolatileCell<u32>, // Register C            (0x1c)
+    sr: VolatileCell<u32>, // Status                (0x20)
+    ier: VolatileCell<u32>, // Interrupt enable     (0x24)
+    idr: VolatileCell<u32>, // Interrupt disable    (0x28)
+    imr: VolatileCell<u32>, // Interrupt mask       (0x2c)
+}
+
+// Page 59 of SAM4L data sheet
+const TC0_BASE: usize = 0x40010000;
+const TC1_BASE: usize = 0x40014000;
+const CHANNEL_SIZE: usize = 0x40;
+
+// CCR
+const CLKEN: u32 = 1 << 0;
+const CLKDIS: u32 = 1 << 1;
+const SWTRG: u32 = 1 << 2;
+
+// SR
+const CLKSTA: u32 = 1 << 16;
+
+// CMR in waveform mode
+const TCCLKS_MASK: u32 = 0x7;
+const EEVT_XC0: u32 = 1 << 10; // Frees TIOB for use as an output
+const WAVSEL_UP_RC: u32 = 2 << 13;
+const WAVE: u32 = 1 << 15;
+const ACPA_SHIFT: u32 = 16;
+const ACPC_SHIFT: u32 = 18;
+const BCPB_SHIFT: u32 = 24;
+const BCPC_SHIFT: u32 = 26;
+const ACTION_MASK: u32 = 0x3;
+const ACTION_SET: u32 = 1;
+const ACTION_CLEAR: u32 = 2;
+
+/// PBA clock dividers of TIMER_CLOCK2 to TIMER_CLOCK5.
+const DIVIDERS: [(u32, usize); 4] = [(1, 2), (2, 8), (3, 32), (4, 128)];
+
+/// Largest period in counter ticks. One less than the counter range, so an
+/// always high output can use a compare value past the period.
+const MAX_PERIOD: usize = 0xfffe;
+
+const DEFAULT_FREQUENCY: usize = 1000;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Output {
+    A,
+    B,
+}
+
+impl Output {
+    /// Shifts of the actions on compare with the output's register and with
+    /// RC.
+    fn shifts(&self) -> (u32, u32) {
+        match *self {
+            Output::A => (ACPA_SHIFT, ACPC_SHIFT),
+            Output::B => (BCPB_SHIFT, BCPC_SHIFT),
+        }
+    }
+
+    fn other(&self) -> Output {
+        match *self {
+            Output::A => Output::B,
+            Output::B => Output::A,
+        }
+    }
+}
+
+pub struct TcPwm {
+    registers: *mut ChannelRegisters,
+    clock: PBAClock,
+    output: Output,
+    duty_cycle: Cell<usize>,
+    enabled: Cell<bool>,
+}
+
+pub static mut TC0_A0: TcPwm = TcPwm::new(TC0_BASE, PBAClock::TC0, 0, Output::A);
+pub static mut TC0_B0: TcPwm = TcPwm::new(TC0_BASE, PBAClock::TC0, 0, Output::B);
+pub static mut TC0_A1: TcPwm = TcPwm::new(TC0_BASE, PBAClock::TC0, 1, Output::A);
+pub static mut TC0_B1: TcPwm = TcPwm::new(TC0_BASE, PBAClock::TC0, 1, Output::B);
+pub static mut TC0_A2: TcPwm = TcPwm::new(TC0_BASE, PBAClock::TC0, 2, Output::A);
+pub static mut TC0_B2: TcPwm = TcPwm::new(TC0_BASE, PBAClock::TC0, 2, Output::B);
+pub static mut TC1_A0: TcPwm = TcPwm::new(TC1_BASE, PBAClock::TC1, 0, Output::A);
+pub static mut TC1_B0: TcPwm = TcPwm::new(TC1_BASE, PBAClock::TC1, 0, Output::B);
+pub static mut TC1_A1: TcPwm = TcPwm::new(TC1_BASE, PBAClock::TC1, 1, Output::A);
+pub static mut TC1_B1: TcPwm = TcPwm::new(TC1_BASE, PBAClock::TC1, 1, Output::B);
+pub static mut TC1_A2: TcPwm = TcPwm::new(TC1_BASE, PBAClock::TC1, 2, Output::A);
+pub static mut TC1_B2: TcPwm = TcPwm::new(TC1_BASE, PBAClock::TC1, 2, Output::B);
+
+impl TcPwm {
+    const fn new(base: usize, clock: PBAClock, channel: usize, output: Output) -> TcPwm {
+        TcPwm {
+            registers: (base + channel * CHANNEL_SIZE) as *mut ChannelRegisters,
+            clock: clock,
+            output: output,
+            duty_cycle: Cell::new(0),
+            enabled: Cell::new(false),
+        }
+    }
+
+    fn regs(&self) -> &ChannelRegisters {
+        unsafe { mem::transmute(self.registers) }
+    }
+
+    fn compare_register(&self, output: Output) -> &VolatileCell<u32> {
+        match output {
+            Output::A => &self.regs().ra,
+            Output::B => &self.regs().rb,
+        }
+    }
+
+    /// Sets the actions of `output` on its own compare and on RC.
+    fn set_actions(&self, output: Output, on_compare: u32, on_rc: u32) {
+        let (compare_shift, rc_shift) = output.shifts();
+        let cmr = self.regs().cmr.get() &
+                  !(ACTION_MASK << compare_shift | ACTION_MASK << rc_shift);
+        self.regs().cmr.set(cmr | on_compare << compare_shift | on_rc << rc_shift);
+    }
+
+    /// Whether `output` of the channel is generating a signal, which is the
+    /// case if it is set on RC compare.
+    fn is_active(&self, output: Output) -> bool {
+        let (_, rc_shift) = output.shifts();
+        (self.regs().cmr.get() >> rc_shift) & ACTION_MASK == ACTION_SET
+    }
+
+    fn update_compare(&self) {
+        let period = self.regs().rc.get() as usize;
+        let compare = period * self.duty_cycle.get() / MAX_DUTY_CYCLE;
+        // A compare value past the period is never reached, so the output
+        // stays high
+        let compare = if self.duty_cycle.get() >= MAX_DUTY_CYCLE { period + 1 } else { compare };
+        self.compare_register(self.output).set(compare as u32);
+    }
+
+    fn pba_frequency(&self) -> usize {
+        unsafe { pm::get_system_frequency() as usize }
+    }
+}
+
+impl hil::pwm::PwmPin for TcPwm {
+    fn set_frequency(&self, frequency_hz: usize) -> bool {
+        if frequency_hz == 0 {
+            return false;
+        }
+        let pba = self.pba_frequency();
+        let setting = DIVIDERS.iter()
+            .map(|&(tcclks, divider)| (tcclks, pba / divider / frequency_hz))
+            .find(|&(_, period)| period >= 2 && period <= MAX_PERIOD);
+        match setting {
+            Some((tcclks, period)) => {
+                unsafe {
+                    pm::enable_clock(Clock::PBA(self.clock));
+                }
+                let regs = self.regs();
+                let old_period = regs.rc.get() as usize;
+                regs.cmr.set(regs.cmr.get() & !TCCLKS_MASK | tcclks | WAVE | WAVSEL_UP_RC |
+                             EEVT_XC0);
+                regs.rc.set(period as u32);
+
+                // Keep the duty cycle of the other output of the channel
+                let other = self.compare_register(self.output.other());
+                if old_period > 0 {
+                    other.set((other.get() as usize * period / old_period) as u32);
+                }
+                self.update_compare();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_duty_cycle(&self, duty_cycle: usize) {
+        self.duty_cycle.set(if duty_cycle > MAX_DUTY_CYCLE {
+            MAX_DUTY_CYCLE
+        } else {
+            duty_cycle
+        });
+        if self.enabled.get() {
+            self.update_compare();
+        }
+    }
+
+    fn enable(&self) {
+        if self.enabled.get() {
+            return;
+        }
+        self.enabled.set(true);
+        // The counter runs off the PBA clock, so stay out of deep sleep while
+        // generating the signal
+        unsafe {
+            bpm::CLOCKED_PERIPHERALS.acquire();
+        }
+        if self.regs().rc.get() == 0 {
+            self.set_frequency(DEFAULT_FREQUENCY);
+        }
+        self.update_compare();
+        self.set_actions(self.output, ACTION_CLEAR, ACTION_SET);
+        if self.regs().sr.get() & CLKSTA == 0 {
+            self.regs().ccr.set(CLKEN | SWTRG);
+        }
+    }
+
+    fn disable(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+        self.enabled.set(false);
+        // Clearing the output on both compares keeps it low
+        self.set_actions(self.output, ACTION_CLEAR, ACTION_CLEAR);
+        if !self.is_active(self.output.other()) {
+            self.regs().ccr.set(CLKDIS);
+        }
+        unsafe {
+            bpm::CLOCKED_PERIPHERALS.release();
+        }
+    }
+
+    fn max_frequency(&self) -> usize {
+        self.pba_frequency() / DIVIDERS[0].1 / 2
+    }
+}
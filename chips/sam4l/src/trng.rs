@@ -0,0 +1,122 @@
+//! Implementation of the SAM4L TRNG.
+//!
+//! The TRNG produces a new 32-bit random value every 84 peripheral clock
+//! cycles and raises the DATRDY interrupt when one is available. Values are
+//! copied into the buffer four bytes at a time until it is full, after which
+//! the peripheral and its clock are turned off again.
+
+use bpm;
+use core::cell::Cell;
+use core::mem;
+use kernel::common::take_cell::TakeCell;
+use kernel::common::volatile_cell::VolatileCell;
+use kernel::hil;
+use nvic;
+use pm::{self, Clock, PBAClock};
+
+#[repr(C, packed)]
+pub struct TrngRegisters {
+    cr: VolatileCell<u32>, // Control               (0x00)
+    _reserved0: [VolatileCell<u32>; 3],
+    ier: VolatileCell<u32>, // Interrupt enable     (0x10)
+    idr: VolatileCell<u32>, // Interrupt disable    (0x14)
+    imr: VolatileCell<u32>, // Interrupt mask       (0x18)
+    isr: VolatileCell<u32>, // Interrupt status     (0x1c)
+    _reserved1: [VolatileCell<u32>; 12],
+    odata: VolatileCell<u32>, // Output data        (0x50)
+}
+
+// Page 59 of SAM4L data sheet
+const BASE_ADDRESS: *mut TrngRegisters = 0x40068000 as *mut TrngRegisters;
+
+// Writes to CR are ignored unless they carry this key ("RNG")
+const KEY: u32 = 0x524e47 << 8;
+
+pub struct Trng {
+    registers: *mut TrngRegisters,
+    buffer: TakeCell<&'static mut [u8]>,
+    len: Cell<usize>,
+    index: Cell<usize>,
+    client: Cell<Option<&'static hil::rng::Client>>,
+}
+
+pub static mut TRNG: Trng = Trng::new(BASE_ADDRESS);
+
+impl Trng {
+    const fn new(base_address: *mut TrngRegisters) -> Trng {
+        Trng {
+            registers: base_address,
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            index: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static hil::rng::Client) {
+        self.client.set(Some(client));
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs: &mut TrngRegisters = unsafe { mem::transmute(self.registers) };
+        if regs.isr.get() & 1 == 0 {
+            return;
+        }
+
+        // Reading ODATA clears the interrupt
+        let value = regs.odata.get();
+        let done = self.buffer.map_or(true, |buf| {
+            let mut index = self.index.get();
+            for i in 0..4 {
+                if index >= self.len.get() {
+                    break;
+                }
+                buf[index] = (value >> (i * 8)) as u8;
+                index += 1;
+            }
+            self.index.set(index);
+            index >= self.len.get()
+        });
+
+        if done {
+            regs.idr.set(1);
+            regs.cr.set(KEY);
+            unsafe {
+                pm::disable_clock(Clock::PBA(PBAClock::TRNG));
+                bpm::CLOCKED_PERIPHERALS.release();
+            }
+            self.buffer.take().map(|buf| {
+                self.client.get().map(move |client| client.fill_done(buf, self.len.get()));
+            });
+        }
+    }
+}
+
+impl hil::rng::RNG for Trng {
+    fn fill(&self, buf: &'static mut [u8], len: usize) -> Result<(), &'static mut [u8]> {
+        let len = if len < buf.len() { len } else { buf.len() };
+        if self.buffer.is_some() || len == 0 {
+            return Err(buf);
+        }
+        let regs: &mut TrngRegisters = unsafe { mem::transmute(self.registers) };
+
+        self.len.set(len);
+        self.index.set(0);
+        self.buffer.replace(buf);
+
+        // The TRNG runs off the peripheral clock, so stay out of deep sleep
+        // until the buffer is full
+        unsafe {
+            pm::enable_clock(Clock::PBA(PBAClock::TRNG));
+            bpm::CLOCKED_PERIPHERALS.acquire();
+        }
+        regs.cr.set(KEY | 1);
+        regs.ier.set(1);
+        unsafe {
+            nvic::enable(nvic::NvicIdx::TRNG);
+        }
+        Ok(())
+    }
+}
+
+interrupt_handler!(trng_handler, TRNG);
@@ -403,14 +403,14 @@ impl USART {
         panic!("didn't write terminator stuff yet");
     }
 
-    // for use by panic in io.rs
+    // for use by transmit_sync
     pub fn send_byte(&self, byte: u8) {
         let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
         let thr_val: u32 = 0x00000000 | byte as u32;
         regs.thr.set(thr_val);
     }
 
-    // for use by panic in io.rs
+    // for use by transmit_sync
     pub fn tx_ready(&self) -> bool {
         let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
         let csr_val: u32 = regs.csr.get();
@@ -627,6 +627,26 @@ impl hil::uart::UART for USART {
     }
 }
 
+impl hil::uart::UARTSync for USART {
+    fn transmit_sync(&self, tx_data: &[u8]) {
+        self.enable_tx();
+        for b in tx_data.iter() {
+            while !self.tx_ready() {}
+            self.send_byte(*b);
+        }
+        while !self.tx_ready() {}
+    }
+
+    fn finish_transmit(&self) {
+        // The DMA keeps feeding the USART even when its interrupt cannot be
+        // serviced
+        if self.usart_tx_state.get() == USARTStateTX::DMA_Transmitting {
+            self.tx_dma.map(|dma| while dma.transfer_counter() != 0 {});
+        }
+        while !self.tx_ready() {}
+    }
+}
+
 impl hil::uart::UARTAdvanced for USART {
     fn receive_automatic(&self, rx_buffer: &'static mut [u8], interbyte_timeout: u8) {
         // quit current reception if any
@@ -1,19 +1,40 @@
 use core::nonzero::NonZero;
 use process;
 
-#[derive(Clone,Copy)]
+/// Identifies a process.
+///
+/// Besides the index of the process slot, an `AppId` records the unique
+/// identifier of the process that occupied the slot when the `AppId` was
+/// created. This ensures that an `AppId` (and any `Callback` or `AppSlice`
+/// holding it) never refers to a different process that is later loaded
+/// into the same slot. Two `AppId`s are equal only if both the slot and the
+/// identifier match.
+#[derive(Clone,Copy,PartialEq,Eq)]
 pub struct AppId {
     idx: usize,
+    identifier: usize,
 }
 
 impl AppId {
-    pub unsafe fn new(idx: usize) -> AppId {
-        AppId { idx: idx }
+    pub unsafe fn new(idx: usize, identifier: usize) -> AppId {
+        AppId {
+            idx: idx,
+            identifier: identifier,
+        }
     }
 
     pub fn idx(&self) -> usize {
         self.idx
     }
+
+    pub fn identifier(&self) -> usize {
+        self.identifier
+    }
+
+    /// Whether the process this `AppId` refers to is still alive.
+    pub fn is_live(&self) -> bool {
+        unsafe { process::process_for(*self).is_some() }
+    }
 }
 
 #[derive(Clone, Copy)]
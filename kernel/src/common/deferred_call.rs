@@ -0,0 +1,50 @@
+//! Deferred calls let a chip driver finish an operation from the kernel's
+//! main loop instead of from the call that started it, for operations that
+//! complete without an interrupt of their own. A chip services pending
+//! deferred calls together with its pending interrupts.
+//!
+//! Deferred calls are set and serviced only outside of interrupt handlers,
+//! so the pending set needs no synchronization.
+
+use common::VolatileCell;
+
+/// Bitmask of the pending deferred calls, indexed by chip-specific task
+/// number.
+static mut DEFERRED_CALL: VolatileCell<usize> = VolatileCell::new(0);
+
+#[derive(Copy, Clone)]
+pub struct DeferredCall(usize);
+
+impl DeferredCall {
+    /// Creates a handle for the chip-specific task number `task`, which must
+    /// be less than the number of bits in a `usize`.
+    pub const fn new(task: usize) -> DeferredCall {
+        DeferredCall(task)
+    }
+
+    /// Requests that the chip service this task.
+    pub fn set(&self) {
+        unsafe {
+            DEFERRED_CALL.set(DEFERRED_CALL.get() | 1 << self.0);
+        }
+    }
+
+    /// Whether any deferred call is pending.
+    pub fn has_tasks() -> bool {
+        unsafe { DEFERRED_CALL.get() != 0 }
+    }
+
+    /// Clears and returns the lowest-numbered pending task.
+    pub fn next_pending() -> Option<usize> {
+        unsafe {
+            let pending = DEFERRED_CALL.get();
+            if pending == 0 {
+                None
+            } else {
+                let task = pending.trailing_zeros() as usize;
+                DEFERRED_CALL.set(pending & !(1 << task));
+                Some(task)
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Common operations in the Tock OS.
 
+pub mod deferred_call;
 pub mod ring_buffer;
 pub mod queue;
 pub mod utils;
@@ -7,8 +8,11 @@ pub mod take_cell;
 pub mod volatile_cell;
 pub mod list;
 pub mod math;
+pub mod power_domain;
 
+pub use self::deferred_call::DeferredCall;
 pub use self::list::{List, ListLink, ListNode};
+pub use self::power_domain::PowerDomain;
 pub use self::queue::Queue;
 pub use self::ring_buffer::RingBuffer;
 pub use self::volatile_cell::VolatileCell;
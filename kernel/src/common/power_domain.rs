@@ -0,0 +1,36 @@
+//! Reference-counted tracking of peripheral activity.
+//!
+//! A `PowerDomain` counts the number of outstanding operations (e.g. DMA
+//! transfers in flight) that need the clocks of a domain to keep running. A
+//! chip's `sleep` implementation consults its domains to pick the deepest
+//! sleep state that will not stall any active peripheral.
+
+use common::VolatileCell;
+
+pub struct PowerDomain {
+    active: VolatileCell<usize>,
+}
+
+impl PowerDomain {
+    pub const fn new() -> PowerDomain {
+        PowerDomain { active: VolatileCell::new(0) }
+    }
+
+    /// Registers an operation that requires the domain to stay powered.
+    pub fn acquire(&self) {
+        self.active.set(self.active.get() + 1);
+    }
+
+    /// Releases an operation previously registered with `acquire`.
+    pub fn release(&self) {
+        let active = self.active.get();
+        if active > 0 {
+            self.active.set(active - 1);
+        }
+    }
+
+    /// Whether any operation currently requires the domain.
+    pub fn is_active(&self) -> bool {
+        self.active.get() != 0
+    }
+}
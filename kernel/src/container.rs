@@ -13,7 +13,7 @@ pub struct Container<T: Default> {
 }
 
 pub struct AppliedContainer<T> {
-    appid: usize,
+    appid: AppId,
     container: *mut T,
     _phantom: PhantomData<T>,
 }
@@ -24,7 +24,7 @@ impl<T> AppliedContainer<T> {
               R: Copy
     {
         let mut allocator = Allocator {
-            app: unsafe { process::PROCS[self.appid].as_mut().unwrap() },
+            app: unsafe { process::process_for(self.appid).unwrap() },
             app_id: self.appid,
         };
         let mut root = unsafe { Owned::new(self.container, self.appid) };
@@ -34,16 +34,16 @@ impl<T> AppliedContainer<T> {
 
 pub struct Allocator<'a> {
     app: &'a mut process::Process<'a>,
-    app_id: usize,
+    app_id: AppId,
 }
 
 pub struct Owned<T: ?Sized> {
     data: Unique<T>,
-    app_id: usize,
+    app_id: AppId,
 }
 
 impl<T: ?Sized> Owned<T> {
-    pub unsafe fn new(data: *mut T, app_id: usize) -> Owned<T> {
+    pub unsafe fn new(data: *mut T, app_id: AppId) -> Owned<T> {
         Owned {
             data: Unique::new(data),
             app_id: app_id,
@@ -51,18 +51,17 @@ impl<T: ?Sized> Owned<T> {
     }
 
     pub fn appid(&self) -> AppId {
-        unsafe { AppId::new(self.app_id) }
+        self.app_id
     }
 }
 
 impl<T: ?Sized> Drop for Owned<T> {
     fn drop(&mut self) {
         unsafe {
-            let app_id = self.app_id;
             let data = self.data.get_mut() as *mut T as *mut u8;
-            match process::PROCS[app_id] {
+            match process::process_for(self.app_id) {
                 None => {}
-                Some(ref mut app) => {
+                Some(app) => {
                     app.free(data);
                 }
             }
@@ -108,15 +107,14 @@ impl<T: Default> Container<T> {
 
     pub fn container(&self, appid: AppId) -> Option<AppliedContainer<T>> {
         unsafe {
-            let app_id = appid.idx();
-            match process::PROCS[app_id] {
-                Some(ref mut app) => {
+            match process::process_for(appid) {
+                Some(app) => {
                     let cntr = app.container_for::<T>(self.container_num);
                     if cntr.is_null() {
                         None
                     } else {
                         Some(AppliedContainer {
-                            appid: app_id,
+                            appid: appid,
                             container: *cntr,
                             _phantom: PhantomData,
                         })
@@ -132,15 +130,14 @@ impl<T: Default> Container<T> {
               R: Copy
     {
         unsafe {
-            let app_id = appid.idx();
-            match process::PROCS[app_id] {
-                Some(ref mut app) => {
+            match process::process_for(appid) {
+                Some(app) => {
                     app.container_for_or_alloc::<T>(self.container_num)
                         .map_or(Err(Error::OutOfMemory), move |root_ptr| {
-                            let mut root = Owned::new(root_ptr, app_id);
+                            let mut root = Owned::new(root_ptr, appid);
                             let mut allocator = Allocator {
                                 app: app,
-                                app_id: app_id,
+                                app_id: appid,
                             };
                             let res = fun(&mut root, &mut allocator);
                             Ok(res)
@@ -155,14 +152,15 @@ impl<T: Default> Container<T> {
         where F: Fn(&mut Owned<T>)
     {
         unsafe {
-            let itr = process::PROCS.iter_mut().filter_map(|p| p.as_mut());
-            for (app_id, app) in itr.enumerate() {
-                let ctr_ptr = app.container_for::<T>(self.container_num);
-                if !ctr_ptr.is_null() {
-                    let root_ptr = *ctr_ptr;
-                    let mut root = Owned::new(root_ptr, app_id);
-                    fun(&mut root);
-                }
+            for (idx, p) in process::PROCS.iter_mut().enumerate() {
+                p.as_mut().map(|app| {
+                    let ctr_ptr = app.container_for::<T>(self.container_num);
+                    if !ctr_ptr.is_null() {
+                        let root_ptr = *ctr_ptr;
+                        let mut root = Owned::new(root_ptr, AppId::new(idx, app.identifier()));
+                        fun(&mut root);
+                    }
+                });
             }
         }
     }
@@ -191,7 +189,10 @@ impl<'a, T: Default> Iterator for Iter<'a, T> {
         while self.index < self.len {
             let idx = self.index;
             self.index += 1;
-            let res = self.container.container(unsafe { AppId::new(idx) });
+            let appid = unsafe {
+                process::PROCS[idx].as_ref().map(|p| AppId::new(idx, p.identifier()))
+            };
+            let res = appid.and_then(|appid| self.container.container(appid));
             if res.is_some() {
                 return res;
             }
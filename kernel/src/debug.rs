@@ -0,0 +1,199 @@
+//! Non-blocking kernel debug output.
+//!
+//! The `debug!` macro formats its arguments into a static ring buffer and
+//! returns immediately. Buffered output is drained asynchronously over the
+//! UART passed to `assign_uart`. To share a UART with the console, boards
+//! pass each of them a device of a `capsules::virtual_uart::MuxUart`.
+//!
+//! In framed mode, set with `set_framed`, debug output is sent in frames on
+//! the kernel channel, see `frame`.
+//!
+//! On a kernel panic, `panic` switches the writer to synchronous mode, waits
+//! for any transmission in progress, flushes the buffered output and prints
+//! the panic message using blocking writes.
+//!
+//! # Usage
+//!
+//! ```
+//! let debug_uart = static_init!(
+//!     UartDevice<'static, usart::USART>,
+//!     UartDevice::new(uart_mux, false),
+//!     32);
+//! debug_uart.setup();
+//! kernel::debug::assign_uart(debug_uart);
+//!
+//! debug!("Hello from the kernel: {}", 42);
+//! ```
+
+use common::{Queue, RingBuffer};
+use common::take_cell::TakeCell;
+use core::cell::Cell;
+use core::fmt::{Arguments, Result, Write, write};
+use frame;
+use hil::uart::{self, UARTSync};
+
+static mut INTERNAL_BUF: [u8; 1024] = [0; 1024];
+static mut OUTPUT_BUF: [u8; 64] = [0; 64];
+
+pub struct DebugWriter {
+    uart: Cell<Option<&'static UARTSync>>,
+    internal_buffer: TakeCell<RingBuffer<'static, u8>>,
+    output_buffer: TakeCell<&'static mut [u8]>,
+    transmitting: Cell<bool>,
+    synchronous: Cell<bool>,
+    framed: Cell<bool>,
+    seq: Cell<u16>,
+}
+
+static mut DEBUG_WRITER: DebugWriter = DebugWriter {
+    uart: Cell::new(None),
+    internal_buffer: TakeCell::empty(),
+    output_buffer: TakeCell::empty(),
+    transmitting: Cell::new(false),
+    synchronous: Cell::new(false),
+    framed: Cell::new(false),
+    seq: Cell::new(0),
+};
+
+/// Sets the UART debug output is drained over.
+pub unsafe fn assign_uart(uart: &'static UARTSync) {
+    let writer = &DEBUG_WRITER;
+    writer.internal_buffer.replace(RingBuffer::new(&mut INTERNAL_BUF));
+    writer.output_buffer.replace(&mut OUTPUT_BUF);
+    writer.uart.set(Some(uart));
+    uart.set_client(writer);
+}
+
+pub fn get_writer() -> &'static DebugWriter {
+    unsafe { &DEBUG_WRITER }
+}
+
+/// Sets whether debug output is sent in frames.
+pub fn set_framed(framed: bool) {
+    get_writer().framed.set(framed);
+}
+
+impl DebugWriter {
+    fn buffer_bytes(&self, bytes: &[u8]) {
+        self.internal_buffer.map(|ring| for b in bytes.iter() {
+            if !ring.enqueue(*b) {
+                // Drop output rather than block the kernel
+                break;
+            }
+        });
+    }
+
+    /// Starts transmitting buffered debug output if the UART is idle.
+    fn publish(&self) {
+        if self.transmitting.get() {
+            return;
+        }
+        self.uart.get().map(|uart| {
+            self.output_buffer.take().map(|out| {
+                let len = self.fill(out);
+                if len == 0 {
+                    self.output_buffer.replace(out);
+                } else {
+                    self.transmitting.set(true);
+                    uart.transmit(out, len);
+                }
+            });
+        });
+    }
+
+    /// Moves as much buffered output into `out` as fits, framing it in
+    /// framed mode. Returns the number of bytes to transmit.
+    fn fill(&self, out: &mut [u8]) -> usize {
+        let (offset, capacity) = if self.framed.get() {
+            (frame::payload_offset(&[]), frame::payload_capacity(out.len(), &[]))
+        } else {
+            (0, out.len())
+        };
+        let mut len = 0;
+        self.internal_buffer.map(|ring| while len < capacity {
+            match ring.dequeue() {
+                Some(b) => {
+                    out[offset + len] = b;
+                    len += 1;
+                }
+                None => break,
+            }
+        });
+        if len == 0 || !self.framed.get() {
+            return len;
+        }
+        let seq = self.seq.get();
+        self.seq.set(seq.wrapping_add(1));
+        frame::encode(out, frame::KERNEL_CHANNEL, None, seq, &[], len)
+    }
+
+    /// Writes all buffered output using blocking transmissions.
+    fn flush_sync(&self) {
+        self.uart.get().map(|uart| {
+            let mut chunk = [0u8; 48];
+            loop {
+                let len = self.fill(&mut chunk);
+                if len == 0 {
+                    break;
+                }
+                uart.transmit_sync(&chunk[..len]);
+            }
+        });
+    }
+}
+
+impl Write for DebugWriter {
+    fn write_str(&mut self, s: &str) -> Result {
+        self.buffer_bytes(s.as_bytes());
+        if self.synchronous.get() {
+            self.flush_sync();
+        } else {
+            self.publish();
+        }
+        Ok(())
+    }
+}
+
+impl uart::Client for DebugWriter {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.output_buffer.replace(buffer);
+        self.transmitting.set(false);
+        self.publish();
+    }
+
+    fn receive_complete(&self, _rx_buffer: &'static mut [u8], _rx_len: usize, _error: uart::Error) {
+    }
+}
+
+/// Formats `args` into the debug buffer, followed by a newline. Used by the
+/// `debug!` macro.
+pub fn begin_debug(args: Arguments) {
+    let writer = unsafe { &mut DEBUG_WRITER };
+    let _ = write(writer, args);
+    let _ = writer.write_str("\r\n");
+}
+
+/// Flushes buffered debug output and prints the panic message, blocking
+/// until it has been transmitted. Meant to be called from a board's
+/// `panic_fmt` handler.
+pub unsafe fn panic(args: Arguments, file: &'static str, line: u32) {
+    let writer = &mut DEBUG_WRITER;
+    writer.synchronous.set(true);
+    // Let a chunk already handed to the UART go out first, as it may still
+    // be reading from `OUTPUT_BUF`
+    writer.uart.get().map(|uart| uart.finish_transmit());
+    writer.flush_sync();
+    let _ = writer.write_fmt(format_args!("Kernel panic at {}:{}:\r\n\t\"", file, line));
+    let _ = write(writer, args);
+    let _ = writer.write_str("\"\r\n");
+}
+
+#[macro_export]
+macro_rules! debug {
+    () => ({
+        debug!("")
+    });
+    ($($arg:tt)*) => ({
+        $crate::debug::begin_debug(format_args!($($arg)*));
+    });
+}
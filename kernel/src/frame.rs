@@ -0,0 +1,90 @@
+//! Framing of console output, so that a host can tell apart the output of
+//! the kernel and of each app sharing one UART.
+//!
+//! A frame is laid out as follows, with multi-byte fields little-endian:
+//!
+//! | Bytes | Field                                                      |
+//! |-------|------------------------------------------------------------|
+//! | 2     | `SYNC`                                                     |
+//! | 1     | channel, `KERNEL_CHANNEL` or `APP_CHANNEL`                 |
+//! | 1     | process slot of the app, 0 on the kernel channel           |
+//! | 4     | process identifier of the app, 0 on the kernel channel     |
+//! | 2     | sequence number, counted per app and for the kernel        |
+//! | 1     | name length, at most `MAX_NAME_LEN`                        |
+//! | 1     | payload length                                             |
+//! | n     | name, the app's package name, empty on the kernel channel  |
+//! | n     | payload                                                    |
+//! | 1     | checksum, the wrapping sum of all bytes after `SYNC`       |
+//!
+//! Frames are built in place: the payload is copied to `payload_offset` of
+//! the buffer before `encode` adds the header, name and checksum around it.
+
+use callback::AppId;
+
+pub const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+pub const KERNEL_CHANNEL: u8 = 0;
+pub const APP_CHANNEL: u8 = 1;
+
+/// Longer package names are truncated.
+pub const MAX_NAME_LEN: usize = 16;
+
+const HEADER_LEN: usize = 12;
+const MAX_PAYLOAD_LEN: usize = 255;
+
+/// Where the payload of a frame with the given name starts.
+pub fn payload_offset(name: &[u8]) -> usize {
+    HEADER_LEN + name_len(name)
+}
+
+/// How many payload bytes fit in a frame with the given name built in a
+/// buffer of `buffer_len` bytes.
+pub fn payload_capacity(buffer_len: usize, name: &[u8]) -> usize {
+    let overhead = payload_offset(name) + 1;
+    if buffer_len <= overhead {
+        0
+    } else if buffer_len - overhead > MAX_PAYLOAD_LEN {
+        MAX_PAYLOAD_LEN
+    } else {
+        buffer_len - overhead
+    }
+}
+
+/// Completes a frame around the `payload_len` bytes at `payload_offset(name)`
+/// of `buffer` and returns the length of the frame. The payload must fit, see
+/// `payload_capacity`.
+pub fn encode(buffer: &mut [u8],
+              channel: u8,
+              appid: Option<AppId>,
+              seq: u16,
+              name: &[u8],
+              payload_len: usize)
+              -> usize {
+    let name_len = name_len(name);
+    let (slot, identifier) = appid.map_or((0, 0), |appid| (appid.idx(), appid.identifier()));
+
+    buffer[0] = SYNC[0];
+    buffer[1] = SYNC[1];
+    buffer[2] = channel;
+    buffer[3] = slot as u8;
+    for i in 0..4 {
+        buffer[4 + i] = (identifier >> (8 * i)) as u8;
+    }
+    buffer[8] = seq as u8;
+    buffer[9] = (seq >> 8) as u8;
+    buffer[10] = name_len as u8;
+    buffer[11] = payload_len as u8;
+    buffer[HEADER_LEN..HEADER_LEN + name_len].copy_from_slice(&name[..name_len]);
+
+    let end = HEADER_LEN + name_len + payload_len;
+    buffer[end] = buffer[SYNC.len()..end].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    end + 1
+}
+
+fn name_len(name: &[u8]) -> usize {
+    if name.len() < MAX_NAME_LEN {
+        name.len()
+    } else {
+        MAX_NAME_LEN
+    }
+}
@@ -2,6 +2,10 @@
 pub trait Client {
     /// Called when a sample is ready.
     fn sample_done(&self, sample: u16);
+
+    /// Called instead of `sample_done` when a sample that `sample` accepted
+    /// could not be taken after all.
+    fn sample_failed(&self);
 }
 
 /// Simple interface for reading a single ADC sample on any channel.
@@ -16,13 +20,42 @@ pub trait AdcSingle {
     // fn cancel_sample(&self) -> bool;
 }
 
-// pub trait Frequency {
-//    fn frequency() -> u32;
-// }
-//
-// pub trait AdcContinuous {
-//    type Frequency: Frequency;
-//    fn compute_interval(&self, interval:u32) -> u32;
-//    fn sample_continuous(&self, channel: u8, interval:u32);
-//    fn cancel_sampling(&self);
-// }
+/// Trait for handling callbacks from continuous ADC sampling.
+pub trait ContinuousClient {
+    /// Called when a buffer passed to `sample_continuous` or
+    /// `provide_buffer` has been filled with `length` samples. Ownership of
+    /// the buffer passes back to the client, which can provide it again to
+    /// keep sampling.
+    fn buffer_ready(&self, buffer: &'static mut [u16], length: usize);
+}
+
+/// Interface for sampling an ADC channel at a fixed frequency into buffers.
+///
+/// Sampling is double-buffered: while the ADC fills one buffer the client
+/// queues the next one with `provide_buffer`, and the ADC switches to it
+/// without missing a sample. If no buffer is queued when the current one
+/// fills, sampling stops after that buffer is returned.
+pub trait AdcContinuous {
+    /// Start sampling `channel` at `frequency` Hz, storing the first `length`
+    /// samples in `buffer`. Returns the buffer if sampling could not start,
+    /// for example because the frequency is out of range or the ADC is
+    /// already sampling.
+    fn sample_continuous(&self,
+                         channel: u8,
+                         frequency: u32,
+                         buffer: &'static mut [u16],
+                         length: usize)
+                         -> Result<(), &'static mut [u16]>;
+
+    /// Queue the buffer to fill once the current one is full. Only one
+    /// buffer can be queued at a time. Returns the buffer if the ADC is not
+    /// sampling or a buffer is already queued.
+    fn provide_buffer(&self,
+                      buffer: &'static mut [u16],
+                      length: usize)
+                      -> Result<(), &'static mut [u16]>;
+
+    /// Stop sampling and hand back the buffer being filled and the queued
+    /// buffer, if any. Samples already in the current buffer are discarded.
+    fn stop_sampling(&self) -> (Option<&'static mut [u16]>, Option<&'static mut [u16]>);
+}
@@ -0,0 +1,70 @@
+//! Interface for internal and external flash storage.
+//!
+//! Flash is accessed a page at a time. A page is represented by the
+//! implementation's `Page` type, which fixes the page size for capsules built
+//! on top of the interface. All operations are asynchronous: if an operation
+//! is accepted, its completion is signaled through the `Client` trait,
+//! otherwise the buffer (if any) is handed back immediately together with the
+//! reason.
+//!
+//! # Example
+//!
+//! ```
+//! let page = static_init!(sam4l::flashcalw::Sam4lPage, Default::default(), 512);
+//! sam4l::flashcalw::flash_controller.set_client(storage);
+//! sam4l::flashcalw::flash_controller.read_page(64, page);
+//! ```
+
+/// The type of error encountered during a flash operation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The page number is out of range.
+    InvalidPage,
+
+    /// Another operation is in progress.
+    Busy,
+
+    /// The page is locked against modification.
+    LockError,
+
+    /// The flash controller rejected the command sequence.
+    ProgramError,
+
+    /// No error occurred and the command completed successfully.
+    CommandComplete,
+}
+
+pub trait Flash {
+    /// Buffer holding exactly one page of flash.
+    type Page: AsMut<[u8]> + AsRef<[u8]>;
+
+    /// Number of pages in the flash.
+    fn num_pages(&self) -> usize;
+
+    /// Reads page `page_number` into `buf`.
+    fn read_page(&self,
+                 page_number: usize,
+                 buf: &'static mut Self::Page)
+                 -> Result<(), (Error, &'static mut Self::Page)>;
+
+    /// Erases page `page_number` and programs it with the contents of `buf`.
+    fn write_page(&self,
+                  page_number: usize,
+                  buf: &'static mut Self::Page)
+                  -> Result<(), (Error, &'static mut Self::Page)>;
+
+    /// Erases page `page_number`.
+    fn erase_page(&self, page_number: usize) -> Result<(), Error>;
+}
+
+/// Client interface for flash operations.
+pub trait Client<F: Flash> {
+    /// Called when a `read_page` completed.
+    fn read_complete(&self, read_buffer: &'static mut F::Page, error: Error);
+
+    /// Called when a `write_page` completed.
+    fn write_complete(&self, write_buffer: &'static mut F::Page, error: Error);
+
+    /// Called when an `erase_page` completed.
+    fn erase_complete(&self, error: Error);
+}
@@ -15,6 +15,11 @@ pub enum Error {
     /// higher-priority transmission is in progress by a different master.
     ArbitrationLost,
 
+    /// The command did not finish in time, most likely because a slave is
+    /// holding the bus. The bus has been freed, if the hardware driver knows
+    /// how to.
+    Timeout,
+
     /// No error occured and the command completed successfully.
     CommandComplete,
 }
@@ -25,6 +30,7 @@ impl Display for Error {
             Error::AddressNak => "I2C Address Not Acknowledged",
             Error::DataNak => "I2C Data Not Acknowledged",
             Error::ArbitrationLost => "I2C Bus Arbitration Lost",
+            Error::Timeout => "I2C Command Timed Out",
             Error::CommandComplete => "I2C Command Completed",
         };
         write!(fmt, "{}", display_str)
@@ -38,13 +44,41 @@ pub enum SlaveTransmissionType {
     Read,
 }
 
+/// Whether a segment of a transaction writes to or reads from the slave.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Direction {
+    Write,
+    Read,
+}
+
+/// The most segments a transaction can have.
+pub const MAX_SEGMENTS: usize = 4;
+
+/// A part of a transaction that moves bytes in one direction, from or into
+/// `start..start + len` of the transaction's buffer.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Segment {
+    pub direction: Direction,
+    /// Whether a STOP follows the segment. Otherwise the next segment begins
+    /// with a repeated START. The last segment always ends with a STOP.
+    pub stop: bool,
+    pub start: usize,
+    pub len: usize,
+}
+
 /// Interface for an I2C Master hardware driver.
 pub trait I2CMaster {
     fn enable(&self);
     fn disable(&self);
-    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8);
-    fn write(&self, addr: u8, data: &'static mut [u8], len: u8);
-    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8);
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: usize, read_len: usize);
+    fn write(&self, addr: u8, data: &'static mut [u8], len: usize);
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: usize);
+
+    /// Performs `segments` in order as one transaction with the slave at
+    /// `addr`. Only the first `MAX_SEGMENTS` segments are performed, and
+    /// segments are cut to fit in `data`. The client is called once, when
+    /// the transaction is over or has failed.
+    fn transaction(&self, addr: u8, data: &'static mut [u8], segments: &[Segment]);
 }
 
 /// Interface for an I2C Slave hardware driver.
@@ -98,9 +132,13 @@ pub trait I2CHwSlaveClient {
 pub trait I2CDevice {
     fn enable(&self);
     fn disable(&self);
-    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8);
-    fn write(&self, data: &'static mut [u8], len: u8);
-    fn read(&self, buffer: &'static mut [u8], len: u8);
+    fn write_read(&self, data: &'static mut [u8], write_len: usize, read_len: usize);
+    fn write(&self, data: &'static mut [u8], len: usize);
+    fn read(&self, buffer: &'static mut [u8], len: usize);
+
+    /// Performs `segments` in order as one transaction, as
+    /// `I2CMaster::transaction` does.
+    fn transaction(&self, data: &'static mut [u8], segments: &[Segment]);
 }
 
 /// Client interface for I2CDevice implementations.
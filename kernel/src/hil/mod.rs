@@ -6,6 +6,10 @@ pub mod spi;
 pub mod uart;
 pub mod adc;
 pub mod watchdog;
+pub mod flash;
+pub mod rng;
+pub mod pwm;
+pub mod sensors;
 
 pub trait Controller {
     type Config;
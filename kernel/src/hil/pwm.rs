@@ -0,0 +1,24 @@
+//! Interface for pulse-width modulated outputs.
+
+/// Duty cycle of an output that is always high, in hundredths of a percent.
+pub const MAX_DUTY_CYCLE: usize = 10000;
+
+pub trait PwmPin {
+    /// Sets the frequency of the signal. Returns false if the frequency
+    /// cannot be generated, in which case the previous one is kept. Pins
+    /// sharing a hardware timer may also share its frequency.
+    fn set_frequency(&self, frequency_hz: usize) -> bool;
+
+    /// Sets the fraction of each period the output is high, from 0 (always
+    /// low) to `MAX_DUTY_CYCLE` (always high).
+    fn set_duty_cycle(&self, duty_cycle: usize);
+
+    /// Starts generating the signal on the pin.
+    fn enable(&self);
+
+    /// Stops the signal, leaving the pin low.
+    fn disable(&self);
+
+    /// The highest frequency the pin can generate, in Hz.
+    fn max_frequency(&self) -> usize;
+}
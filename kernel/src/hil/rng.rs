@@ -0,0 +1,16 @@
+//! Interface for random number generators.
+
+/// Trait for handling callbacks from a random number generator.
+pub trait Client {
+    /// Called when the first `len` bytes of `buf` have been filled with
+    /// random bytes.
+    fn fill_done(&self, buf: &'static mut [u8], len: usize);
+}
+
+pub trait RNG {
+    /// Starts filling the first `len` bytes of `buf` (or all of it, if it is
+    /// shorter) with random bytes. The buffer is handed back through
+    /// `Client::fill_done`, or returned right away if the generator is busy
+    /// or there is nothing to fill.
+    fn fill(&self, buf: &'static mut [u8], len: usize) -> Result<(), &'static mut [u8]>;
+}
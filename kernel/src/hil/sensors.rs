@@ -0,0 +1,77 @@
+//! Interfaces for environmental and motion sensors.
+//!
+//! Each kind of reading has its own trait and fixed units, so a capsule can
+//! use whichever chip a board has. A sensor takes one reading per request
+//! and hands it to its client. Requests made while a reading is underway are
+//! served by that reading.
+
+/// Trait for handling temperature readings.
+pub trait TemperatureClient {
+    /// Called with a reading in hundredths of degrees Celsius.
+    fn temperature_ready(&self, temperature: i32);
+}
+
+pub trait TemperatureSensor {
+    fn set_temperature_client(&self, client: &'static TemperatureClient);
+
+    /// Starts a temperature reading. Returns false if the sensor cannot take
+    /// one.
+    fn read_temperature(&self) -> bool;
+}
+
+/// Trait for handling relative humidity readings.
+pub trait HumidityClient {
+    /// Called with a reading in hundredths of a percent.
+    fn humidity_ready(&self, humidity: u32);
+}
+
+pub trait HumiditySensor {
+    fn set_humidity_client(&self, client: &'static HumidityClient);
+
+    /// Starts a humidity reading. Returns false if the sensor cannot take
+    /// one.
+    fn read_humidity(&self) -> bool;
+}
+
+/// Trait for handling ambient light readings.
+pub trait LightClient {
+    /// Called with the illuminance in lux.
+    fn light_ready(&self, lux: u32);
+}
+
+pub trait LightSensor {
+    fn set_light_client(&self, client: &'static LightClient);
+
+    /// Starts an ambient light reading. Returns false if the sensor cannot
+    /// take one.
+    fn read_light(&self) -> bool;
+}
+
+/// Trait for handling barometric pressure readings.
+pub trait PressureClient {
+    /// Called with a reading in microbars, which are tenths of a pascal.
+    fn pressure_ready(&self, pressure: u32);
+}
+
+pub trait PressureSensor {
+    fn set_pressure_client(&self, client: &'static PressureClient);
+
+    /// Starts a pressure reading. Returns false if the sensor cannot take
+    /// one.
+    fn read_pressure(&self) -> bool;
+}
+
+/// Trait for handling acceleration readings.
+pub trait AccelerationClient {
+    /// Called with the acceleration along each axis in thousandths of the
+    /// standard gravity (mg).
+    fn acceleration_ready(&self, x: i32, y: i32, z: i32);
+}
+
+pub trait AccelerationSensor {
+    fn set_acceleration_client(&self, client: &'static AccelerationClient);
+
+    /// Starts reading the acceleration along all three axes. Returns false
+    /// if the sensor cannot take a reading.
+    fn read_acceleration(&self) -> bool;
+}
@@ -1,4 +1,4 @@
-//! Traits and parameters for SPI master communication.
+//! Traits and parameters for SPI master and slave communication.
 
 use core::option::Option;
 
@@ -136,4 +136,58 @@ pub trait SPIMasterDevice {
                         mut read_buffer: Option<&'static mut [u8]>,
                         len: usize)
                         -> bool;
+
+    /// Keep the chip select line low after transfers of this device
+    /// complete, or bring it high again, as `SpiMaster::hold_low` and
+    /// `SpiMaster::release_low` do.
+    fn hold_low(&self);
+    fn release_low(&self);
+}
+
+pub trait SpiSlaveClient {
+    /// Called when the master selects this device while no transfer is set
+    /// up. Clients typically respond by setting one up with
+    /// `read_write_bytes`.
+    fn chip_selected(&self);
+
+    /// Called when a transfer finishes, either because `len` bytes were
+    /// exchanged or because the master deselected this device early. `len`
+    /// is the number of bytes actually exchanged.
+    fn read_write_done(&self,
+                       mut write_buffer: Option<&'static mut [u8]>,
+                       mut read_buffer: Option<&'static mut [u8]>,
+                       len: usize);
+}
+
+/// The `SpiSlave` trait for acting as a slave device on an SPI bus driven
+/// by another master.
+///
+/// The master decides when transfers happen and how long they are, so a
+/// slave can only prepare buffers in advance. While no transfer is set up,
+/// the byte given to `set_write_byte` is sent to the master and received
+/// bytes are discarded.
+pub trait SpiSlave {
+    fn set_client(&self, client: &'static SpiSlaveClient);
+
+    fn init(&self);
+    fn is_busy(&self) -> bool;
+
+    /// Sets the byte sent to the master while no write buffer is set up.
+    fn set_write_byte(&self, write_byte: u8);
+
+    /// Set up buffers for the next transfer, whose completion is signaled
+    /// by invoking SpiSlaveClient on the initialized client. Either buffer
+    /// may be None, but not both. The length of the operation is the
+    /// minimum of `len` and the sizes of the given buffers. Returns false
+    /// if a transfer is already set up.
+    fn read_write_bytes(&self,
+                        mut write_buffer: Option<&'static mut [u8]>,
+                        mut read_buffer: Option<&'static mut [u8]>,
+                        len: usize)
+                        -> bool;
+
+    fn set_clock(&self, polarity: ClockPolarity);
+    fn get_clock(&self) -> ClockPolarity;
+    fn set_phase(&self, phase: ClockPhase);
+    fn get_phase(&self) -> ClockPhase;
 }
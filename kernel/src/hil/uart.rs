@@ -74,6 +74,19 @@ pub trait UARTAdvanced: UART {
     fn receive_until_terminator(&self, rx_buffer: &'static mut [u8], terminator: u8);
 }
 
+/// Blocking transmission for contexts where interrupts cannot be serviced,
+/// such as flushing debug output on a kernel panic.
+pub trait UARTSync: UART {
+    /// Transmit data, returning once every byte has been handed to the
+    /// hardware
+    fn transmit_sync(&self, tx_data: &[u8]);
+
+    /// Block until a transmission started with `transmit` has left the
+    /// hardware, so blocking writes do not interleave with it. The
+    /// transmission's buffer is not returned.
+    fn finish_transmit(&self);
+}
+
 /// Implement Client to receive callbacks from UART
 pub trait Client {
     /// UART transmit complete
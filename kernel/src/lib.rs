@@ -5,11 +5,14 @@ pub mod common;
 
 pub mod callback;
 pub mod container;
+pub mod debug;
+pub mod frame;
 pub mod driver;
 pub mod ipc;
 pub mod mem;
 pub mod process;
 pub mod hil;
+pub mod watchdog;
 
 pub mod support;
 
@@ -36,11 +39,13 @@ pub fn main<P: Platform, C: Chip>(platform: &P,
 
     loop {
         unsafe {
+            watchdog::tickle();
             chip.service_pending_interrupts();
 
             for (i, p) in processes.iter_mut().enumerate() {
                 p.as_mut().map(|process| {
-                    sched::do_process(platform, chip, process, AppId::new(i), ipc);
+                    let appid = AppId::new(i, process.identifier());
+                    sched::do_process(platform, chip, process, appid, ipc);
                 });
                 if chip.has_pending_interrupts() {
                     break;
@@ -49,7 +54,8 @@ pub fn main<P: Platform, C: Chip>(platform: &P,
 
             support::atomic(|| {
                 if !chip.has_pending_interrupts() && process::processes_blocked() {
-                    support::wfi();
+                    watchdog::tickle();
+                    chip.sleep();
                 }
             })
         };
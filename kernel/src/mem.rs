@@ -41,10 +41,7 @@ impl<L, T> DerefMut for AppPtr<L, T> {
 impl<L, T> Drop for AppPtr<L, T> {
     fn drop(&mut self) {
         unsafe {
-            let ps = &mut process::PROCS;
-            if ps.len() > self.process.idx() {
-                ps[self.process.idx()].as_mut().map(|process| process.free(self.ptr.get_mut()));
-            }
+            process::process_for(self.process).map(|process| process.free(self.ptr.get_mut()));
         }
     }
 }
@@ -62,8 +59,14 @@ impl<L, T> AppSlice<L, T> {
         }
     }
 
+    /// Length of the slice, or 0 once the process that shared it has died
+    /// or been restarted.
     pub fn len(&self) -> usize {
-        self.len
+        if self.ptr.process.is_live() {
+            self.len
+        } else {
+            0
+        }
     }
 
     pub unsafe fn ptr(&self) -> *const T {
@@ -71,10 +74,8 @@ impl<L, T> AppSlice<L, T> {
     }
 
     pub unsafe fn expose_to(&self, appid: AppId) -> bool {
-        let ps = &mut process::PROCS;
-        if appid.idx() != self.ptr.process.idx() && ps.len() > appid.idx() {
-            ps[appid.idx()]
-                .as_ref()
+        if appid.idx() != self.ptr.process.idx() && self.ptr.process.is_live() {
+            process::process_for(appid)
                 .map(|process| process.add_mpu_region(self.ptr() as *const u8, self.len()))
                 .unwrap_or(false)
         } else {
@@ -87,14 +88,16 @@ impl<L, T> AppSlice<L, T> {
     }
 }
 
+// A slice whose process is gone is empty, since its memory may have been
+// reused by a restarted instance of the process.
 impl<L, T> AsRef<[T]> for AppSlice<L, T> {
     fn as_ref(&self) -> &[T] {
-        unsafe { slice::from_raw_parts(self.ptr.ptr.get(), self.len) }
+        unsafe { slice::from_raw_parts(self.ptr.ptr.get(), self.len()) }
     }
 }
 
 impl<L, T> AsMut<[T]> for AppSlice<L, T> {
     fn as_mut(&mut self) -> &mut [T] {
-        unsafe { slice::from_raw_parts_mut(self.ptr.ptr.get_mut(), self.len) }
+        unsafe { slice::from_raw_parts_mut(self.ptr.ptr.get_mut(), self.len()) }
     }
 }
@@ -12,6 +12,13 @@ pub trait Chip {
     fn has_pending_interrupts(&self) -> bool;
     fn mpu(&self) -> &Self::MPU;
     fn systick(&self) -> &Self::SysTick;
+
+    /// Put the chip to sleep until the next interrupt.
+    ///
+    /// Called by the scheduler with interrupts disabled when no process has
+    /// work to do. Implementations should pick the deepest sleep state that
+    /// is safe given which peripherals are currently active.
+    fn sleep(&self);
 }
 
 pub trait MPU {
@@ -20,17 +20,28 @@ extern "C" {
 
 pub static mut PROCS: &'static mut [Option<Process<'static>>] = &mut [];
 
-pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
-    let procs = unsafe { &mut PROCS };
+/// Source of unique process identifiers, see `AppId`.
+static mut NEXT_IDENTIFIER: usize = 0;
+
+/// Returns the process `appid` refers to, or `None` if that process is no
+/// longer alive (even if its slot is now occupied by another process).
+pub unsafe fn process_for(appid: AppId) -> Option<&'static mut Process<'static>> {
+    let procs = &mut PROCS;
     let idx = appid.idx();
     if idx >= procs.len() {
-        return false;
+        return None;
     }
 
     match procs[idx] {
+        Some(ref mut p) if p.identifier == appid.identifier() => Some(p),
+        _ => None,
+    }
+}
+
+pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
+    match unsafe { process_for(appid) } {
         None => false,
-        Some(ref mut p) => {
-            // TODO(alevy): validate appid liveness
+        Some(p) => {
             unsafe {
                 HAVE_WORK.set(HAVE_WORK.get() + 1);
             }
@@ -40,6 +51,62 @@ pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
     }
 }
 
+/// Restarts the process `appid` refers to from its entry point, discarding
+/// its memory, pending callbacks and container state. The restarted process
+/// gets a new identifier, so `appid` and any other `AppId` referring to the
+/// old instance become stale. This revokes the old instance's container
+/// state, callbacks and `AppSlice`s (which read as empty from then on)
+/// before its memory is reused. Capsules only give kernel buffers to DMA, so
+/// no transfer into the old memory can be in flight.
+///
+/// Must not be called while the process is executing a system call, i.e.
+/// only from interrupt bottom halves (e.g. alarm callbacks).
+pub unsafe fn restart(appid: AppId) -> bool {
+    match process_for(appid) {
+        None => false,
+        Some(p) => {
+            // Drop any work the old instance still accounts for
+            while p.dequeue_task().is_some() {}
+            p.yield_state();
+
+            let text = p.text;
+            let memory = slice::from_raw_parts_mut(p.memory.as_mut_ptr(), p.memory.len());
+            let restarted = Process::create(text.as_ptr(), text.len(), memory);
+            *p = restarted;
+            true
+        }
+    }
+}
+
+/// Returns the accounting counters of the process in slot `idx`, if any.
+pub fn statistics(idx: usize) -> Option<Statistics> {
+    let procs = unsafe { &PROCS };
+    procs.get(idx).and_then(|p| p.as_ref().map(|p| p.statistics()))
+}
+
+/// Returns the size of the nonvolatile storage region requested in the
+/// header of the process in slot `idx`, if any.
+pub fn storage_size(idx: usize) -> Option<usize> {
+    let procs = unsafe { &PROCS };
+    procs.get(idx).and_then(|p| p.as_ref().map(|p| p.storage_size()))
+}
+
+/// Returns the package name of the process `appid` refers to.
+pub fn package_name(appid: AppId) -> Option<&'static [u8]> {
+    unsafe { process_for(appid).map(|p| p.pkg_name) }
+}
+
+/// Returns the package name of the process in slot `idx`, if any.
+pub fn slot_package_name(idx: usize) -> Option<&'static [u8]> {
+    let procs = unsafe { &PROCS };
+    procs.get(idx).and_then(|p| p.as_ref().map(|p| p.pkg_name))
+}
+
+/// Number of process slots on the platform.
+pub fn num_slots() -> usize {
+    unsafe { PROCS.len() }
+}
+
 #[derive(Copy,Clone,PartialEq,Eq)]
 pub enum Error {
     NoSuchApp,
@@ -74,6 +141,24 @@ pub struct FunctionCall {
     pub pc: usize,
 }
 
+/// Per-process accounting maintained by the scheduler.
+#[derive(Copy, Clone, Default)]
+pub struct Statistics {
+    /// Approximate time spent running, in microseconds. Wraps around.
+    pub cpu_time_us: usize,
+
+    /// Number of times the process was switched to.
+    pub context_switches: usize,
+
+    /// Number of times the process was preempted because its timeslice
+    /// expired.
+    pub timeslice_expirations: usize,
+
+    /// Number of system calls made, indexed by system call class (`yield`,
+    /// `subscribe`, `command`, `allow` and `memop`).
+    pub syscalls: [usize; 5],
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct LoadInfo {
@@ -91,6 +176,7 @@ struct LoadInfo {
     bss_size: u32,
     pkg_name_offset: u32,
     pkg_name_size: u32,
+    storage_size: u32,
 }
 
 pub struct Process<'a> {
@@ -129,6 +215,16 @@ pub struct Process<'a> {
 
     tasks: RingBuffer<'a, Task>,
 
+    /// Unique identifier of this process, distinguishing it from other
+    /// processes that occupy (or occupied) the same slot.
+    identifier: usize,
+
+    statistics: Statistics,
+
+    /// Size in bytes of the nonvolatile storage region the process requested
+    /// in its header.
+    storage_size: usize,
+
     pub pkg_name: &'static [u8],
 }
 
@@ -162,6 +258,36 @@ impl<'a> Process<'a> {
         self.state
     }
 
+    pub fn identifier(&self) -> usize {
+        self.identifier
+    }
+
+    pub fn storage_size(&self) -> usize {
+        self.storage_size
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        self.statistics
+    }
+
+    /// Accounts for a single run of the process, as measured by the
+    /// scheduler around `switch_to`.
+    pub fn record_run(&mut self, run_time_us: u32, timeslice_expired: bool) {
+        let stats = &mut self.statistics;
+        stats.cpu_time_us = stats.cpu_time_us.wrapping_add(run_time_us as usize);
+        stats.context_switches += 1;
+        if timeslice_expired {
+            stats.timeslice_expirations += 1;
+        }
+    }
+
+    pub fn record_syscall(&mut self, svc_number: u8) {
+        self.statistics
+            .syscalls
+            .get_mut(svc_number as usize)
+            .map(|count| *count += 1);
+    }
+
     pub fn yield_state(&mut self) {
         if self.state == State::Running {
             self.state = State::Yielded;
@@ -294,7 +420,11 @@ impl<'a> Process<'a> {
             pkg_name: load_result.pkg_name,
             state: State::Yielded,
             tasks: tasks,
+            identifier: NEXT_IDENTIFIER,
+            statistics: Default::default(),
+            storage_size: load_result.storage_size,
         };
+        NEXT_IDENTIFIER += 1;
 
         if (load_result.init_fn - 1) % 8 != 0 {
             panic!("{}", (load_result.init_fn - 1) % 8);
@@ -466,6 +596,9 @@ struct LoadResult {
 
     /// The process's package name (used for IPC)
     pkg_name: &'static [u8],
+
+    /// Requested size of the process's nonvolatile storage region
+    storage_size: usize,
 }
 
 /// Loads the process into memory
@@ -487,8 +620,15 @@ unsafe fn load(start_addr: *const u8, mem_base: *mut u8) -> LoadResult {
                                         load_info.pkg_name_size as usize),
         init_fn: 0,
         app_mem_start: ptr::null(),
+        storage_size: 0,
     };
 
+    // Images built before `storage_size` was added to the header have their
+    // relocation data right after `pkg_name_size`.
+    if load_info.rel_data_offset as usize >= mem::size_of::<LoadInfo>() {
+        result.storage_size = load_info.storage_size as usize;
+    }
+
     let text_start = start_addr.offset(load_info.text_offset as isize);
 
     let rel_data: &[u32] =
@@ -13,8 +13,10 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
     systick.set_timer(10000);
     systick.enable(true);
 
+    let mut expired = false;
     loop {
-        if chip.has_pending_interrupts() || systick.overflowed() || systick.value() <= 500 {
+        if chip.has_pending_interrupts() || expired || systick.overflowed() ||
+           systick.value() <= 500 {
             break;
         }
 
@@ -22,8 +24,14 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
             process::State::Running => {
                 process.setup_mpu(chip.mpu());
                 systick.enable(true);
+                let start = systick.value();
                 process.switch_to();
                 systick.enable(false);
+                // Reading the control register clears its overflow flag, so
+                // keep the result for the check at the top of the loop
+                expired = systick.overflowed() || C::SysTick::overflow_fired();
+                let end = if expired { 0 } else { systick.value() };
+                process.record_run(start.saturating_sub(end), expired);
             }
             process::State::Yielded => {
                 match process.dequeue_task() {
@@ -47,7 +55,10 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
             break;
         }
 
-        match process.svc_number() {
+        let svc_number = process.svc_number();
+        svc_number.map(|svc| process.record_syscall(svc));
+
+        match svc_number {
             Some(syscall::MEMOP) => {
                 let brk_type = process.r0();
                 let r1 = process.r1();
@@ -0,0 +1,48 @@
+//! Kernel management of the hardware watchdog.
+//!
+//! Boards opt in by passing the chip's watchdog to `enable`. The kernel main
+//! loop then services the watchdog on every iteration of the scheduler loop
+//! and right before putting the chip to sleep. Since the watchdog keeps
+//! running while the chip sleeps, the board must make sure the kernel wakes
+//! up at least once per period, e.g. by using the `app_watchdog` capsule,
+//! which keeps an alarm armed for that purpose.
+//!
+//! `reset` stops servicing the watchdog, so the hardware resets the board
+//! once the current period expires.
+
+use core::cell::Cell;
+use hil::watchdog::Watchdog;
+
+struct KernelWatchdog {
+    watchdog: Cell<Option<&'static Watchdog>>,
+    serviced: Cell<bool>,
+}
+
+static mut KERNEL_WATCHDOG: KernelWatchdog = KernelWatchdog {
+    watchdog: Cell::new(None),
+    serviced: Cell::new(true),
+};
+
+/// Starts `watchdog` with a timeout of `period` milliseconds and hands it to
+/// the kernel to service.
+pub unsafe fn enable(watchdog: &'static Watchdog, period: usize) {
+    watchdog.start(period);
+    KERNEL_WATCHDOG.watchdog.set(Some(watchdog));
+}
+
+/// Services the watchdog, if enabled and no reset has been requested. Called
+/// by the kernel main loop.
+pub fn tickle() {
+    let kwdt = unsafe { &KERNEL_WATCHDOG };
+    if kwdt.serviced.get() {
+        kwdt.watchdog.get().map(|watchdog| watchdog.tickle());
+    }
+}
+
+/// Stops servicing the watchdog so it resets the board. Returns `false` if
+/// the board has not enabled a watchdog, in which case nothing happens.
+pub fn reset() -> bool {
+    let kwdt = unsafe { &KERNEL_WATCHDOG };
+    kwdt.serviced.set(false);
+    kwdt.watchdog.get().is_some()
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Keep in sync with kernel/src/frame.rs
+const SYNC: [u8; 2] = [0xa5, 0x5a];
+const KERNEL_CHANNEL: u8 = 0;
+const APP_CHANNEL: u8 = 1;
+const MAX_NAME_LEN: usize = 16;
+const HEADER_LEN: usize = 12;
+
+/// A decoded frame.
+struct Frame {
+    channel: u8,
+    slot: u8,
+    identifier: u32,
+    seq: u16,
+    name: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+/// Result of looking for a frame at the start of the input.
+enum Parse {
+    Frame(Frame, usize),
+    /// The input is too short to tell.
+    Incomplete,
+    /// There is no valid frame at the start of the input.
+    Invalid,
+}
+
+fn parse(input: &[u8]) -> Parse {
+    if input.len() < HEADER_LEN {
+        return Parse::Incomplete;
+    }
+    if input[..2] != SYNC || input[2] > APP_CHANNEL {
+        return Parse::Invalid;
+    }
+    let name_len = input[10] as usize;
+    let payload_len = input[11] as usize;
+    if name_len > MAX_NAME_LEN {
+        return Parse::Invalid;
+    }
+    let end = HEADER_LEN + name_len + payload_len;
+    if input.len() < end + 1 {
+        return Parse::Incomplete;
+    }
+    let checksum = input[2..end].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if checksum != input[end] {
+        return Parse::Invalid;
+    }
+
+    let frame = Frame {
+        channel: input[2],
+        slot: input[3],
+        identifier: input[4..8].iter().rev().fold(0, |id, b| id << 8 | *b as u32),
+        seq: input[8] as u16 | (input[9] as u16) << 8,
+        name: input[HEADER_LEN..HEADER_LEN + name_len].to_vec(),
+        payload: input[HEADER_LEN + name_len..end].to_vec(),
+    };
+    Parse::Frame(frame, end + 1)
+}
+
+/// Output of the kernel or of one app instance.
+struct Stream {
+    label: String,
+    log: File,
+    next_seq: Option<u16>,
+    line: Vec<u8>,
+}
+
+impl Stream {
+    fn open(dir: &Path, file_name: &str, label: String) -> io::Result<Stream> {
+        let log = OpenOptions::new().create(true).append(true).open(dir.join(file_name))?;
+        Ok(Stream {
+            label,
+            log,
+            next_seq: None,
+            line: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, seq: Option<u16>, data: &[u8]) -> io::Result<()> {
+        if let (Some(seq), Some(expected)) = (seq, self.next_seq) {
+            if seq != expected {
+                eprintln!("console_demux: lost {} frame(s) of {}",
+                          seq.wrapping_sub(expected),
+                          self.label);
+            }
+        }
+        self.next_seq = seq.map(|seq| seq.wrapping_add(1));
+        self.log.write_all(data)?;
+
+        // Print complete lines to stdout
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for b in data {
+            match *b {
+                b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).into_owned();
+                    writeln!(stdout, "[{}] {}", self.label, line.trim_end_matches('\r'))?;
+                    self.line.clear();
+                }
+                b => self.line.push(b),
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Demux {
+    dir: PathBuf,
+    /// Streams by channel, process slot and process identifier.
+    streams: HashMap<(u8, u8, u32), Stream>,
+    unframed: Stream,
+    buffer: Vec<u8>,
+}
+
+impl Demux {
+    fn new(dir: PathBuf) -> io::Result<Demux> {
+        let unframed = Stream::open(&dir, "unframed.log", String::from("unframed"))?;
+        Ok(Demux {
+            dir,
+            streams: HashMap::new(),
+            unframed,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn feed(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        let mut start = 0;
+        let mut skipped = Vec::new();
+        while start < self.buffer.len() {
+            match parse(&self.buffer[start..]) {
+                Parse::Frame(frame, len) => {
+                    if !skipped.is_empty() {
+                        self.unframed.write(None, &skipped)?;
+                        skipped.clear();
+                    }
+                    self.dispatch(frame)?;
+                    start += len;
+                }
+                Parse::Incomplete => break,
+                Parse::Invalid => {
+                    skipped.push(self.buffer[start]);
+                    start += 1;
+                }
+            }
+        }
+        if !skipped.is_empty() {
+            self.unframed.write(None, &skipped)?;
+        }
+        self.buffer.drain(..start);
+        Ok(())
+    }
+
+    fn dispatch(&mut self, frame: Frame) -> io::Result<()> {
+        let key = (frame.channel, frame.slot, frame.identifier);
+        if !self.streams.contains_key(&key) {
+            let stream = if frame.channel == KERNEL_CHANNEL {
+                Stream::open(&self.dir, "kernel.log", String::from("kernel"))?
+            } else {
+                let name = sanitize(&frame.name, frame.slot);
+                let label = format!("{}#{}", name, frame.identifier);
+                Stream::open(&self.dir, &format!("{}.log", name), label)?
+            };
+            self.streams.insert(key, stream);
+        }
+        match self.streams.get_mut(&key) {
+            Some(stream) => stream.write(Some(frame.seq), &frame.payload),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Turns a package name into a file name, falling back to the process slot
+/// for apps without a usable name.
+fn sanitize(name: &[u8], slot: u8) -> String {
+    let name: String = String::from_utf8_lossy(name)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    if name.is_empty() || name == "kernel" || name == "unframed" {
+        format!("app{}", slot)
+    } else {
+        name
+    }
+}
+
+fn run<R: Read>(input: &mut R, dir: PathBuf) -> io::Result<()> {
+    let mut demux = Demux::new(dir)?;
+    let mut buffer = [0u8; 512];
+    loop {
+        let len = input.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(());
+        }
+        demux.feed(&buffer[..len])?;
+    }
+}
+
+fn print_usage(program: &str) {
+    println!("Usage: {} [-o OUTDIR] [INPUT]", program);
+    println!();
+    println!("Splits framed Tock console output read from INPUT, or stdin, into");
+    println!("per-app logs in OUTDIR, by default the current directory.");
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+    let mut dir = PathBuf::from(".");
+    let mut input = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" => {
+                match rest.next() {
+                    Some(path) => dir = PathBuf::from(path),
+                    None => {
+                        print_usage(&program);
+                        process::exit(1);
+                    }
+                }
+            }
+            "-h" | "--help" => {
+                print_usage(&program);
+                return;
+            }
+            path if input.is_none() => input = Some(path.to_string()),
+            _ => {
+                print_usage(&program);
+                process::exit(1);
+            }
+        }
+    }
+
+    let result = match input {
+        Some(path) => File::open(&path).and_then(|mut file| run(&mut file, dir)),
+        None => {
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            run(&mut stdin, dir)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("console_demux: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn encode(channel: u8, slot: u8, identifier: u32, seq: u16, name: &[u8], payload: &[u8])
+              -> Vec<u8> {
+        let mut frame = SYNC.to_vec();
+        frame.push(channel);
+        frame.push(slot);
+        frame.extend_from_slice(&[identifier as u8,
+                                  (identifier >> 8) as u8,
+                                  (identifier >> 16) as u8,
+                                  (identifier >> 24) as u8]);
+        frame.extend_from_slice(&[seq as u8, (seq >> 8) as u8]);
+        frame.push(name.len() as u8);
+        frame.push(payload.len() as u8);
+        frame.extend_from_slice(name);
+        frame.extend_from_slice(payload);
+        let checksum = frame[2..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        frame.push(checksum);
+        frame
+    }
+
+    /// A fresh output directory for the test `name`.
+    fn out_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("console_demux-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_log(dir: &Path, file_name: &str) -> Vec<u8> {
+        fs::read(dir.join(file_name)).unwrap_or_default()
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let input = encode(APP_CHANNEL, 3, 0x12345678, 0xbeef, b"blink", b"hello\n");
+        match parse(&input) {
+            Parse::Frame(frame, len) => {
+                assert_eq!(len, input.len());
+                assert_eq!(frame.channel, APP_CHANNEL);
+                assert_eq!(frame.slot, 3);
+                assert_eq!(frame.identifier, 0x12345678);
+                assert_eq!(frame.seq, 0xbeef);
+                assert_eq!(frame.name, b"blink");
+                assert_eq!(frame.payload, b"hello\n");
+            }
+            _ => panic!("frame not parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_rejects_bad_checksum() {
+        let mut input = encode(APP_CHANNEL, 0, 1, 0, b"blink", b"hello\n");
+        let last = input.len() - 1;
+        input[last] = input[last].wrapping_add(1);
+        assert!(matches!(parse(&input), Parse::Invalid));
+    }
+
+    #[test]
+    fn feed_split_frame() {
+        let dir = out_dir("split");
+        let input = encode(APP_CHANNEL, 0, 7, 0, b"blink", b"hello\n");
+        let mut demux = Demux::new(dir.clone()).unwrap();
+        demux.feed(&input[..5]).unwrap();
+        assert!(read_log(&dir, "blink.log").is_empty());
+        demux.feed(&input[5..]).unwrap();
+        assert_eq!(read_log(&dir, "blink.log"), b"hello\n");
+        assert!(read_log(&dir, "unframed.log").is_empty());
+        assert!(demux.buffer.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn feed_resyncs_after_garbage() {
+        let dir = out_dir("garbage");
+        let mut input = b"boot\xa5".to_vec();
+        input.extend(encode(KERNEL_CHANNEL, 0, 0, 0, b"", b"panic\n"));
+        input.extend(encode(APP_CHANNEL, 1, 2, 0, b"blink", b"hi\n"));
+        let mut demux = Demux::new(dir.clone()).unwrap();
+        demux.feed(&input).unwrap();
+        assert_eq!(read_log(&dir, "unframed.log"), b"boot\xa5");
+        assert_eq!(read_log(&dir, "kernel.log"), b"panic\n");
+        assert_eq!(read_log(&dir, "blink.log"), b"hi\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn feed_drops_frame_with_bad_checksum() {
+        let dir = out_dir("checksum");
+        let mut bad = encode(APP_CHANNEL, 0, 1, 0, b"blink", b"lost\n");
+        let last = bad.len() - 1;
+        bad[last] = bad[last].wrapping_add(1);
+        let mut input = bad.clone();
+        input.extend(encode(APP_CHANNEL, 0, 1, 1, b"blink", b"kept\n"));
+        let mut demux = Demux::new(dir.clone()).unwrap();
+        demux.feed(&input).unwrap();
+        assert_eq!(read_log(&dir, "blink.log"), b"kept\n");
+        assert_eq!(read_log(&dir, "unframed.log"), bad);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
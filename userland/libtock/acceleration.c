@@ -0,0 +1,49 @@
+#include "acceleration.h"
+
+struct acceleration_data {
+  int x;
+  int y;
+  int z;
+  bool fired;
+};
+
+// internal callback for faking synchronous reads
+static void acceleration_cb(int x,
+                            int y,
+                            int z, void* ud) {
+  struct acceleration_data* result = (struct acceleration_data*)ud;
+  result->x = x;
+  result->y = y;
+  result->z = z;
+  result->fired = true;
+}
+
+int acceleration_read_sync(int* x, int* y, int* z) {
+  struct acceleration_data result = { .fired = false };
+  int err;
+
+  err = acceleration_subscribe(acceleration_cb, (void*)(&result));
+  if (err < 0) {
+    return err;
+  }
+
+  err = acceleration_start_reading();
+  if (err < 0) {
+    return err;
+  }
+
+  yield_for(&result.fired);
+
+  *x = result.x;
+  *y = result.y;
+  *z = result.z;
+  return 0;
+}
+
+int acceleration_subscribe(subscribe_cb callback, void* userdata) {
+  return subscribe(DRIVER_NUM_ACCELERATION, 0, callback, userdata);
+}
+
+int acceleration_start_reading() {
+  return command(DRIVER_NUM_ACCELERATION, 1, 0);
+}
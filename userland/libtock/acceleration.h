@@ -0,0 +1,25 @@
+#ifndef _ACCELERATION_H
+#define _ACCELERATION_H
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Board-independent acceleration driver.
+// Readings are in milli-g along the x, y and z axes.
+#define DRIVER_NUM_ACCELERATION 25
+
+int acceleration_subscribe(subscribe_cb callback, void* userdata);
+int acceleration_start_reading();
+
+// Blocks until a reading is available. Returns 0 on success or a negative
+// error code.
+int acceleration_read_sync(int* x, int* y, int* z);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _ACCELERATION_H
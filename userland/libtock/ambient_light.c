@@ -0,0 +1,43 @@
+#include "ambient_light.h"
+
+struct ambient_light_data {
+  int lux;
+  bool fired;
+};
+
+// internal callback for faking synchronous reads
+static void ambient_light_cb(int lux,
+                             __attribute__ ((unused)) int unused1,
+                             __attribute__ ((unused)) int unused2, void* ud) {
+  struct ambient_light_data* result = (struct ambient_light_data*)ud;
+  result->lux = lux;
+  result->fired = true;
+}
+
+int ambient_light_read_sync(int* lux) {
+  struct ambient_light_data result = { .fired = false };
+  int err;
+
+  err = ambient_light_subscribe(ambient_light_cb, (void*)(&result));
+  if (err < 0) {
+    return err;
+  }
+
+  err = ambient_light_start_reading();
+  if (err < 0) {
+    return err;
+  }
+
+  yield_for(&result.fired);
+
+  *lux = result.lux;
+  return 0;
+}
+
+int ambient_light_subscribe(subscribe_cb callback, void* userdata) {
+  return subscribe(DRIVER_NUM_AMBIENT_LIGHT, 0, callback, userdata);
+}
+
+int ambient_light_start_reading() {
+  return command(DRIVER_NUM_AMBIENT_LIGHT, 1, 0);
+}
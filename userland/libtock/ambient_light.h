@@ -0,0 +1,25 @@
+#ifndef _AMBIENT_LIGHT_H
+#define _AMBIENT_LIGHT_H
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Board-independent ambient light driver.
+// Readings are in lux.
+#define DRIVER_NUM_AMBIENT_LIGHT 23
+
+int ambient_light_subscribe(subscribe_cb callback, void* userdata);
+int ambient_light_start_reading();
+
+// Blocks until a reading is available. Returns 0 on success or a negative
+// error code.
+int ambient_light_read_sync(int* lux);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _AMBIENT_LIGHT_H
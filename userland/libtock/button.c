@@ -16,3 +16,6 @@ int button_read(int pin_num) {
   return command(DRIVER_NUM_BUTTON, 2, pin_num);
 }
 
+int button_select_events(int event_mask) {
+  return command(DRIVER_NUM_BUTTON, 3, event_mask);
+}
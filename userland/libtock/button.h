@@ -13,6 +13,17 @@ int button_enable_interrupt(int pin_num);
 int button_disable_interrupt(int pin_num);
 int button_read(int pin_num);
 
+// Events passed as the third callback argument, and the bits selecting them
+// in button_select_events. By default apps receive presses and releases.
+typedef enum {
+  ButtonPress=0,
+  ButtonRelease,
+  ButtonLongPress,
+  ButtonDoubleClick,
+} ButtonEvent_t;
+
+int button_select_events(int event_mask);
+
 
 #ifdef __cplusplus
 }
@@ -63,3 +63,59 @@ void putnstr_async(const char *str, size_t len, subscribe_cb cb, void* userdata)
 void putstr(const char *str) {
   putnstr(str, strlen(str));
 }
+
+typedef struct getstr_data {
+  int len;
+  bool called;
+} getstr_data_t;
+
+static void getstr_cb(int len,
+                      int _y __attribute__ ((unused)),
+                      int _z __attribute__ ((unused)),
+                      void* ud) {
+  getstr_data_t* data = (getstr_data_t*)ud;
+  data->len = len;
+  data->called = true;
+}
+
+static int getnstr_start(char* buf, size_t len, int cmd, subscribe_cb cb, void* userdata) {
+  int ret = allow(0, 0, (void*)buf, len);
+  if (ret < 0) return ret;
+  ret = subscribe(0, 0, cb, userdata);
+  if (ret < 0) return ret;
+  return command(0, cmd, len);
+}
+
+int getnstr_async(char* buf, size_t len, subscribe_cb cb, void* userdata) {
+  return getnstr_start(buf, len, 1, cb, userdata);
+}
+
+int readline_async(char* buf, size_t len, subscribe_cb cb, void* userdata) {
+  return getnstr_start(buf, len, 2, cb, userdata);
+}
+
+int getnstr(char* buf, size_t len) {
+  getstr_data_t data = { 0, false };
+  int ret = getnstr_async(buf, len, getstr_cb, &data);
+  if (ret < 0) return ret;
+  yield_for(&data.called);
+  return data.len;
+}
+
+int readline(char* buf, size_t len) {
+  if (len == 0) return -1;
+  getstr_data_t data = { 0, false };
+  int ret = readline_async(buf, len - 1, getstr_cb, &data);
+  if (ret < 0) return ret;
+  yield_for(&data.called);
+  buf[data.len] = '\0';
+  return data.len;
+}
+
+int getnstr_abort(void) {
+  return command(0, 4, 0);
+}
+
+int console_set_echo(bool echo) {
+  return command(0, 3, echo);
+}
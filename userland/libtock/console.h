@@ -7,4 +7,17 @@ void putstr(const char* str);
 void putnstr(const char* str, size_t len);
 void putnstr_async(const char* str, size_t len, subscribe_cb cb, void* userdata);
 
+// Read up to `len` bytes into `buf`. Returns the number of bytes read, or a
+// negative error code.
+int getnstr(char* buf, size_t len);
+// Read a line of at most `len - 1` characters into `buf` and NUL-terminate it.
+// The line ending is not stored.
+int readline(char* buf, size_t len);
+int getnstr_async(char* buf, size_t len, subscribe_cb cb, void* userdata);
+int readline_async(char* buf, size_t len, subscribe_cb cb, void* userdata);
+// Abort the outstanding read; its callback gets the bytes read so far.
+int getnstr_abort(void);
+// Echo received characters back to the console.
+int console_set_echo(bool echo);
+
 #endif // CONSOLE_H
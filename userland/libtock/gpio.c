@@ -43,3 +43,10 @@ int gpio_interrupt_callback(subscribe_cb callback, void* callback_args) {
   return subscribe(GPIO_DRIVER_NUM, 0, callback, callback_args);
 }
 
+int gpio_claim(GPIO_Pin_t pin) {
+  return command(GPIO_DRIVER_NUM, 9, pin);
+}
+
+int gpio_release(GPIO_Pin_t pin) {
+  return command(GPIO_DRIVER_NUM, 10, pin);
+}
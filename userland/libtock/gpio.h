@@ -36,6 +36,11 @@ int gpio_disable_interrupt(GPIO_Pin_t pin);
 int gpio_disable(GPIO_Pin_t pin);
 int gpio_interrupt_callback(subscribe_cb callback, void* callback_args);
 
+// Claim a pin so no other app can use it. Using a pin claims it implicitly;
+// commands on a pin owned by another app return -2.
+int gpio_claim(GPIO_Pin_t pin);
+int gpio_release(GPIO_Pin_t pin);
+
 #ifdef __cplusplus
 }
 #endif
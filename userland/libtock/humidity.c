@@ -0,0 +1,43 @@
+#include "humidity.h"
+
+struct humidity_data {
+  unsigned humidity;
+  bool fired;
+};
+
+// internal callback for faking synchronous reads
+static void humidity_cb(int humidity,
+                        __attribute__ ((unused)) int unused1,
+                        __attribute__ ((unused)) int unused2, void* ud) {
+  struct humidity_data* result = (struct humidity_data*)ud;
+  result->humidity = humidity;
+  result->fired = true;
+}
+
+int humidity_read_sync(unsigned* humidity) {
+  struct humidity_data result = { .fired = false };
+  int err;
+
+  err = humidity_subscribe(humidity_cb, (void*)(&result));
+  if (err < 0) {
+    return err;
+  }
+
+  err = humidity_start_reading();
+  if (err < 0) {
+    return err;
+  }
+
+  yield_for(&result.fired);
+
+  *humidity = result.humidity;
+  return 0;
+}
+
+int humidity_subscribe(subscribe_cb callback, void* userdata) {
+  return subscribe(DRIVER_NUM_HUMIDITY, 0, callback, userdata);
+}
+
+int humidity_start_reading() {
+  return command(DRIVER_NUM_HUMIDITY, 1, 0);
+}
@@ -0,0 +1,25 @@
+#ifndef _HUMIDITY_H
+#define _HUMIDITY_H
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Board-independent relative humidity driver.
+// Readings are in hundredths of a percent.
+#define DRIVER_NUM_HUMIDITY 22
+
+int humidity_subscribe(subscribe_cb callback, void* userdata);
+int humidity_start_reading();
+
+// Blocks until a reading is available. Returns 0 on success or a negative
+// error code.
+int humidity_read_sync(unsigned* humidity);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _HUMIDITY_H
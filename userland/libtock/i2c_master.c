@@ -0,0 +1,71 @@
+#include "tock.h"
+#include "i2c_master.h"
+
+struct i2c_master_data {
+  bool fired;
+  int error;
+};
+
+// Internal callback for faking synchronous transactions
+static void i2c_master_cb(__attribute__ ((unused)) int command,
+                          int error,
+                          __attribute__ ((unused)) int unused,
+                          void* ud) {
+  struct i2c_master_data* data = (struct i2c_master_data*) ud;
+  data->error = error;
+  data->fired = true;
+}
+
+int i2c_master_set_callback(subscribe_cb callback, void* callback_args) {
+  return subscribe(DRIVER_NUM_I2CMASTER, 0, callback, callback_args);
+}
+
+int i2c_master_set_buffer(uint8_t* buffer, uint32_t len) {
+  return allow(DRIVER_NUM_I2CMASTER, 0, (void*) buffer, len);
+}
+
+int i2c_master_write(uint8_t address, uint8_t write_len) {
+  uint32_t a = (((uint32_t) write_len) << 8) | address;
+  return command(DRIVER_NUM_I2CMASTER, 0, a);
+}
+
+int i2c_master_read(uint8_t address, uint8_t read_len) {
+  uint32_t a = (((uint32_t) read_len) << 16) | address;
+  return command(DRIVER_NUM_I2CMASTER, 1, a);
+}
+
+int i2c_master_write_read(uint8_t address, uint8_t write_len, uint8_t read_len) {
+  uint32_t a = (((uint32_t) read_len) << 16) | (((uint32_t) write_len) << 8) | address;
+  return command(DRIVER_NUM_I2CMASTER, 2, a);
+}
+
+static int i2c_master_sync(int command_num, uint8_t address, uint8_t* buffer,
+                           uint8_t write_len, uint8_t read_len) {
+  struct i2c_master_data data = { .fired = false, .error = 0 };
+  uint8_t len = write_len > read_len ? write_len : read_len;
+
+  int err = i2c_master_set_buffer(buffer, len);
+  if (err < 0) return err;
+  err = i2c_master_set_callback(i2c_master_cb, &data);
+  if (err < 0) return err;
+
+  uint32_t a = (((uint32_t) read_len) << 16) | (((uint32_t) write_len) << 8) | address;
+  err = command(DRIVER_NUM_I2CMASTER, command_num, a);
+  if (err < 0) return err;
+
+  yield_for(&data.fired);
+  return data.error;
+}
+
+int i2c_master_write_sync(uint8_t address, uint8_t* buffer, uint8_t write_len) {
+  return i2c_master_sync(0, address, buffer, write_len, 0);
+}
+
+int i2c_master_read_sync(uint8_t address, uint8_t* buffer, uint8_t read_len) {
+  return i2c_master_sync(1, address, buffer, 0, read_len);
+}
+
+int i2c_master_write_read_sync(uint8_t address, uint8_t* buffer,
+                               uint8_t write_len, uint8_t read_len) {
+  return i2c_master_sync(2, address, buffer, write_len, read_len);
+}
@@ -0,0 +1,34 @@
+#pragma once
+
+#include "tock.h"
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DRIVER_NUM_I2CMASTER 20
+
+/* I2C master access to a bus shared with other apps and the kernel. The
+ * board decides which device addresses each app may use.
+ *
+ * The callback receives the command of the finished transaction (0 write,
+ * 1 read, 2 write-read) and 0 on success or a negative error: -1 address
+ * not acknowledged, -2 data not acknowledged, -3 arbitration lost,
+ * -4 timed out. */
+int i2c_master_set_callback(subscribe_cb callback, void* callback_args);
+int i2c_master_set_buffer(uint8_t* buffer, uint32_t len);
+
+int i2c_master_write(uint8_t address, uint8_t write_len);
+int i2c_master_read(uint8_t address, uint8_t read_len);
+/* Writes the first `write_len` bytes of the buffer, then reads into it. */
+int i2c_master_write_read(uint8_t address, uint8_t write_len, uint8_t read_len);
+
+/* Return 0 on success or a negative error. */
+int i2c_master_write_sync(uint8_t address, uint8_t* buffer, uint8_t write_len);
+int i2c_master_read_sync(uint8_t address, uint8_t* buffer, uint8_t read_len);
+int i2c_master_write_read_sync(uint8_t address, uint8_t* buffer,
+                               uint8_t write_len, uint8_t read_len);
+
+#ifdef __cplusplus
+}
+#endif
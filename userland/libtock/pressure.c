@@ -0,0 +1,43 @@
+#include "pressure.h"
+
+struct pressure_data {
+  unsigned pressure;
+  bool fired;
+};
+
+// internal callback for faking synchronous reads
+static void pressure_cb(int pressure,
+                        __attribute__ ((unused)) int unused1,
+                        __attribute__ ((unused)) int unused2, void* ud) {
+  struct pressure_data* result = (struct pressure_data*)ud;
+  result->pressure = pressure;
+  result->fired = true;
+}
+
+int pressure_read_sync(unsigned* pressure) {
+  struct pressure_data result = { .fired = false };
+  int err;
+
+  err = pressure_subscribe(pressure_cb, (void*)(&result));
+  if (err < 0) {
+    return err;
+  }
+
+  err = pressure_start_reading();
+  if (err < 0) {
+    return err;
+  }
+
+  yield_for(&result.fired);
+
+  *pressure = result.pressure;
+  return 0;
+}
+
+int pressure_subscribe(subscribe_cb callback, void* userdata) {
+  return subscribe(DRIVER_NUM_PRESSURE, 0, callback, userdata);
+}
+
+int pressure_start_reading() {
+  return command(DRIVER_NUM_PRESSURE, 1, 0);
+}
@@ -0,0 +1,25 @@
+#ifndef _PRESSURE_H
+#define _PRESSURE_H
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Board-independent barometric pressure driver.
+// Readings are in microbars.
+#define DRIVER_NUM_PRESSURE 24
+
+int pressure_subscribe(subscribe_cb callback, void* userdata);
+int pressure_start_reading();
+
+// Blocks until a reading is available. Returns 0 on success or a negative
+// error code.
+int pressure_read_sync(unsigned* pressure);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _PRESSURE_H
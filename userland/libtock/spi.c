@@ -13,7 +13,13 @@ int spi_hold_low()                        {return command(4, 10, 0);}
 int spi_release_low()                     {return command(4, 11, 0);}
 
 int spi_write_byte(unsigned char byte) {
-  return command(4, 0, byte);
+  char write = byte;
+  char read = 0;
+  int err = spi_read_write_sync(&write, &read, 1);
+  if (err < 0) {
+    return err;
+  }
+  return (unsigned char)read;
 }
 
 int spi_read_buf(const char* str, size_t len) {
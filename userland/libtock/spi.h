@@ -9,12 +9,13 @@ extern "C" {
 
 /* SPI system calls */
 int spi_init();
-/* All SPI operations depend on which peripheral is
- * active, determined by set_chip_select. Configuration
- * of a peripheral is persistent; e.g., setting the rate R
- * for peripheral 3, then switching to peripheral 2,
- * peripheral 2 will not necessarily have rate R. Then
- * back to peripheral 3, it still has rate R.*/
+/* The bus is shared with other apps and the kernel. Each
+ * app has its own chip select, rate, phase and polarity,
+ * which apply to all of its transfers; changing the chip
+ * select does not change the other settings. Transfers
+ * of different apps are queued, except that while an app
+ * holds its chip select low with spi_hold_low, other apps
+ * wait until it calls spi_release_low.*/
 int spi_set_chip_select(unsigned char cs);
 int spi_get_chip_select();
 int spi_set_rate(int rate);
@@ -0,0 +1,69 @@
+#include "spi_slave.h"
+
+struct spi_slave_data {
+  bool fired;
+  int len;
+};
+
+int spi_slave_set_write_byte(unsigned char byte) {
+  return command(DRIVER_NUM_SPI_SLAVE, 1, byte);
+}
+int spi_slave_set_phase(bool phase) {
+  return command(DRIVER_NUM_SPI_SLAVE, 2, (unsigned char)phase);
+}
+int spi_slave_get_phase() {
+  return command(DRIVER_NUM_SPI_SLAVE, 3, 0);
+}
+int spi_slave_set_polarity(bool pol) {
+  return command(DRIVER_NUM_SPI_SLAVE, 4, (unsigned char)pol);
+}
+int spi_slave_get_polarity() {
+  return command(DRIVER_NUM_SPI_SLAVE, 5, 0);
+}
+
+int spi_slave_chip_selected(subscribe_cb cb, void* ud) {
+  return subscribe(DRIVER_NUM_SPI_SLAVE, 1, cb, ud);
+}
+
+static void spi_slave_cb(int len,
+                         __attribute__ ((unused)) int unused1,
+                         __attribute__ ((unused)) int unused2,
+                         void* ud) {
+  struct spi_slave_data* data = (struct spi_slave_data*) ud;
+  data->len = len;
+  data->fired = true;
+}
+
+int spi_slave_read_write(const char* write, char* read, size_t len,
+                         subscribe_cb cb, void* ud) {
+  // An empty buffer leaves its direction out of the transfer. The kernel
+  // does not accept NULL, so the other buffer stands in for it.
+  if (read == NULL && write == NULL) {
+    return -1;
+  }
+  int err = allow(DRIVER_NUM_SPI_SLAVE, 0,
+                  read == NULL ? (void*)write : (void*)read, read == NULL ? 0 : len);
+  if (err < 0) {
+    return err;
+  }
+  err = allow(DRIVER_NUM_SPI_SLAVE, 1,
+              write == NULL ? (void*)read : (void*)write, write == NULL ? 0 : len);
+  if (err < 0) {
+    return err;
+  }
+  err = subscribe(DRIVER_NUM_SPI_SLAVE, 0, cb, ud);
+  if (err < 0) {
+    return err;
+  }
+  return command(DRIVER_NUM_SPI_SLAVE, 0, len);
+}
+
+int spi_slave_read_write_sync(const char* write, char* read, size_t len) {
+  struct spi_slave_data data = { .fired = false, .len = 0 };
+  int err = spi_slave_read_write(write, read, len, spi_slave_cb, &data);
+  if (err < 0) {
+    return err;
+  }
+  yield_for(&data.fired);
+  return data.len;
+}
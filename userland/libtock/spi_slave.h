@@ -0,0 +1,36 @@
+#ifndef _SPI_SLAVE_H
+#define _SPI_SLAVE_H
+
+#include <tock.h>
+
+#define DRIVER_NUM_SPI_SLAVE 19
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* SPI slave system calls. The master decides when bytes are exchanged,
+ * so transfers are set up in advance and complete when `len` bytes were
+ * exchanged or the master deselects this device. The callback of a
+ * transfer receives the number of bytes exchanged as its first argument. */
+int spi_slave_set_write_byte(unsigned char byte);
+int spi_slave_set_phase(bool phase);
+int spi_slave_get_phase();
+int spi_slave_set_polarity(bool pol);
+int spi_slave_get_polarity();
+
+/* Called when the master selects this device while no transfer is set up. */
+int spi_slave_chip_selected(subscribe_cb cb, void* ud);
+
+/* Either buffer may be NULL. */
+int spi_slave_read_write(const char* write, char* read, size_t len,
+                         subscribe_cb cb, void* ud);
+
+/* Returns the number of bytes exchanged. */
+int spi_slave_read_write_sync(const char* write, char* read, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _SPI_SLAVE_H
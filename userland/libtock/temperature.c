@@ -0,0 +1,43 @@
+#include "temperature.h"
+
+struct temperature_data {
+  int temperature;
+  bool fired;
+};
+
+// internal callback for faking synchronous reads
+static void temperature_cb(int temperature,
+                           __attribute__ ((unused)) int unused1,
+                           __attribute__ ((unused)) int unused2, void* ud) {
+  struct temperature_data* result = (struct temperature_data*)ud;
+  result->temperature = temperature;
+  result->fired = true;
+}
+
+int temperature_read_sync(int* temperature) {
+  struct temperature_data result = { .fired = false };
+  int err;
+
+  err = temperature_subscribe(temperature_cb, (void*)(&result));
+  if (err < 0) {
+    return err;
+  }
+
+  err = temperature_start_reading();
+  if (err < 0) {
+    return err;
+  }
+
+  yield_for(&result.fired);
+
+  *temperature = result.temperature;
+  return 0;
+}
+
+int temperature_subscribe(subscribe_cb callback, void* userdata) {
+  return subscribe(DRIVER_NUM_TEMPERATURE, 0, callback, userdata);
+}
+
+int temperature_start_reading() {
+  return command(DRIVER_NUM_TEMPERATURE, 1, 0);
+}
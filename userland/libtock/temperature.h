@@ -0,0 +1,25 @@
+#ifndef _TEMPERATURE_H
+#define _TEMPERATURE_H
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Board-independent temperature driver.
+// Readings are in hundredths of degrees Celsius.
+#define DRIVER_NUM_TEMPERATURE 21
+
+int temperature_subscribe(subscribe_cb callback, void* userdata);
+int temperature_start_reading();
+
+// Blocks until a reading is available. Returns 0 on success or a negative
+// error code.
+int temperature_read_sync(int* temperature);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // _TEMPERATURE_H
@@ -29,6 +29,7 @@ struct LoadInfo {
     bss_size: u32,
     pkg_name_offset: u32,
     pkg_name_size: u32,
+    storage_size: u32,
 }
 
 fn main() {
@@ -37,6 +38,10 @@ fn main() {
     let mut opts = Options::new();
     opts.optopt("o", "", "set output file name", "OUTFILE");
     opts.optopt("n", "", "set package name", "PKG_NAME");
+    opts.optopt("s",
+                "",
+                "set size of the app's nonvolatile storage region in bytes",
+                "STORAGE_SIZE");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -44,6 +49,10 @@ fn main() {
     };
     let output = matches.opt_str("o");
     let pkg_name = matches.opt_str("n");
+    let storage_size = match matches.opt_str("s") {
+        Some(size) => size.parse::<u32>().expect("Invalid storage size"),
+        None => 0,
+    };
     let input = if !matches.free.is_empty() {
         matches.free[0].clone()
     } else {
@@ -61,11 +70,11 @@ fn main() {
     match output {
             None => {
                 let mut out = io::stdout();
-                do_work(&file, &mut out, pkg_name)
+                do_work(&file, &mut out, pkg_name, storage_size)
             }
             Some(name) => {
                 match File::create(Path::new(&name)) {
-                    Ok(mut f) => do_work(&file, &mut f, pkg_name),
+                    Ok(mut f) => do_work(&file, &mut f, pkg_name, storage_size),
                     Err(e) => panic!("Error: {:?}", e),
                 }
             }
@@ -74,7 +83,7 @@ fn main() {
 }
 
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [-o OUTFILE] FILE", program);
+    let brief = format!("Usage: {} [-o OUTFILE] [-n PKG_NAME] [-s STORAGE_SIZE] FILE", program);
     print!("{}", opts.usage(&brief));
 }
 
@@ -110,7 +119,11 @@ unsafe fn as_byte_slice<'a, T: Copy>(input: &'a T) -> &'a [u8] {
     slice::from_raw_parts(input as *const T as *const u8, mem::size_of::<T>())
 }
 
-fn do_work(input: &elf::File, output: &mut Write, pkg_name: Option<String>) -> io::Result<()> {
+fn do_work(input: &elf::File,
+           output: &mut Write,
+           pkg_name: Option<String>,
+           storage_size: u32)
+           -> io::Result<()> {
     let pkg_name = pkg_name.unwrap_or(String::new());
     let (rel_data_size, rel_data) = match input.sections
         .iter()
@@ -162,6 +175,7 @@ fn do_work(input: &elf::File, output: &mut Write, pkg_name: Option<String>) -> i
         bss_size: bss.shdr.size as u32,
         pkg_name_offset: pkg_name_offset,
         pkg_name_size: pkg_name_size,
+        storage_size: storage_size,
     };
 
     try!(output.write_all(unsafe { as_byte_slice(&load_info) }));